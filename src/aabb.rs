@@ -2,6 +2,9 @@
 use crate::vec::*;
 use crate::ray::*;
 use crate::hit::*;
+use crate::serialize::*;
+
+use std::io::{self, Read, Write};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Aabb {
@@ -26,7 +29,7 @@ impl Aabb {
     }
 
     pub fn from_points<T: Iterator<Item = Vec3>>(points: T) -> Option<Aabb> {
-        points.map(|p| Aabb::empty(p)).reduce(|a, b| Aabb::merged(a, b))
+        points.map(Aabb::empty).reduce(Aabb::merged)
     }
 
     pub fn contains(&self, pos: Vec3) -> bool {
@@ -42,6 +45,18 @@ impl Aabb {
     pub fn center(&self) -> Vec3 {
         (self.min + self.max) * 0.5
     }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_vec3(writer, self.min)?;
+        write_vec3(writer, self.max)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Aabb> {
+        Ok(Aabb {
+            min: read_vec3(reader)?,
+            max: read_vec3(reader)?,
+        })
+    }
 }
 
 
@@ -0,0 +1,222 @@
+use crate::vec::*;
+use crate::color::*;
+use crate::image::*;
+use crate::serialize::*;
+
+use std::path::Path;
+use std::io::{self, Read, Write};
+
+use exr::prelude::{AnyChannel, AnyChannels, FlatSamples, SmallVec, Layer, LayerAttributes, Encoding, WritableImage};
+use exr::prelude::Image as ExrImage;
+
+
+// First hit data of a single sample, object and material IDs start at 1 so that 0 means no hit
+#[derive(Debug, Clone, Copy)]
+pub struct Aovs {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub depth: f32,
+    pub position: Vec3,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AovPixel {
+    albedo: Color,
+    normal: Vec3,
+    depth: f32,
+    position: Vec3,
+    object_id: u32,
+    material_id: u32,
+    direct: Color,
+    indirect: Color,
+    count: u32,
+}
+
+pub struct AovImages {
+    pub albedo: Image,
+    pub normal: Image<Vec3>,
+    pub depth: Image<f32>,
+    pub position: Image<Vec3>,
+    pub object_id: Image<u32>,
+    pub material_id: Image<u32>,
+    pub direct: Image,
+    pub indirect: Image,
+}
+
+
+impl Aovs {
+    pub fn background(radiance: Color) -> Aovs {
+        Aovs {
+            albedo: Color::from(0.0),
+            normal: Vec3::zero(),
+            depth: 0.0,
+            position: Vec3::zero(),
+            object_id: 0,
+            material_id: 0,
+            direct: radiance,
+            indirect: Color::from(0.0),
+        }
+    }
+}
+
+
+impl AovPixel {
+    pub fn new() -> AovPixel {
+        AovPixel {
+            count: 0,
+            ..AovPixel::from(Aovs::background(Color::from(0.0)))
+        }
+    }
+
+    pub fn add(&mut self, aovs: &Aovs) {
+        // IDs can't be averaged, keep the ones of the first sample
+        if self.count == 0 {
+            self.object_id = aovs.object_id;
+            self.material_id = aovs.material_id;
+        }
+
+        self.albedo += aovs.albedo;
+        self.normal = self.normal + aovs.normal;
+        self.depth += aovs.depth;
+        self.position = self.position + aovs.position;
+        self.direct += aovs.direct;
+        self.indirect += aovs.indirect;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> Aovs {
+        let norm = 1.0 / (self.count.max(1) as f32);
+        let normal = self.normal * norm;
+
+        Aovs {
+            albedo: self.albedo * norm,
+            normal: if normal.length2() > 0.0 { normal.normalized() } else { normal },
+            depth: self.depth * norm,
+            position: self.position * norm,
+            object_id: self.object_id,
+            material_id: self.material_id,
+            direct: self.direct * norm,
+            indirect: self.indirect * norm,
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for v in [self.albedo.r, self.albedo.g, self.albedo.b, self.normal.x, self.normal.y, self.normal.z, self.depth, self.position.x, self.position.y, self.position.z] {
+            write_f32(writer, v)?;
+        }
+        for v in [self.direct.r, self.direct.g, self.direct.b, self.indirect.r, self.indirect.g, self.indirect.b] {
+            write_f32(writer, v)?;
+        }
+        write_u32(writer, self.object_id)?;
+        write_u32(writer, self.material_id)?;
+        write_u32(writer, self.count)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<AovPixel> {
+        let read_vec = |reader: &mut R| -> io::Result<Vec3> {
+            Ok(Vec3::new(read_f32(reader)?, read_f32(reader)?, read_f32(reader)?))
+        };
+        let to_color = |v: Vec3| {
+            if v.x >= 0.0 && v.y >= 0.0 && v.z >= 0.0 {
+                Ok(Color::new(v.x, v.y, v.z))
+            } else {
+                Err(invalid_data("invalid AOV value"))
+            }
+        };
+
+        let albedo = to_color(read_vec(reader)?)?;
+        let normal = read_vec(reader)?;
+        let depth = read_f32(reader)?;
+        let position = read_vec(reader)?;
+        let direct = to_color(read_vec(reader)?)?;
+        let indirect = to_color(read_vec(reader)?)?;
+
+        Ok(AovPixel {
+            albedo: albedo,
+            normal: normal,
+            depth: depth,
+            position: position,
+            object_id: read_u32(reader)?,
+            material_id: read_u32(reader)?,
+            direct: direct,
+            indirect: indirect,
+            count: read_u32(reader)?,
+        })
+    }
+}
+
+impl From<Aovs> for AovPixel {
+    fn from(aovs: Aovs) -> AovPixel {
+        AovPixel {
+            albedo: aovs.albedo,
+            normal: aovs.normal,
+            depth: aovs.depth,
+            position: aovs.position,
+            object_id: aovs.object_id,
+            material_id: aovs.material_id,
+            direct: aovs.direct,
+            indirect: aovs.indirect,
+            count: 1,
+        }
+    }
+}
+
+
+impl AovImages {
+    pub fn from_pixels(width: u32, height: u32, pixels: &[AovPixel]) -> AovImages {
+        let aovs = pixels.iter().map(|p| p.mean()).collect::<Vec<_>>();
+
+        AovImages {
+            albedo: Image::new(width, height, aovs.iter().map(|a| a.albedo).collect()),
+            normal: Image::new(width, height, aovs.iter().map(|a| a.normal).collect()),
+            depth: Image::new(width, height, aovs.iter().map(|a| a.depth).collect()),
+            position: Image::new(width, height, aovs.iter().map(|a| a.position).collect()),
+            object_id: Image::new(width, height, aovs.iter().map(|a| a.object_id).collect()),
+            material_id: Image::new(width, height, aovs.iter().map(|a| a.material_id).collect()),
+            direct: Image::new(width, height, aovs.iter().map(|a| a.direct).collect()),
+            indirect: Image::new(width, height, aovs.iter().map(|a| a.indirect).collect()),
+        }
+    }
+
+    // Writes every buffer in a single EXR part, using the "layer.channel" naming compositing tools expect
+    pub fn save_exr<P: AsRef<Path>>(&self, path: P, beauty: &Image) -> exr::error::Result<()> {
+        let mut channels = Vec::new();
+
+        let mut push_color = |prefix: &str, image: &Image| {
+            channels.push(float_channel(prefix, "R", image, |c| c.r));
+            channels.push(float_channel(prefix, "G", image, |c| c.g));
+            channels.push(float_channel(prefix, "B", image, |c| c.b));
+        };
+        push_color("", beauty);
+        push_color("albedo.", &self.albedo);
+        push_color("direct.", &self.direct);
+        push_color("indirect.", &self.indirect);
+
+        for (prefix, image) in [("normal.", &self.normal), ("position.", &self.position)] {
+            channels.push(float_channel(prefix, "X", image, |v| v.x));
+            channels.push(float_channel(prefix, "Y", image, |v| v.y));
+            channels.push(float_channel(prefix, "Z", image, |v| v.z));
+        }
+
+        channels.push(float_channel("depth.", "Z", &self.depth, |d| d));
+
+        for (name, image) in [("object_id.ID", &self.object_id), ("material_id.ID", &self.material_id)] {
+            channels.push(AnyChannel::new(name, FlatSamples::U32(image.pixels().to_vec())));
+        }
+
+        let size = (beauty.width() as usize, beauty.height() as usize);
+        let layer = Layer::new(size, LayerAttributes::named("aovs"), Encoding::FAST_LOSSLESS, AnyChannels::sort(SmallVec::from_vec(channels)));
+
+        ExrImage::from_layer(layer).write().to_file(path)
+    }
+}
+
+
+fn float_channel<T: Copy, F: Fn(T) -> f32>(prefix: &str, name: &str, image: &Image<T>, component: F) -> AnyChannel<FlatSamples> {
+    let samples = image.pixels().iter().map(|p| component(*p)).collect();
+    AnyChannel::new(format!("{}{}", prefix, name).as_str(), FlatSamples::F32(samples))
+}
@@ -0,0 +1,101 @@
+use crate::vec::*;
+use crate::image::*;
+use crate::color::*;
+use crate::aov::*;
+use crate::utils::*;
+
+use rayon::prelude::*;
+
+
+const ITERATIONS: usize = 5;
+
+const LUMINANCE_SIGMA: f32 = 4.0;
+const NORMAL_POWER: f32 = 128.0;
+// Relative depth difference tolerated per pixel of distance
+const DEPTH_SIGMA: f32 = 0.02;
+const ALBEDO_SIGMA: f32 = 0.1;
+
+const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+
+// Edge-avoiding a-trous wavelet filter (Dammertz et al. 2010), with the variance guided luminance weights of SVGF (Schied et al. 2017).
+// variance is the per pixel variance of the luminance of input.
+pub fn denoise_atrous(input: &Image, variance: &Image<f32>, aovs: &AovImages) -> Image {
+    let mut color = input.clone();
+    let mut variance = variance.clone();
+
+    for i in 0..ITERATIONS {
+        let (c, v) = atrous_iteration(&color, &variance, aovs, 1 << i);
+        color = c;
+        variance = v;
+    }
+
+    color
+}
+
+fn atrous_iteration(color: &Image, variance: &Image<f32>, aovs: &AovImages, step: i32) -> (Image, Image<f32>) {
+    let width = color.width();
+
+    let (pixels, variances): (Vec<_>, Vec<_>) = (0..color.pixel_count()).into_par_iter().map(|i| {
+        let (x, y) = ((i as u32 % width) as i32, (i as u32 / width) as i32);
+
+        let center = color.pixel_at_clamped(x, y);
+        let center_lum = center.luminance();
+        let normal = aovs.normal.pixel_at_clamped(x, y);
+        let depth = aovs.depth.pixel_at_clamped(x, y);
+        let albedo = Vec3::from(aovs.albedo.pixel_at_clamped(x, y));
+
+        let lum_sigma = LUMINANCE_SIGMA * blurred_variance(variance, x, y).sqrt() + EPSILON;
+
+        let mut acc = Color::from(0.0);
+        let mut acc_variance = 0.0;
+        let mut total = 0.0;
+
+        for dy in -2..3_i32 {
+            for dx in -2..3_i32 {
+                let (qx, qy) = (x + dx * step, y + dy * step);
+                if !color.contains(qx, qy) {
+                    continue;
+                }
+
+                let q = color.pixel_at_clamped(qx, qy);
+                let q_normal = aovs.normal.pixel_at_clamped(qx, qy);
+                let q_depth = aovs.depth.pixel_at_clamped(qx, qy);
+                let q_albedo = Vec3::from(aovs.albedo.pixel_at_clamped(qx, qy));
+
+                // Identical normals also covers pixels where nothing was hit
+                let w_normal = if normal == q_normal { 1.0 } else { normal.dot(q_normal).max(0.0).powf(NORMAL_POWER) };
+
+                let dist = ((dx * dx + dy * dy) as f32).sqrt() * step as f32;
+                let w_depth = (depth - q_depth).abs() / (DEPTH_SIGMA * depth.max(q_depth) * dist + EPSILON);
+                let w_lum = (center_lum - q.luminance()).abs() / lum_sigma;
+                // Keeps texture edges when the noise is too high for the luminance to stop at them
+                let w_albedo = (albedo - q_albedo).length2() / (ALBEDO_SIGMA * ALBEDO_SIGMA);
+
+                let w = KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize] * w_normal * (-(w_depth + w_lum + w_albedo)).exp();
+
+                acc += q * w;
+                acc_variance += w * w * variance.pixel_at_clamped(qx, qy);
+                total += w;
+            }
+        }
+
+        // The center pixel always contributes, so total can't be 0
+        (acc / total, acc_variance / (total * total))
+    }).unzip();
+
+    (Image::new(color.width(), color.height(), pixels), Image::new(color.width(), color.height(), variances))
+}
+
+// 3x3 gaussian, makes the luminance edge stopping function robust to outliers in the variance estimate
+fn blurred_variance(variance: &Image<f32>, x: i32, y: i32) -> f32 {
+    const GAUSSIAN: [f32; 2] = [1.0 / 2.0, 1.0 / 4.0];
+
+    let mut acc = 0.0;
+    for dy in -1..2_i32 {
+        for dx in -1..2_i32 {
+            acc += GAUSSIAN[dx.unsigned_abs() as usize] * GAUSSIAN[dy.unsigned_abs() as usize] * variance.pixel_at_clamped(x + dx, y + dy);
+        }
+    }
+    acc
+}
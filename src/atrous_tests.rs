@@ -0,0 +1,108 @@
+// Checks the edge avoiding a-trous filter of the preview: it keeps constant images, stops at normal, depth and albedo edges, and reduces noise.
+
+use crate::vec::*;
+use crate::color::*;
+use crate::image::*;
+use crate::aov::*;
+use crate::atrous::*;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+
+const WIDTH: u32 = 32;
+const HEIGHT: u32 = 24;
+
+// The depth tolerance grows with the distance between pixels, so the widest steps still leak a little
+const MAX_EDGE_CHANGE: f32 = 0.01;
+
+// Noisy enough that the luminance alone doesn't stop the filter at the edges
+const NOISE_VARIANCE: f32 = 1.0;
+
+
+fn aov(normal: Vec3, depth: f32, albedo: Color) -> Aovs {
+    Aovs {
+        albedo: albedo,
+        normal: normal,
+        depth: depth,
+        position: Vec3::zero(),
+        object_id: 1,
+        material_id: 1,
+        direct: Color::from(0.0),
+        indirect: Color::from(0.0),
+    }
+}
+
+fn aov_images<F: Fn(u32) -> Aovs>(aov_at: F) -> AovImages {
+    let pixels = (0..WIDTH * HEIGHT).map(|i| AovPixel::from(aov_at(i % WIDTH))).collect::<Vec<_>>();
+    AovImages::from_pixels(WIDTH, HEIGHT, &pixels)
+}
+
+fn image<T: Copy, F: Fn(u32) -> T>(value_at: F) -> Image<T> {
+    Image::new(WIDTH, HEIGHT, (0..WIDTH * HEIGHT).map(|i| value_at(i % WIDTH)).collect())
+}
+
+fn flat_aovs() -> AovImages {
+    aov_images(|_| aov(Vec3::new(0.0, 0.0, 1.0), 2.0, Color::from(0.5)))
+}
+
+// Left and right halves of 0.2 and 0.8, split by the given AOVs
+fn filter_edge(aovs: &AovImages) -> Image {
+    let input = image(|x| Color::from(side(x, 0.2, 0.8)));
+    denoise_atrous(&input, &image(|_| NOISE_VARIANCE), aovs)
+}
+
+fn side<T>(x: u32, left: T, right: T) -> T {
+    if x < WIDTH / 2 { left } else { right }
+}
+
+// Largest change of the pixels on both sides of the edge
+fn edge_change(output: &Image) -> f32 {
+    (0..HEIGHT).flat_map(|y| [(WIDTH / 2 - 1, y, 0.2), (WIDTH / 2, y, 0.8)]).map(|(x, y, expected)| {
+        (output.pixel_at(x, y).r - expected).abs()
+    }).fold(0.0, f32::max)
+}
+
+
+#[test]
+fn constant_image() {
+    let input = image(|_| Color::new(0.3, 0.6, 0.9));
+    let output = denoise_atrous(&input, &image(|_| NOISE_VARIANCE), &flat_aovs());
+
+    for (p, q) in output.pixels().iter().zip(input.pixels()) {
+        assert!((p.r - q.r).abs() < 1e-5 && (p.g - q.g).abs() < 1e-5 && (p.b - q.b).abs() < 1e-5, "{:?} instead of {:?}", p, q);
+    }
+}
+
+#[test]
+fn stops_at_edges() {
+    let (normal, depth, albedo) = (Vec3::new(0.0, 0.0, 1.0), 2.0, Color::from(0.5));
+
+    // Without any AOV edge, the filter blurs the two halves together
+    assert!(edge_change(&filter_edge(&flat_aovs())) > 0.1);
+
+    let normals = aov_images(|x| aov(side(x, normal, Vec3::new(1.0, 0.0, 0.0)), depth, albedo));
+    assert!(edge_change(&filter_edge(&normals)) < MAX_EDGE_CHANGE, "blurred across normals");
+
+    let depths = aov_images(|x| aov(normal, side(x, depth, depth * 2.0), albedo));
+    assert!(edge_change(&filter_edge(&depths)) < MAX_EDGE_CHANGE, "blurred across depths");
+
+    let albedos = aov_images(|x| aov(normal, depth, side(x, Color::from(0.2), Color::from(0.8))));
+    assert!(edge_change(&filter_edge(&albedos)) < MAX_EDGE_CHANGE, "blurred across albedos");
+}
+
+#[test]
+fn reduces_variance() {
+    let mut rng = StdRng::seed_from_u64(3);
+    // Uniform noise around 0.5, of variance 1 / 12
+    let noisy = Image::new(WIDTH, HEIGHT, (0..WIDTH * HEIGHT).map(|_| Color::from(rng.gen::<f32>())).collect());
+    let output = denoise_atrous(&noisy, &image(|_| 1.0 / 12.0), &flat_aovs());
+
+    let variance = |image: &Image| {
+        let mean = image.pixels().iter().map(|c| c.r).sum::<f32>() / image.pixel_count() as f32;
+        image.pixels().iter().map(|c| (c.r - mean) * (c.r - mean)).sum::<f32>() / image.pixel_count() as f32
+    };
+
+    let (before, after) = (variance(&noisy), variance(&output));
+    assert!(after < before * 0.1, "variance went from {} to {}", before, after);
+}
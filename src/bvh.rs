@@ -3,94 +3,139 @@ use crate::aabb::*;
 use crate::ray::*;
 use crate::vec::*;
 use crate::hit::*;
+use crate::serialize::*;
 
-pub struct Bvh<T> {
-    root: BvhNode<T>,
-}
+use std::io::{self, Read, Write};
 
-struct BvhNode<T> {
-    aabb: Aabb,
-    content: BvhContent<T>,
+
+// Marks inner nodes, where leaves have their object count instead
+const INNER_NODE: u32 = u32::MAX;
+// Trees are balanced, so anything deeper comes from a corrupted file
+const MAX_DEPTH: usize = 64;
+// Bytes of a node in files: its AABB, then first and count
+const NODE_SIZE: usize = 32;
+
+// Nodes are stored depth first, so the first child of an inner node follows it.
+// Leaves are ranges of the object array the BVH is built on, which building sorts into leaf order
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
 }
 
-enum BvhContent<T> {
-    Leaf(Vec<T>),
-    Node(Box<(BvhNode<T>, BvhNode<T>)>),
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    aabb: Aabb,
+    // Index of the second child for inner nodes, of the first object for leaves
+    first: u32,
+    // Number of objects of leaves, or INNER_NODE
+    count: u32,
 }
 
 
-impl<T: Clone> Bvh<T> {
-    pub fn new<F: Fn(&T) -> Aabb>(objects: &mut [T], to_aabb: F, max_object_per_node: usize) -> Bvh<T> {
+impl Bvh {
+    pub fn new<T, F: Fn(&T) -> Aabb>(objects: &mut [T], to_aabb: F, max_object_per_node: usize) -> Bvh {
         debug_assert!(!objects.is_empty());
+        let mut nodes = Vec::new();
+        Self::build(&mut nodes, objects, 0, &to_aabb, max_object_per_node, 0);
         Bvh {
-            root: BvhNode::build(objects, &to_aabb, max_object_per_node, 0)
+            nodes: nodes,
         }
     }
 
-    pub fn empty() -> Bvh<T> {
+    pub fn empty() -> Bvh {
         Bvh {
-            root: BvhNode {
+            nodes: vec![BvhNode {
                 aabb: Aabb::empty(Vec3::zero()),
-                content: BvhContent::Leaf(Vec::new())
-            },
+                first: 0,
+                count: 0,
+            }],
         }
     }
 
     pub fn is_empty(&self) -> bool {
-        match &self.root.content {
-            BvhContent::Leaf(objects) => objects.is_empty(),
-            _ => false,
-        }
+        self.nodes[0].count == 0
     }
 
     pub fn aabb(&self) -> Aabb {
-        self.root.aabb
+        self.nodes[0].aabb
+    }
+
+    // The node array is written at once, so that it can be read in bulk
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut nodes = Vec::with_capacity(self.nodes.len() * NODE_SIZE);
+        for node in &self.nodes {
+            node.aabb.write_to(&mut nodes)?;
+            write_u32(&mut nodes, node.first)?;
+            write_u32(&mut nodes, node.count)?;
+        }
+
+        write_u64(writer, self.nodes.len() as u64)?;
+        writer.write_all(&nodes)
+    }
+
+    // Leaves must be within the object_count objects the BVH was built on
+    pub fn read_from<R: Read>(reader: &mut R, object_count: usize) -> io::Result<Bvh> {
+        let node_count = read_u64(reader)? as usize;
+        let nodes = read_bytes(reader, node_count.checked_mul(NODE_SIZE).ok_or_else(|| invalid_data("too many BVH nodes"))?)?;
+        let nodes = nodes.chunks_exact(NODE_SIZE).map(|mut node| Ok(BvhNode {
+            aabb: Aabb::read_from(&mut node)?,
+            first: read_u32(&mut node)?,
+            count: read_u32(&mut node)?,
+        })).collect::<io::Result<Vec<_>>>()?;
+
+        if nodes.is_empty() || Self::check_node(&nodes, object_count, 0, 0)? != nodes.len() {
+            return Err(invalid_data("invalid BVH layout"));
+        }
+
+        Ok(Bvh {
+            nodes: nodes,
+        })
     }
 
-    pub fn trace<'hit, F: Fn(Ray, &[T]) -> Option<HitRecord<'hit>>>(&self, ray: Ray, hit_func: F) -> Option<HitRecord<'hit>> {
-        Self::trace_node(&self.root, ray, &hit_func)
+    // Objects must be the array the BVH was built on, as building left it
+    pub fn trace<'hit, T, F: Fn(Ray, &[T]) -> Option<HitRecord<'hit>>>(&self, ray: Ray, objects: &[T], hit_func: F) -> Option<HitRecord<'hit>> {
+        self.trace_node(0, ray, objects, &hit_func)
     }
 
 
 
-    fn trace_node<'hit, F: Fn(Ray, &[T]) -> Option<HitRecord<'hit>>>(node: &BvhNode<T>, mut ray: Ray, hit_func: &F) -> Option<HitRecord<'hit>> {
-        if node.aabb.hit(ray).is_none() {
-            return None;
+    fn trace_node<'hit, T, F: Fn(Ray, &[T]) -> Option<HitRecord<'hit>>>(&self, index: usize, mut ray: Ray, objects: &[T], hit_func: &F) -> Option<HitRecord<'hit>> {
+        let node = &self.nodes[index];
+        node.aabb.hit(ray)?;
+
+        if node.count != INNER_NODE {
+            let first = node.first as usize;
+            return hit_func(ray, &objects[first..first + node.count as usize]);
         }
-        match &node.content {
-            BvhContent::Leaf(objects) => hit_func(ray, objects),
-            BvhContent::Node(children) => {
-                let dist_sq = |c: &BvhNode<T>| c.aabb.center().distance2(ray.orig);
-
-                let children = if dist_sq(&children.0) < dist_sq(&children.1) {
-                    [&children.0, &children.1]
-                } else {
-                    [&children.1, &children.0]
-                };
-
-                let mut hit_rec: Option<HitRecord<'hit>> = None;
-                for child in children.iter() {
-                    if let Some(hit) = Bvh::trace_node(child, ray, hit_func) {
-                        ray = ray.with_max(hit.dist);
-                        hit_rec = Some(hit);
-                    }
-                }
-                hit_rec
-            },
+
+        let dist_sq = |i: usize| self.nodes[i].aabb.center().distance2(ray.orig);
+        let children = if dist_sq(index + 1) < dist_sq(node.first as usize) {
+            [index + 1, node.first as usize]
+        } else {
+            [node.first as usize, index + 1]
+        };
+
+        let mut hit_rec: Option<HitRecord<'hit>> = None;
+        for child in children {
+            if let Some(hit) = self.trace_node(child, ray, objects, hit_func) {
+                ray = ray.with_max(hit.dist);
+                hit_rec = Some(hit);
+            }
         }
+        hit_rec
     }
-}
-
 
-impl<T: Clone> BvhNode<T> {
-    fn build<F: Fn(&T) -> Aabb>(objects: &mut [T], to_aabb: &F, max_object_per_node: usize, axis: usize) -> BvhNode<T> {
+    // Appends the nodes of objects, which start at offset in the whole object array, and returns their AABB
+    fn build<T, F: Fn(&T) -> Aabb>(nodes: &mut Vec<BvhNode>, objects: &mut [T], offset: usize, to_aabb: &F, max_object_per_node: usize, axis: usize) -> Aabb {
         debug_assert!(!objects.is_empty());
 
         if objects.len() <= max_object_per_node {
-            return BvhNode {
-                aabb: objects.iter().map(|o| to_aabb(o)).reduce(|acc, e| acc.merged(e)).unwrap(),
-                content: BvhContent::Leaf(Vec::from(objects)),
-            };
+            let aabb = objects.iter().map(to_aabb).reduce(|acc, e| acc.merged(e)).unwrap();
+            nodes.push(BvhNode {
+                aabb: aabb,
+                first: offset as u32,
+                count: objects.len() as u32,
+            });
+            return aabb;
         }
 
         let on_axis = |obj: &T| {
@@ -98,19 +143,43 @@ impl<T: Clone> BvhNode<T> {
         };
         objects.sort_by(|a, b| on_axis(a).partial_cmp(&on_axis(b)).unwrap());
 
+        let index = nodes.len();
+        nodes.push(BvhNode {
+            aabb: Aabb::empty(Vec3::zero()),
+            first: 0,
+            count: INNER_NODE,
+        });
+
         let (a, b) = objects.split_at_mut(objects.len() / 2);
         debug_assert!(!a.is_empty());
         debug_assert!(!b.is_empty());
 
         let next_axis = (axis + 1) % 3;
-        let children = (
-            Self::build(a, to_aabb, max_object_per_node, next_axis),
-            Self::build(b, to_aabb, max_object_per_node, next_axis),
-        );
-
-        BvhNode {
-            aabb: children.0.aabb.merged(children.1.aabb),
-            content: BvhContent::Node(Box::new(children)),
+        let a_aabb = Self::build(nodes, a, offset, to_aabb, max_object_per_node, next_axis);
+        nodes[index].first = nodes.len() as u32;
+        let b_aabb = Self::build(nodes, b, offset + a.len(), to_aabb, max_object_per_node, next_axis);
+
+        nodes[index].aabb = a_aabb.merged(b_aabb);
+        nodes[index].aabb
+    }
+
+    // Checks that the subtree at index is laid out depth first, and returns the index following it
+    fn check_node(nodes: &[BvhNode], object_count: usize, index: usize, depth: usize) -> io::Result<usize> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("BVH is too deep"));
+        }
+
+        let node = nodes.get(index).ok_or_else(|| invalid_data("BVH node index out of range"))?;
+        if node.count == INNER_NODE {
+            let second = Self::check_node(nodes, object_count, index + 1, depth + 1)?;
+            if node.first as usize != second {
+                return Err(invalid_data("invalid BVH layout"));
+            }
+            Self::check_node(nodes, object_count, second, depth + 1)
+        } else if node.first as usize + node.count as usize > object_count {
+            Err(invalid_data("BVH leaf out of range"))
+        } else {
+            Ok(index + 1)
         }
     }
-}
\ No newline at end of file
+}
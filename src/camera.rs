@@ -2,6 +2,9 @@
 use crate::transform::*;
 use crate::vec::*;
 use crate::ray::*;
+use crate::serialize::*;
+
+use std::io::{self, Read, Write};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
@@ -10,6 +13,12 @@ pub struct Camera {
     ratio: f32,
 }
 
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new(Transform::identity(), 60.0_f32.to_radians(), 1.0)
+    }
+}
+
 impl Camera {
     pub fn new(tr: Transform, vfov: f32, ratio: f32) -> Camera {
         Camera {
@@ -19,6 +28,20 @@ impl Camera {
         }
     }
 
+    pub fn transformed(&self, tr: &Transform) -> Camera {
+        Camera {
+            transform: tr.then(self.transform),
+            ..*self
+        }
+    }
+
+    pub fn with_ratio(&self, ratio: f32) -> Camera {
+        Camera {
+            ratio: ratio,
+            ..*self
+        }
+    }
+
     pub fn generate_ray(&self, u: f32, v: f32) -> Ray {
         let x = (u * 2.0 - 1.0) * self.tan_half_vfov * self.ratio;
         let y = (v * 2.0 - 1.0) * self.tan_half_vfov;
@@ -27,6 +50,20 @@ impl Camera {
         Ray::new(self.position(), dir)
     }
 
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.transform.write_to(writer)?;
+        write_f32(writer, self.tan_half_vfov)?;
+        write_f32(writer, self.ratio)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Camera> {
+        Ok(Camera {
+            transform: Transform::read_from(reader)?,
+            tan_half_vfov: read_f32(reader)?,
+            ratio: read_f32(reader)?,
+        })
+    }
+
     pub fn ratio(&self) -> f32 {
         self.ratio
     }
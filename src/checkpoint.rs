@@ -0,0 +1,147 @@
+use crate::film::*;
+use crate::scene_cache::*;
+use crate::serialize::*;
+
+use std::path::Path;
+use std::time::Duration;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::fs::{self, File};
+
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 4;
+
+
+pub struct Checkpoint {
+    pub film: Film,
+    pub passes: usize,
+    pub elapsed: Duration,
+    pub source: RenderSource,
+}
+
+// What a checkpoint was rendered from. Resuming it with anything else would mix samples of different images
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSource {
+    pub scene: SceneSource,
+    pub spp: usize,
+    pub max_rays: usize,
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    pub time_budget: Option<Duration>,
+}
+
+
+impl Checkpoint {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+
+        // Write next to the previous checkpoint and swap, so being killed mid-write never loses it
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(MAGIC)?;
+            write_u32(&mut writer, VERSION)?;
+            write_u64(&mut writer, self.passes as u64)?;
+            write_f64(&mut writer, self.elapsed.as_secs_f64())?;
+            self.source.write_to(&mut writer)?;
+            self.film.write_to(&mut writer)?;
+            writer.flush()?;
+        }
+
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a checkpoint file"));
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!("unsupported checkpoint version {}", version)));
+        }
+
+        let passes = read_u64(&mut reader)? as usize;
+        let elapsed = Duration::from_secs_f64(read_f64(&mut reader)?.max(0.0));
+        let source = RenderSource::read_from(&mut reader)?;
+        let film = Film::read_from(&mut reader)?;
+
+        Ok(Checkpoint {
+            film: film,
+            passes: passes,
+            elapsed: elapsed,
+            source: source,
+        })
+    }
+}
+
+impl RenderSource {
+    // What differs from the render that would resume the checkpoint, if anything
+    pub fn mismatch(&self, render: &RenderSource) -> Option<&'static str> {
+        if self.scene != render.scene {
+            Some("from another scene, or the scene changed since")
+        } else if (self.spp, self.max_rays) != (render.spp, render.max_rays) {
+            Some("with another spp or number of bounces")
+        } else if self.adaptive_sampling != render.adaptive_sampling || self.time_budget != render.time_budget {
+            Some("with other adaptive sampling or time budget settings")
+        } else {
+            None
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.scene.write_to(writer)?;
+        write_u64(writer, self.spp as u64)?;
+        write_u64(writer, self.max_rays as u64)?;
+
+        write_u32(writer, self.adaptive_sampling.is_some() as u32)?;
+        if let Some(adaptive) = self.adaptive_sampling {
+            write_u64(writer, adaptive.min_spp as u64)?;
+            write_u64(writer, adaptive.max_spp as u64)?;
+            write_u64(writer, adaptive.pass_spp as u64)?;
+            write_f32(writer, adaptive.threshold)?;
+        }
+
+        // In nanoseconds, so that it reads back exactly
+        write_u32(writer, self.time_budget.is_some() as u32)?;
+        if let Some(budget) = self.time_budget {
+            write_u64(writer, budget.as_nanos() as u64)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<RenderSource> {
+        let scene = SceneSource::read_from(reader)?;
+        let spp = read_u64(reader)? as usize;
+        let max_rays = read_u64(reader)? as usize;
+
+        let adaptive_sampling = if read_u32(reader)? != 0 {
+            Some(AdaptiveSampling {
+                min_spp: read_u64(reader)? as usize,
+                max_spp: read_u64(reader)? as usize,
+                pass_spp: read_u64(reader)? as usize,
+                threshold: read_f32(reader)?,
+            })
+        } else {
+            None
+        };
+
+        let time_budget = if read_u32(reader)? != 0 {
+            Some(Duration::from_nanos(read_u64(reader)?))
+        } else {
+            None
+        };
+
+        Ok(RenderSource {
+            scene: scene,
+            spp: spp,
+            max_rays: max_rays,
+            adaptive_sampling: adaptive_sampling,
+            time_budget: time_budget,
+        })
+    }
+}
@@ -0,0 +1,154 @@
+// Writes films and checkpoints and reads them back, including corrupted ones that must fail without allocating their claimed size,
+// and checks which renders may resume a checkpoint and which ones write one.
+
+use crate::color::*;
+use crate::tile::*;
+use crate::film::*;
+use crate::checkpoint::*;
+use crate::normals::*;
+use crate::scene_cache::*;
+use crate::serialize::*;
+use crate::{parse_args, CHECKPOINT_FILE};
+
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+
+const WIDTH: u32 = 6;
+const HEIGHT: u32 = 4;
+
+
+fn test_film() -> Film {
+    let mut film = Film::new(WIDTH, HEIGHT);
+    let tile = Tile { x: 0, y: 0, width: WIDTH, height: HEIGHT };
+    let mut data = film.tile(&tile);
+    for (i, p) in data.pixels.iter_mut().enumerate() {
+        for s in 0..=(i % 3) {
+            p.add(Color::new(i as f32, s as f32, 0.5));
+        }
+    }
+    film.set_tile(&tile, data);
+    film
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rt-checkpoint-tests-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn render_source(scene_file: &Path) -> RenderSource {
+    RenderSource {
+        scene: SceneSource::new(&[SourceFile::new(scene_file)], NormalGeneration::Flat),
+        spp: 16,
+        max_rays: 5,
+        adaptive_sampling: None,
+        time_budget: Some(Duration::from_millis(1500)),
+    }
+}
+
+fn to_bytes(film: &Film) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    film.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+
+#[test]
+fn film_round_trip() {
+    let film = test_film();
+    let read = Film::read_from(&mut to_bytes(&film).as_slice()).unwrap();
+    assert_eq!((read.width(), read.height()), (WIDTH, HEIGHT));
+    assert!(!read.has_aovs());
+    assert_eq!(to_bytes(&read), to_bytes(&film));
+}
+
+#[test]
+fn corrupted_film() {
+    let read = |bytes: &[u8]| match Film::read_from(&mut &bytes[..]) {
+        Err(err) => err.kind(),
+        Ok(_) => panic!("corrupted film should be rejected"),
+    };
+
+    // A size far beyond the data fails instead of trying to allocate it
+    let mut huge = Vec::new();
+    write_u32(&mut huge, 60000).unwrap();
+    write_u32(&mut huge, 60000).unwrap();
+    assert_eq!(read(&huge), io::ErrorKind::UnexpectedEof);
+
+    let mut oversized = Vec::new();
+    write_u32(&mut oversized, u32::MAX).unwrap();
+    write_u32(&mut oversized, u32::MAX).unwrap();
+    assert_eq!(read(&oversized), io::ErrorKind::InvalidData);
+
+    let bytes = to_bytes(&test_film());
+    assert_eq!(read(&bytes[..bytes.len() - 8]), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn checkpoint_round_trip() {
+    let scene_file = temp_path("round_trip.gltf");
+    fs::write(&scene_file, b"scene").unwrap();
+
+    let checkpoint = Checkpoint {
+        film: test_film(),
+        passes: 3,
+        elapsed: Duration::from_secs(2),
+        source: RenderSource {
+            adaptive_sampling: Some(AdaptiveSampling { min_spp: 4, max_spp: 64, pass_spp: 4, threshold: 0.05 }),
+            ..render_source(&scene_file)
+        },
+    };
+    let path = temp_path("round_trip.checkpoint");
+    checkpoint.save(&path).unwrap();
+
+    let loaded = Checkpoint::load(&path).unwrap();
+    assert_eq!(loaded.passes, 3);
+    assert_eq!(loaded.elapsed, Duration::from_secs(2));
+    assert!(loaded.source == checkpoint.source);
+    assert_eq!(loaded.source.mismatch(&checkpoint.source), None);
+    assert_eq!(to_bytes(&loaded.film), to_bytes(&checkpoint.film));
+}
+
+// Resuming a checkpoint of another render would silently merge unrelated samples
+#[test]
+fn checkpoint_source_mismatch() {
+    let scene_file = temp_path("mismatch.gltf");
+    fs::write(&scene_file, b"scene").unwrap();
+    let other_file = temp_path("other.gltf");
+    fs::write(&other_file, b"other").unwrap();
+
+    let source = render_source(&scene_file);
+    let mismatch = |render: RenderSource| source.mismatch(&render).expect("render should be rejected");
+
+    assert!(mismatch(render_source(&other_file)).contains("another scene"));
+    assert!(mismatch(RenderSource { spp: 32, ..render_source(&scene_file) }).contains("spp"));
+    assert!(mismatch(RenderSource { max_rays: 2, ..render_source(&scene_file) }).contains("bounces"));
+    assert!(mismatch(RenderSource { time_budget: None, ..render_source(&scene_file) }).contains("time budget"));
+    assert!(mismatch(RenderSource {
+        scene: SceneSource::new(&[SourceFile::new(&scene_file)], NormalGeneration::Smooth),
+        ..render_source(&scene_file)
+    }).contains("another scene"));
+
+    fs::write(&scene_file, b"edited scene").unwrap();
+    assert!(mismatch(render_source(&scene_file)).contains("the scene changed"));
+}
+
+// Checkpoints are only written when asked for, resuming without a file uses the default one, and renders can stop after a number of passes
+#[test]
+fn checkpoint_arguments() {
+    let checkpoint_file = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string())).unwrap().checkpoint_file;
+
+    assert_eq!(checkpoint_file(&[]), None);
+    assert_eq!(checkpoint_file(&["--denoise"]), None);
+    assert_eq!(checkpoint_file(&["--resume"]), Some(PathBuf::from(CHECKPOINT_FILE)));
+    assert_eq!(checkpoint_file(&["--checkpoint", "long.checkpoint"]), Some(PathBuf::from("long.checkpoint")));
+    assert_eq!(checkpoint_file(&["--checkpoint", "long.checkpoint", "--resume"]), Some(PathBuf::from("long.checkpoint")));
+
+    let max_passes = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string())).map(|args| args.max_passes);
+    assert_eq!(max_passes(&[]), Ok(None));
+    assert_eq!(max_passes(&["--max-passes", "3", "--checkpoint", "long.checkpoint"]), Ok(Some(3)));
+    assert!(max_passes(&["--max-passes", "0"]).is_err());
+}
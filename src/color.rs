@@ -1,6 +1,6 @@
 use std::ops::*;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -29,7 +29,38 @@ impl Color {
         self.r <= 0.0 && self.g <= 0.0 && self.b <= 0.0
     }
 
-    pub fn to_srgb(&self) -> SRgbColor {
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    // Linear sRGB color of a black body with a luminance of 1, from the chromaticity of the Planckian locus
+    // (Kang et al. 2002, valid from 1667K to 25000K)
+    pub fn blackbody(kelvin: f32) -> Color {
+        let t = kelvin.clamp(1667.0, 25000.0) as f64;
+        let (t2, t3) = (t * t, t * t * t);
+        let x = if t <= 4000.0 {
+            -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+        } else {
+            -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+        };
+        let (x2, x3) = (x * x, x * x * x);
+        let y = if t <= 2222.0 {
+            -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+        } else if t <= 4000.0 {
+            -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+        } else {
+            3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+        };
+
+        let (cx, cz) = ((x / y) as f32, ((1.0 - x - y) / y) as f32);
+        Color::new(
+            (3.2406 * cx - 1.5372 - 0.4986 * cz).max(0.0),
+            (-0.9689 * cx + 1.8758 + 0.0415 * cz).max(0.0),
+            (0.0557 * cx - 0.2040 + 1.0570 * cz).max(0.0),
+        )
+    }
+
+    pub fn to_srgb(self) -> SRgbColor {
         SRgbColor {
             r: to_srgb(self.r), 
             g: to_srgb(self.g), 
@@ -38,11 +69,21 @@ impl Color {
     }
 }
 
+impl SRgbColor {
+    pub fn to_linear(self) -> Color {
+        Color::new(to_linear(self.r), to_linear(self.g), to_linear(self.b))
+    }
+}
+
 fn to_srgb(x: f32) -> u8 {
     let gamma = x.max(0.0).powf(1.0 / 2.2);
     (gamma * 255.0).min(255.0) as u8
 }
 
+fn to_linear(x: u8) -> f32 {
+    (x as f32 / 255.0).powf(2.2)
+}
+
 
 impl From<f32> for Color {
     fn from(x: f32) -> Color {
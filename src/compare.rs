@@ -0,0 +1,182 @@
+use crate::color::*;
+use crate::image::*;
+use crate::flip::*;
+
+use std::fmt;
+
+
+// Keeps the relative error of black reference pixels from blowing up
+const REL_MSE_EPSILON: f32 = 0.01;
+
+const SSIM_SIGMA: f32 = 1.5;
+const SSIM_RADIUS: i32 = 5;
+const SSIM_C1: f32 = 0.01 * 0.01;
+const SSIM_C2: f32 = 0.03 * 0.03;
+
+// Approximation of the magma colormap FLIP uses for its error maps, sRGB encoded
+const ERROR_COLORMAP: [[f32; 3]; 9] = [
+    [0.001, 0.000, 0.014],
+    [0.079, 0.054, 0.212],
+    [0.232, 0.060, 0.438],
+    [0.390, 0.100, 0.502],
+    [0.550, 0.161, 0.506],
+    [0.716, 0.215, 0.475],
+    [0.869, 0.288, 0.409],
+    [0.967, 0.440, 0.360],
+    [0.987, 0.991, 0.750],
+];
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Mse,
+    RelMse,
+    Psnr,
+    Ssim,
+    Flip,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metrics {
+    pub mse: f32,
+    pub rel_mse: f32,
+    pub psnr: f32,
+    pub ssim: f32,
+    pub flip: f32,
+}
+
+
+impl Metric {
+    pub fn parse(name: &str) -> Option<Metric> {
+        match name.to_ascii_lowercase().as_str() {
+            "mse" => Some(Metric::Mse),
+            "relmse" => Some(Metric::RelMse),
+            "psnr" => Some(Metric::Psnr),
+            "ssim" => Some(Metric::Ssim),
+            "flip" => Some(Metric::Flip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Mse => "MSE",
+            Metric::RelMse => "relMSE",
+            Metric::Psnr => "PSNR",
+            Metric::Ssim => "SSIM",
+            Metric::Flip => "FLIP",
+        }
+    }
+
+    // PSNR and SSIM grow as the images get closer, the others are errors
+    pub fn higher_is_better(&self) -> bool {
+        matches!(self, Metric::Psnr | Metric::Ssim)
+    }
+}
+
+impl Metrics {
+    pub fn get(&self, metric: Metric) -> f32 {
+        match metric {
+            Metric::Mse => self.mse,
+            Metric::RelMse => self.rel_mse,
+            Metric::Psnr => self.psnr,
+            Metric::Ssim => self.ssim,
+            Metric::Flip => self.flip,
+        }
+    }
+
+    pub fn exceeds(&self, metric: Metric, threshold: f32) -> bool {
+        // A NaN metric always fails
+        let value = self.get(metric);
+        if metric.higher_is_better() {
+            value.is_nan() || value < threshold
+        } else {
+            value.is_nan() || value > threshold
+        }
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:8}{:.6}", Metric::Mse.name(), self.mse)?;
+        writeln!(f, "{:8}{:.6}", Metric::RelMse.name(), self.rel_mse)?;
+        writeln!(f, "{:8}{:.2} dB", Metric::Psnr.name(), self.psnr)?;
+        writeln!(f, "{:8}{:.4}", Metric::Ssim.name(), self.ssim)?;
+        write!(f, "{:8}{:.4}", Metric::Flip.name(), self.flip)
+    }
+}
+
+
+// MSE and relMSE are computed on the linear values, the other metrics see the images as a display would, clamped to [0, 1].
+// Also returns the per pixel FLIP error.
+pub fn compare(test: &Image, reference: &Image) -> (Metrics, Image<f32>) {
+    assert!(test.width() == reference.width() && test.height() == reference.height());
+
+    let channels = |c: Color| [c.r, c.g, c.b];
+    let values = (test.pixel_count() * 3) as f32;
+
+    let mut mse = 0.0;
+    let mut rel_mse = 0.0;
+    let mut clamped_mse = 0.0;
+    for (t, r) in test.pixels().iter().zip(reference.pixels()) {
+        for (t, r) in channels(*t).into_iter().zip(channels(*r)) {
+            let diff = t - r;
+            mse += diff * diff;
+            rel_mse += diff * diff / (r * r + REL_MSE_EPSILON);
+
+            let diff = t.min(1.0) - r.min(1.0);
+            clamped_mse += diff * diff;
+        }
+    }
+
+    let errors = flip(test, reference, PIXELS_PER_DEGREE);
+
+    let metrics = Metrics {
+        mse: mse / values,
+        rel_mse: rel_mse / values,
+        psnr: -10.0 * (clamped_mse / values).log10(),
+        ssim: ssim(test, reference),
+        flip: errors.pixels().iter().sum::<f32>() / errors.pixel_count() as f32,
+    };
+
+    (metrics, errors)
+}
+
+// Mean structural similarity of the clamped luminance, over Gaussian windows
+fn ssim(test: &Image, reference: &Image) -> f32 {
+    let kernel = (-SSIM_RADIUS..(SSIM_RADIUS + 1)).map(|x| (-((x * x) as f32) / (2.0 * SSIM_SIGMA * SSIM_SIGMA)).exp()).collect::<Vec<_>>();
+    let total = kernel.iter().sum::<f32>();
+    let kernel = kernel.iter().map(|k| k / total).collect::<Vec<_>>();
+
+    let x = test.map(|c| c.luminance().min(1.0));
+    let y = reference.map(|c| c.luminance().min(1.0));
+    let blur = |image: Image<f32>| image.convolve_separable(&kernel, &kernel);
+
+    let product = |a: &Image<f32>, b: &Image<f32>| Image::new(a.width(), a.height(), a.pixels().iter().zip(b.pixels()).map(|(a, b)| a * b).collect());
+    let (xx, yy, xy) = (blur(product(&x, &x)), blur(product(&y, &y)), blur(product(&x, &y)));
+    let (mx, my) = (blur(x), blur(y));
+
+    let sum = (0..mx.pixel_count()).map(|i| {
+        let (mx, my) = (mx.pixels()[i], my.pixels()[i]);
+        let var_x = xx.pixels()[i] - mx * mx;
+        let var_y = yy.pixels()[i] - my * my;
+        let cov = xy.pixels()[i] - mx * my;
+        ((2.0 * mx * my + SSIM_C1) * (2.0 * cov + SSIM_C2)) / ((mx * mx + my * my + SSIM_C1) * (var_x + var_y + SSIM_C2))
+    }).sum::<f32>();
+
+    sum / mx.pixel_count() as f32
+}
+
+
+// Maps errors in [0, 1] to colors, so small differences stand out
+pub fn false_color(errors: &Image<f32>) -> Image {
+    errors.map(|e| {
+        let t = e.clamp(0.0, 1.0) * (ERROR_COLORMAP.len() - 1) as f32;
+        let i = (t as usize).min(ERROR_COLORMAP.len() - 2);
+        let f = t - i as f32;
+
+        let (a, b) = (ERROR_COLORMAP[i], ERROR_COLORMAP[i + 1]);
+        let lerp = |c: usize| (a[c] + (b[c] - a[c]) * f).powf(2.2);
+        Color::new(lerp(0), lerp(1), lerp(2))
+    })
+}
@@ -0,0 +1,96 @@
+// Checks the image metrics on images with known differences, and the threshold that decides the exit code of compare.
+
+use crate::color::*;
+use crate::image::*;
+use crate::compare::*;
+use crate::{parse_compare_args, compare_images};
+
+use std::path::PathBuf;
+
+
+const WIDTH: u32 = 32;
+const HEIGHT: u32 = 24;
+const OFFSET: f32 = 0.1;
+
+
+// Smooth gradients with some detail, kept in [0, 0.8] so adding OFFSET doesn't clamp
+fn test_image() -> Image {
+    let pixels = (0..HEIGHT).flat_map(|y| (0..WIDTH).map(move |x| {
+        let (u, v) = (x as f32 / WIDTH as f32, y as f32 / HEIGHT as f32);
+        let checker = ((x / 4 + y / 4) % 2) as f32 * 0.2;
+        Color::new(u * 0.6 + checker, v * 0.6 + checker, (u + v) * 0.3)
+    })).collect();
+    Image::new(WIDTH, HEIGHT, pixels)
+}
+
+fn offset_image() -> Image {
+    test_image().map(|c| c + Color::from(OFFSET))
+}
+
+fn write_temp(name: &str, image: &Image) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rt-compare-tests-{}", std::process::id())).join(name);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    image.save(&path).unwrap();
+    path
+}
+
+
+#[test]
+fn identical_images() {
+    let image = test_image();
+    let (metrics, errors) = compare(&image, &image);
+
+    assert_eq!(metrics.mse, 0.0);
+    assert_eq!(metrics.rel_mse, 0.0);
+    assert_eq!(metrics.psnr, f32::INFINITY);
+    assert!((metrics.ssim - 1.0).abs() < 1e-5, "SSIM is {}", metrics.ssim);
+    assert_eq!(metrics.flip, 0.0);
+    assert!(errors.pixels().iter().all(|e| *e == 0.0));
+}
+
+#[test]
+fn offset_images() {
+    let (metrics, _) = compare(&offset_image(), &test_image());
+
+    let expected_mse = OFFSET * OFFSET;
+    assert!((metrics.mse - expected_mse).abs() < 1e-6, "MSE is {}", metrics.mse);
+    assert!((metrics.psnr - -10.0 * expected_mse.log10()).abs() < 1e-3, "PSNR is {}", metrics.psnr);
+    assert!(metrics.ssim < 1.0);
+    assert!(metrics.flip > 0.0);
+}
+
+#[test]
+fn metric_thresholds() {
+    let (metrics, _) = compare(&offset_image(), &test_image());
+
+    assert!(!metrics.exceeds(Metric::Mse, 0.02));
+    assert!(metrics.exceeds(Metric::Mse, 0.005));
+    // Higher is better for PSNR, 20 dB here
+    assert!(!metrics.exceeds(Metric::Psnr, 15.0));
+    assert!(metrics.exceeds(Metric::Psnr, 25.0));
+
+    let nan = Metrics { mse: f32::NAN, ssim: f32::NAN, ..metrics };
+    assert!(nan.exceeds(Metric::Mse, f32::INFINITY));
+    assert!(nan.exceeds(Metric::Ssim, f32::NEG_INFINITY));
+}
+
+// compare exits with 1 when compare_images returns false
+#[test]
+fn compare_exit_code() {
+    let reference = write_temp("reference.exr", &test_image());
+    let test = write_temp("test.exr", &offset_image());
+
+    let run = |test: &PathBuf, args: &[&str]| {
+        let paths = [test, &reference].map(|path| path.to_string_lossy().into_owned());
+        let args = parse_compare_args(paths.into_iter().chain(args.iter().map(|arg| arg.to_string()))).unwrap();
+        compare_images(&args).unwrap()
+    };
+
+    assert!(run(&test, &[]));
+    assert!(run(&test, &["--metric", "mse", "--threshold", "0.02"]));
+    assert!(!run(&test, &["--metric", "mse", "--threshold", "0.005"]));
+    assert!(run(&test, &["--metric", "psnr", "--threshold", "15"]));
+    assert!(!run(&test, &["--metric", "psnr", "--threshold", "25"]));
+    assert!(!run(&test, &["--threshold", "0"]));
+    assert!(run(&reference, &["--threshold", "0"]));
+}
@@ -2,19 +2,35 @@ use crate::vec::*;
 use crate::image::*;
 use crate::color::*;
 use crate::utils::*;
+use crate::aov::*;
 
+use rayon::prelude::*;
 
-const SIGMA: f32 = 0.4;
 
-pub fn denoise(input: &Image) -> Image {
-    let mut pixels = vec![Color::from(0.0); input.pixel_count()];
+// Filtering strength relative to the noise level, and how much of the expected noise is cancelled from the distances
+const STRENGTH: f32 = 0.45;
+const VARIANCE_CANCELLATION: f32 = 1.0;
+// The half buffer variance of a single pixel is very noisy, it is averaged over this radius
+const VARIANCE_RADIUS: i32 = 3;
+const MIN_VARIANCE: f32 = 1e-10;
 
-    for x in 0..input.width() {
-        for y in 0..input.height() {
-            let index = input.pixel_index(x, y);
-            pixels[index] = denoise_pixel(x, y, input, SIGMA);
-        }
-    }
+const GUIDED_NEIGH_SIZE: i32 = 1;
+const GUIDED_WEIGHT_SIZE: i32 = 7;
+const NORMAL_SIGMA: f32 = 0.25;
+const DEPTH_SIGMA: f32 = 0.05;
+const ALBEDO_SIGMA: f32 = 0.1;
+
+// NL-means with the color distance normalized by the variance of the mean of every pixel,
+// so noisy pixels are filtered more than clean ones without any per scene tuning
+pub fn denoise(input: &Image, variance: &Image) -> Image {
+    let variance = smooth_variance(variance);
+    let patch_values = (NEIGH_PIXELS * 3) as f32;
+    let accumulated = nl_means(input, WEIGHT_SIZE, NEIGH_SIZE, color_distance(input, &variance), |_, _, d2| (-(d2 / patch_values).max(0.0)).exp());
+
+    // The center pixel is its own best match (weight of 1), and gets counted a second time with that maximum weight
+    let pixels = accumulated.into_iter().zip(input.pixels()).map(|((acc, total), center)| {
+        (acc + *center) / (total + 1.0)
+    }).collect();
 
     Image::new(input.width(), input.height(), pixels)
 }
@@ -25,88 +41,153 @@ const NEIGH_SIDE_PX: usize = (NEIGH_SIZE * 2 + 1) as usize;
 const NEIGH_PIXELS: usize = NEIGH_SIDE_PX * NEIGH_SIDE_PX;
 
 const WEIGHT_SIZE: i32 = 10;
-const WEIGHT_SIDE_PX: usize = (WEIGHT_SIZE * 2 + 1) as usize;
-const WEIGHT_PIXELS: usize = WEIGHT_SIDE_PX * WEIGHT_SIDE_PX;
 
-fn collect_neighbourhood(x: u32, y: u32, input: &Image) -> [Color; NEIGH_PIXELS] {
-    let mut neighbourhood = [Color::from(0.0); NEIGH_PIXELS];
 
-    let (x, y) = (x as i32, y as i32);
-    for i in 0..NEIGH_PIXELS {
-        let a = (i % NEIGH_SIDE_PX) as i32;
-        let b = (i / NEIGH_SIDE_PX) as i32;
-        neighbourhood[i] = input.pixel_at_clamped(x + a - NEIGH_SIZE, y + b - NEIGH_SIZE);
-    }
+// Accumulates every candidate of the search window of every pixel, weighted by weight(pixel, offset, patch distance).
+// Works one offset at a time so patch distances can be computed with separable running sums,
+// which makes the cost independent of the patch size.
+pub fn nl_means<D, F>(input: &Image, weight_size: i32, neigh_size: i32, pixel_distance: D, weight: F) -> Vec<(Color, f32)>
+    where D: Fn((i32, i32), (i32, i32)) -> f32 + Sync, F: Fn((i32, i32), (i32, i32), f32) -> f32 + Sync {
+    let width = input.width() as i32;
 
-    neighbourhood
-}
+    let offsets = (-weight_size..(weight_size + 1)).flat_map(|kx| (-weight_size..(weight_size + 1)).map(move |ky| (kx, ky))).collect::<Vec<_>>();
 
-fn compute_weights(x: u32, y: u32, neighbourhood: [Color; NEIGH_PIXELS], input: &Image, h: f32) -> [f32; WEIGHT_PIXELS] {
-    let mut weights = [0.0; WEIGHT_PIXELS];
-    let mut w_index = 0;
+    let empty = || vec![(Color::from(0.0), 0.0); input.pixel_count()];
+    offsets.par_iter().fold(empty, |mut acc, &(kx, ky)| {
+        let distances = patch_distances(input.width(), input.height(), (kx, ky), neigh_size, &pixel_distance);
+        for (i, (d2, (color, total))) in distances.into_iter().zip(acc.iter_mut()).enumerate() {
+            let (x, y) = (i as i32 % width, i as i32 / width);
+            let w = weight((x, y), (kx, ky), d2);
+            *color += input.pixel_at_clamped(x + kx, y + ky) * w;
+            *total += w;
+        }
+        acc
+    }).reduce(empty, |mut a, b| {
+        for ((color, total), (c, t)) in a.iter_mut().zip(b) {
+            *color += c;
+            *total += t;
+        }
+        a
+    })
+}
 
-    let (x, y) = (x as i32, y as i32);
-    for kx in -WEIGHT_SIZE..(WEIGHT_SIZE + 1) {
-        for ky in -WEIGHT_SIZE..(WEIGHT_SIZE + 1) {
-            let mut d2 = 0.0;
-            let mut index = 0;
+// Sum of pixel_distance over the patch around every pixel and the patch around the same pixel shifted by (kx, ky)
+fn patch_distances<D: Fn((i32, i32), (i32, i32)) -> f32>(width: u32, height: u32, (kx, ky): (i32, i32), neigh_size: i32, pixel_distance: &D) -> Vec<f32> {
+    let (width, height) = (width as usize, height as usize);
+    let side = (neigh_size * 2 + 1) as usize;
+    let padded_width = width + side - 1;
+    let padded_height = height + side - 1;
+
+    // Per pixel differences, over the image extended by the patch radius
+    let mut diffs = Vec::with_capacity(padded_width * padded_height);
+    for v in -neigh_size..(height as i32 + neigh_size) {
+        for u in -neigh_size..(width as i32 + neigh_size) {
+            diffs.push(pixel_distance((u, v), (u + kx, v + ky)));
+        }
+    }
 
-            for px in -NEIGH_SIZE..(NEIGH_SIZE + 1) {
-                for py in -NEIGH_SIZE..(NEIGH_SIZE + 1) {
-                    let qc = Vec3::from(input.pixel_at_clamped(x + kx + px, y + ky + py));
-                    let pc = Vec3::from(neighbourhood[index]);
+    let mut horizontal = vec![0.0; width * padded_height];
+    for (row, out) in diffs.chunks_exact(padded_width).zip(horizontal.chunks_exact_mut(width)) {
+        let mut sum: f32 = row[..side].iter().sum();
+        out[0] = sum;
+        for x in 1..width {
+            sum += row[x + side - 1] - row[x - 1];
+            out[x] = sum;
+        }
+    }
 
-                    let diff = pc - qc;
-                    let err = diff * diff;
-                    d2 += err.x + err.y + err.z;
+    let row = |v: usize| &horizontal[v * width..(v + 1) * width];
 
-                    index += 1;
-                }
-            }
+    let mut sums = vec![0.0; width];
+    for v in 0..(side - 1) {
+        sums.iter_mut().zip(row(v)).for_each(|(s, h)| *s += h);
+    }
 
-            weights[w_index] = (-d2 / (index as f32 * h * h)).exp();
-            w_index += 1;
-        }
+    let mut distances = Vec::with_capacity(width * height);
+    for y in 0..height {
+        sums.iter_mut().zip(row(y + side - 1)).for_each(|(s, h)| *s += h);
+        distances.extend(sums.iter());
+        sums.iter_mut().zip(row(y)).for_each(|(s, h)| *s -= h);
     }
 
-    weights
+    distances
 }
 
-fn apply_weights(x: u32, y: u32, weights: [f32; WEIGHT_PIXELS], input: &Image) -> Color {
-    let mut acc = Color::from(0.0);
-    let mut total = 0.0;
-    let mut max: f32 = 0.0;
-    let mut w_index = 0;
 
-    let center = input.pixel_at(x, y);
 
-    let (x, y) = (x as i32, y as i32);
-    for kx in -WEIGHT_SIZE..(WEIGHT_SIZE + 1) {
-        for ky in -WEIGHT_SIZE..(WEIGHT_SIZE + 1) {
-            let c = input.pixel_at_clamped(x + kx, y + ky);
-            let w = weights[w_index];
+// Joint NL-means guided by the first hit albedo, normal and depth.
+// Filtering happens on the demodulated irradiance so texture detail carried by the albedo is never blurred.
+pub fn denoise_guided(input: &Image, variance: &Image, aovs: &AovImages) -> Image {
+    let demodulated = Image::new(input.width(), input.height(), input.pixels().iter().zip(aovs.albedo.pixels()).map(|(c, a)| demodulate(*c, *a)).collect());
 
-            total += w;
-            acc += c * w;
+    // Dividing the color by the albedo divides its variance by the squared albedo
+    let variance = smooth_variance(variance);
+    let variance = Image::new(input.width(), input.height(), variance.pixels().iter().zip(aovs.albedo.pixels()).map(|(v, a)| demodulate(demodulate(*v, *a), *a)).collect());
 
-            max = max.max(w);
-            w_index += 1;
-        }
-    }
+    let patch_values = ((GUIDED_NEIGH_SIZE * 2 + 1) * (GUIDED_NEIGH_SIZE * 2 + 1) * 3) as f32;
+
+    let accumulated = nl_means(&demodulated, GUIDED_WEIGHT_SIZE, GUIDED_NEIGH_SIZE, color_distance(&demodulated, &variance), |(x, y), (kx, ky), d2| {
+        (-((d2 / patch_values).max(0.0) + feature_distance(aovs, (x, y), (x + kx, y + ky)))).exp()
+    });
+
+    // The center pixel always has a weight of 1, so total can't be 0
+    let pixels = accumulated.into_iter().zip(aovs.albedo.pixels()).map(|((acc, total), albedo)| {
+        remodulate(acc / total, *albedo)
+    }).collect();
 
-    if max < EPSILON {
-        center
-    } else {
-        acc += center * max;
-        total += max;
-        acc / total
+    Image::new(input.width(), input.height(), pixels)
+}
+
+// Squared color difference of two pixels over the variance of that difference, summed over the channels.
+// The expected noise is subtracted first, so two pixels only differing by noise end up close to 0 (Rousselle et al. 2012)
+pub fn color_distance<'a>(input: &'a Image, variance: &'a Image) -> impl Fn((i32, i32), (i32, i32)) -> f32 + Sync + 'a {
+    move |(px, py), (qx, qy)| {
+        let (p, q) = (input.pixel_at_clamped(px, py), input.pixel_at_clamped(qx, qy));
+        let (p_var, q_var) = (variance.pixel_at_clamped(px, py), variance.pixel_at_clamped(qx, qy));
+
+        let channel = |p: f32, q: f32, p_var: f32, q_var: f32| {
+            let diff = p - q;
+            (diff * diff - VARIANCE_CANCELLATION * (p_var + p_var.min(q_var))) / (MIN_VARIANCE + STRENGTH * STRENGTH * (p_var + q_var))
+        };
+
+        channel(p.r, q.r, p_var.r, q_var.r) + channel(p.g, q.g, p_var.g, q_var.g) + channel(p.b, q.b, p_var.b, q_var.b)
     }
 }
 
-fn denoise_pixel(x: u32, y: u32, input: &Image, sigma: f32) -> Color {
-    let neighbourhood = collect_neighbourhood(x, y, input);
-    let weights = compute_weights(x, y, neighbourhood, input, sigma);
+pub fn smooth_variance(variance: &Image) -> Image {
+    let pixels = (0..variance.height() as i32).flat_map(|y| (0..variance.width() as i32).map(move |x| (x, y))).map(|(x, y)| {
+        let mut sum = Color::from(0.0);
+        for v in (y - VARIANCE_RADIUS)..(y + VARIANCE_RADIUS + 1) {
+            for u in (x - VARIANCE_RADIUS)..(x + VARIANCE_RADIUS + 1) {
+                sum += variance.pixel_at_clamped(u, v);
+            }
+        }
+        sum / ((VARIANCE_RADIUS * 2 + 1) * (VARIANCE_RADIUS * 2 + 1)) as f32
+    }).collect();
+
+    Image::new(variance.width(), variance.height(), pixels)
+}
+
+fn demodulate(color: Color, albedo: Color) -> Color {
+    let div = |c: f32, a: f32| if a > EPSILON { c / a } else { c };
+    Color::new(div(color.r, albedo.r), div(color.g, albedo.g), div(color.b, albedo.b))
+}
 
-    apply_weights(x, y, weights, input)
+fn remodulate(irradiance: Color, albedo: Color) -> Color {
+    let mul = |c: f32, a: f32| if a > EPSILON { c * a } else { c };
+    Color::new(mul(irradiance.r, albedo.r), mul(irradiance.g, albedo.g), mul(irradiance.b, albedo.b))
 }
 
+fn feature_distance(aovs: &AovImages, p: (i32, i32), q: (i32, i32)) -> f32 {
+    let normal_diff = aovs.normal.pixel_at_clamped(p.0, p.1) - aovs.normal.pixel_at_clamped(q.0, q.1);
+    let albedo_diff = Vec3::from(aovs.albedo.pixel_at_clamped(p.0, p.1)) - Vec3::from(aovs.albedo.pixel_at_clamped(q.0, q.1));
+
+    // Depth is compared relatively so the filter behaves the same at any scene scale
+    let p_depth = aovs.depth.pixel_at_clamped(p.0, p.1);
+    let q_depth = aovs.depth.pixel_at_clamped(q.0, q.1);
+    let depth_diff = (p_depth - q_depth) / p_depth.max(q_depth).max(EPSILON);
+
+    normal_diff.length2() / (NORMAL_SIGMA * NORMAL_SIGMA) +
+    albedo_diff.length2() / (ALBEDO_SIGMA * ALBEDO_SIGMA) +
+    depth_diff * depth_diff / (DEPTH_SIGMA * DEPTH_SIGMA)
+}
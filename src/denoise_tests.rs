@@ -0,0 +1,95 @@
+// Checks the separable NL-means against a direct per pixel implementation, which compares every patch pixel by pixel.
+
+use crate::vec::*;
+use crate::color::*;
+use crate::image::*;
+use crate::denoise::*;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+
+const WIDTH: u32 = 23;
+const HEIGHT: u32 = 17;
+
+// Both sum the same distances in a different order
+const TOLERANCE: f32 = 1e-3;
+
+
+fn random_image(rng: &mut StdRng, scale: f32, offset: f32) -> Image {
+    let pixels = (0..WIDTH * HEIGHT).map(|_| Color::new(rng.gen(), rng.gen(), rng.gen()) * scale + Color::from(offset)).collect();
+    Image::new(WIDTH, HEIGHT, pixels)
+}
+
+fn brute_force<D, F>(input: &Image, weight_size: i32, neigh_size: i32, pixel_distance: D, weight: F) -> Vec<(Color, f32)>
+    where D: Fn((i32, i32), (i32, i32)) -> f32, F: Fn((i32, i32), (i32, i32), f32) -> f32 {
+    let mut accumulated = Vec::new();
+    for y in 0..input.height() as i32 {
+        for x in 0..input.width() as i32 {
+            let mut acc = Color::from(0.0);
+            let mut total = 0.0;
+            for ky in -weight_size..(weight_size + 1) {
+                for kx in -weight_size..(weight_size + 1) {
+                    let mut d2 = 0.0;
+                    for py in -neigh_size..(neigh_size + 1) {
+                        for px in -neigh_size..(neigh_size + 1) {
+                            d2 += pixel_distance((x + px, y + py), (x + kx + px, y + ky + py));
+                        }
+                    }
+
+                    let w = weight((x, y), (kx, ky), d2);
+                    acc += input.pixel_at_clamped(x + kx, y + ky) * w;
+                    total += w;
+                }
+            }
+            accumulated.push((acc, total));
+        }
+    }
+    accumulated
+}
+
+fn check_close(name: &str, result: &[(Color, f32)], expected: &[(Color, f32)]) {
+    assert_eq!(result.len(), expected.len());
+    let close = |a: f32, b: f32| (a - b).abs() <= TOLERANCE * b.abs().max(1.0);
+    for (i, ((acc, total), (expected_acc, expected_total))) in result.iter().zip(expected).enumerate() {
+        let ok = close(*total, *expected_total) &&
+            close(acc.r, expected_acc.r) && close(acc.g, expected_acc.g) && close(acc.b, expected_acc.b);
+        assert!(ok, "{}: pixel {} is {:?}, expected {:?}", name, i, (acc, total), (expected_acc, expected_total));
+    }
+}
+
+
+#[test]
+fn nl_means_matches_brute_force() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let input = random_image(&mut rng, 1.0, 0.0);
+    let variance = smooth_variance(&random_image(&mut rng, 0.05, 0.01));
+
+    let (weight_size, neigh_size) = (4, 2);
+    let patch_values = ((neigh_size * 2 + 1) * (neigh_size * 2 + 1) * 3) as f32;
+    let weight = |_, _, d2: f32| (-(d2 / patch_values).max(0.0)).exp();
+
+    let result = nl_means(&input, weight_size, neigh_size, color_distance(&input, &variance), weight);
+    let expected = brute_force(&input, weight_size, neigh_size, color_distance(&input, &variance), weight);
+    check_close("color distance", &result, &expected);
+}
+
+// The weight also gets the pixel and the offset, like the feature distances of the guided denoiser
+#[test]
+fn nl_means_pixel_weights_match_brute_force() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let input = random_image(&mut rng, 2.0, 0.0);
+    let features = random_image(&mut rng, 1.0, 0.0);
+
+    let squared_diff = |(px, py), (qx, qy)| {
+        (Vec3::from(input.pixel_at_clamped(px, py)) - Vec3::from(input.pixel_at_clamped(qx, qy))).length2()
+    };
+    let weight = |(x, y), (kx, ky), d2: f32| {
+        let diff = features.pixel_at_clamped(x, y).r - features.pixel_at_clamped(x + kx, y + ky).r;
+        (-(d2 / 27.0 + diff * diff * 4.0)).exp()
+    };
+
+    let result = nl_means(&input, 3, 1, squared_diff, weight);
+    let expected = brute_force(&input, 3, 1, squared_diff, weight);
+    check_close("pixel weights", &result, &expected);
+}
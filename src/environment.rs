@@ -0,0 +1,118 @@
+use crate::vec::*;
+use crate::color::*;
+use crate::image::*;
+use crate::transform::*;
+use crate::serialize::*;
+
+use std::f32::consts::PI;
+use std::io::{self, Read, Write};
+
+
+// Radiance coming from infinitely far away, seen by rays that leave the scene
+pub enum Environment {
+    Color(Color),
+    // to_map turns world directions into directions of the map
+    Image { image: Image, strength: f32, mapping: EnvironmentMapping, to_map: Transform },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvironmentMapping {
+    // Longitude and latitude, with -Z at the center of the image and +Y at its top
+    Equirectangular,
+    // Octahedral equal area square of pbrt-v4 (Clarberg 2008), with +Z at the center of the image
+    EqualArea,
+}
+
+
+impl Environment {
+    pub fn black() -> Environment {
+        Environment::Color(Color::from(0.0))
+    }
+
+    pub fn radiance(&self, dir: Vec3) -> Color {
+        match self {
+            Environment::Color(color) => *color,
+            Environment::Image { image, strength, mapping, to_map } => {
+                let (u, v) = mapping.uv(to_map.transform_dir(dir).normalized());
+                let x = ((u * image.width() as f32) as u32).min(image.width() - 1);
+                let y = ((v * image.height() as f32) as u32).min(image.height() - 1);
+                image.pixel_at(x, y) * *strength
+            },
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Environment::Color(color) => {
+                write_u32(writer, 0)?;
+                write_color(writer, *color)
+            },
+            Environment::Image { image, strength, mapping, to_map } => {
+                write_u32(writer, 1)?;
+                write_u32(writer, image.width())?;
+                write_u32(writer, image.height())?;
+                for p in image.pixels() {
+                    write_color(writer, *p)?;
+                }
+                write_f32(writer, *strength)?;
+                write_u32(writer, match mapping {
+                    EnvironmentMapping::Equirectangular => 0,
+                    EnvironmentMapping::EqualArea => 1,
+                })?;
+                to_map.write_to(writer)
+            },
+        }
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Environment> {
+        match read_u32(reader)? {
+            0 => Ok(Environment::Color(read_color(reader)?)),
+            1 => {
+                let width = read_u32(reader)?;
+                let height = read_u32(reader)?;
+                // radiance always reads a pixel
+                if width == 0 || height == 0 {
+                    return Err(invalid_data("empty environment image"));
+                }
+                let pixels = (0..width as u64 * height as u64).map(|_| read_color(reader)).collect::<io::Result<Vec<_>>>()?;
+                let strength = read_f32(reader)?;
+                let mapping = match read_u32(reader)? {
+                    0 => EnvironmentMapping::Equirectangular,
+                    1 => EnvironmentMapping::EqualArea,
+                    _ => return Err(invalid_data("invalid environment mapping")),
+                };
+                Ok(Environment::Image {
+                    image: Image::new(width, height, pixels),
+                    strength: strength,
+                    mapping: mapping,
+                    to_map: Transform::read_from(reader)?,
+                })
+            },
+            _ => Err(invalid_data("invalid environment")),
+        }
+    }
+}
+
+
+impl EnvironmentMapping {
+    // Image coordinates in [0, 1], from the top left corner
+    fn uv(&self, dir: Vec3) -> (f32, f32) {
+        match self {
+            EnvironmentMapping::Equirectangular => (0.5 + dir.x.atan2(-dir.z) / (2.0 * PI), dir.y.clamp(-1.0, 1.0).acos() / PI),
+            EnvironmentMapping::EqualArea => {
+                let (x, y, z) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+                let r = (1.0 - z).max(0.0).sqrt();
+                let (a, b) = (x.max(y), x.min(y));
+                let b = if a == 0.0 { 0.0 } else { b / a };
+                let phi = b.atan() * 2.0 / PI;
+                let phi = if x < y { 1.0 - phi } else { phi };
+
+                let (mut u, mut v) = (r - phi * r, phi * r);
+                if dir.z < 0.0 {
+                    (u, v) = (1.0 - v, 1.0 - u);
+                }
+                (0.5 * (u.copysign(dir.x) + 1.0), 0.5 * (v.copysign(dir.y) + 1.0))
+            },
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::vec::*;
+use crate::color::*;
+use crate::image::*;
+use crate::tile::*;
+use crate::serialize::*;
+use crate::aov::*;
+
+use std::io::{self, Read, Write};
+
+
+// Keeps the relative error of very dark pixels from blowing up
+const MIN_ERROR_LUMINANCE: f32 = 0.01;
+// Larger films come from a corrupted file
+const MAX_FILM_SIZE: u32 = 1 << 16;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    pub min_spp: usize,
+    pub max_spp: usize,
+    pub pass_spp: usize,
+    pub threshold: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PixelStats {
+    sum: Color,
+    // Sum of the odd samples only, which splits the pixel in two independent half buffers
+    half_sum: Color,
+    mean: f32,
+    m2: f32,
+    count: u32,
+}
+
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<PixelStats>,
+    aovs: Option<Vec<AovPixel>>,
+}
+
+pub struct FilmTile {
+    pub pixels: Vec<PixelStats>,
+    pub aovs: Option<Vec<AovPixel>>,
+}
+
+
+impl PixelStats {
+    pub fn new() -> PixelStats {
+        PixelStats {
+            sum: Color::from(0.0),
+            half_sum: Color::from(0.0),
+            mean: 0.0,
+            m2: 0.0,
+            count: 0,
+        }
+    }
+
+    // Welford's online algorithm over the sample luminance
+    pub fn add(&mut self, color: Color) {
+        let lum = color.luminance();
+
+        if self.count % 2 == 1 {
+            self.half_sum += color;
+        }
+
+        self.count += 1;
+        self.sum += color;
+
+        let delta = lum - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (lum - self.mean);
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn mean(&self) -> Color {
+        if self.count == 0 {
+            Color::from(0.0)
+        } else {
+            self.sum / self.count as f32
+        }
+    }
+
+    pub fn variance(&self) -> f32 {
+        if self.count < 2 {
+            0.0
+        } else {
+            (self.m2 / (self.count - 1) as f32).max(0.0)
+        }
+    }
+
+    // Per channel variance of the mean, estimated from the difference between the two half buffers
+    pub fn buffer_variance(&self) -> Color {
+        let odd = self.count / 2;
+        let even = self.count - odd;
+        if odd == 0 {
+            return Color::from(0.0);
+        }
+
+        let half = Vec3::from(self.half_sum);
+        let diff = (Vec3::from(self.sum) - half) / even as f32 - half / odd as f32;
+        let scale = (even * odd) as f32 / (self.count * self.count) as f32;
+        Color::new(diff.x * diff.x * scale, diff.y * diff.y * scale, diff.z * diff.z * scale)
+    }
+
+    // Relative standard error of the mean luminance
+    pub fn error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+
+        let std_error = (self.variance() / self.count as f32).sqrt();
+        std_error / self.mean.max(MIN_ERROR_LUMINANCE)
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_color(writer, self.sum)?;
+        write_color(writer, self.half_sum)?;
+        write_f32(writer, self.mean)?;
+        write_f32(writer, self.m2)?;
+        write_u32(writer, self.count)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<PixelStats> {
+        let sum = [read_f32(reader)?, read_f32(reader)?, read_f32(reader)?];
+        let half_sum = [read_f32(reader)?, read_f32(reader)?, read_f32(reader)?];
+        if sum.iter().chain(&half_sum).any(|c| c.is_nan() || *c < 0.0) {
+            return Err(invalid_data("invalid pixel value"));
+        }
+
+        Ok(PixelStats {
+            sum: Color::from(sum),
+            half_sum: Color::from(half_sum),
+            mean: read_f32(reader)?,
+            m2: read_f32(reader)?,
+            count: read_u32(reader)?,
+        })
+    }
+}
+
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film {
+            width: width,
+            height: height,
+            pixels: vec![PixelStats::new(); (width as usize) * (height as usize)],
+            aovs: None,
+        }
+    }
+
+    pub fn with_aovs(width: u32, height: u32) -> Film {
+        Film {
+            aovs: Some(vec![AovPixel::new(); (width as usize) * (height as usize)]),
+            ..Film::new(width, height)
+        }
+    }
+
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[PixelStats] {
+        self.pixels.as_slice()
+    }
+
+    pub fn pixel_at(&self, x: u32, y: u32) -> &PixelStats {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn tile(&self, tile: &Tile) -> FilmTile {
+        let index = |(x, y): (u32, u32)| (y * self.width + x) as usize;
+        FilmTile {
+            pixels: tile.pixels().map(|p| self.pixels[index(p)]).collect(),
+            aovs: self.aovs.as_ref().map(|aovs| tile.pixels().map(|p| aovs[index(p)]).collect()),
+        }
+    }
+
+    pub fn set_tile(&mut self, tile: &Tile, data: FilmTile) {
+        debug_assert!(data.pixels.len() == tile.pixel_count());
+
+        let width = self.width;
+        let index = |(x, y): (u32, u32)| (y * width + x) as usize;
+        for (p, stats) in tile.pixels().zip(data.pixels) {
+            self.pixels[index(p)] = stats;
+        }
+
+        if let (Some(aovs), Some(tile_aovs)) = (&mut self.aovs, data.aovs) {
+            for (p, aov) in tile.pixels().zip(tile_aovs) {
+                aovs[index(p)] = aov;
+            }
+        }
+    }
+
+    pub fn total_samples(&self) -> usize {
+        self.pixels.iter().map(|p| p.sample_count()).sum()
+    }
+
+    pub fn max_samples(&self) -> usize {
+        self.pixels.iter().map(|p| p.sample_count()).max().unwrap_or(0)
+    }
+
+    pub fn resolve<F: Fn(Color) -> Color>(&self, map: F) -> Image {
+        Image::new(self.width, self.height, self.pixels.iter().map(|p| map(p.mean())).collect())
+    }
+
+    // Variance of the mean luminance of every pixel
+    pub fn variance_image(&self) -> Image<f32> {
+        Image::new(self.width, self.height, self.pixels.iter().map(|p| p.variance() / (p.sample_count().max(1) as f32)).collect())
+    }
+
+    pub fn buffer_variance_image(&self) -> Image {
+        Image::new(self.width, self.height, self.pixels.iter().map(|p| p.buffer_variance()).collect())
+    }
+
+    pub fn aov_images(&self) -> Option<AovImages> {
+        self.aovs.as_ref().map(|aovs| AovImages::from_pixels(self.width, self.height, aovs))
+    }
+
+    pub fn sample_count_image(&self) -> Image {
+        Image::new(self.width, self.height, self.pixels.iter().map(|p| Color::from(p.sample_count() as f32)).collect())
+    }
+
+    // Stores the raw accumulators bit for bit, so a film read back continues exactly where it stopped
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u32(writer, self.width)?;
+        write_u32(writer, self.height)?;
+        for p in &self.pixels {
+            p.write_to(writer)?;
+        }
+
+        write_u32(writer, self.aovs.is_some() as u32)?;
+        for aov in self.aovs.iter().flatten() {
+            aov.write_to(writer)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Film> {
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        if width > MAX_FILM_SIZE || height > MAX_FILM_SIZE {
+            return Err(invalid_data(format!("invalid film size {}x{}", width, height)));
+        }
+
+        // Pixels are read one by one, so a corrupted size fails on the end of the file instead of allocating it all upfront
+        let pixel_count = width as u64 * height as u64;
+        let pixels = (0..pixel_count).map(|_| PixelStats::read_from(reader)).collect::<io::Result<Vec<_>>>()?;
+
+        let aovs = if read_u32(reader)? != 0 {
+            Some((0..pixel_count).map(|_| AovPixel::read_from(reader)).collect::<io::Result<_>>()?)
+        } else {
+            None
+        };
+
+        Ok(Film {
+            width: width,
+            height: height,
+            pixels: pixels,
+            aovs: aovs,
+        })
+    }
+}
+
+
+impl AdaptiveSampling {
+    // Number of samples to add to a pixel in the next pass, zero once it has converged
+    pub fn samples_for(&self, stats: &PixelStats) -> usize {
+        let count = stats.sample_count();
+        if count < self.min_spp {
+            return self.min_spp - count;
+        }
+
+        if count >= self.max_spp || stats.error() <= self.threshold {
+            return 0;
+        }
+
+        self.pass_spp.min(self.max_spp - count)
+    }
+}
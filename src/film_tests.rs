@@ -0,0 +1,98 @@
+// Checks the per pixel statistics driving adaptive sampling: the half buffer variance, the number of samples each pixel gets, and the arguments enabling it.
+
+use crate::color::*;
+use crate::film::*;
+use crate::parse_args;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+
+const ADAPTIVE: AdaptiveSampling = AdaptiveSampling {
+    min_spp: 8,
+    max_spp: 64,
+    pass_spp: 4,
+    threshold: 0.05,
+};
+
+
+fn pixel<I: IntoIterator<Item = Color>>(samples: I) -> PixelStats {
+    let mut stats = PixelStats::new();
+    for color in samples {
+        stats.add(color);
+    }
+    stats
+}
+
+fn converged(count: usize) -> PixelStats {
+    pixel((0..count).map(|_| Color::new(0.5, 0.25, 1.0)))
+}
+
+// Alternates between black and white, far from converged at any sample count
+fn noisy(count: usize) -> PixelStats {
+    pixel((0..count).map(|i| Color::from((i % 2) as f32)))
+}
+
+
+#[test]
+fn buffer_variance() {
+    assert_eq!(converged(16).buffer_variance(), Color::from(0.0));
+    assert_eq!(pixel([Color::from(1.0)]).buffer_variance(), Color::from(0.0));
+
+    // Half buffers of 0 and 1: the means differ by 1, and each is a quarter of the variance of the mean
+    assert_eq!(noisy(16).buffer_variance(), Color::from(0.25));
+
+    // Averaged over many pixels, it estimates the variance of the mean, 1 / 12 / n for uniform samples
+    let mut rng = StdRng::seed_from_u64(7);
+    let (pixels, count) = (4000, 64);
+    let mean = (0..pixels).map(|_| {
+        let stats = pixel((0..count).map(|_| Color::new(rng.gen::<f32>(), rng.gen::<f32>(), 0.5)));
+        stats.buffer_variance()
+    }).fold(Color::from(0.0), |acc, v| acc + v) / pixels as f32;
+
+    let expected = 1.0 / 12.0 / count as f32;
+    assert!((mean.r - expected).abs() < expected * 0.1, "{} instead of {}", mean.r, expected);
+    assert!((mean.g - expected).abs() < expected * 0.1, "{} instead of {}", mean.g, expected);
+    assert_eq!(mean.b, 0.0);
+}
+
+#[test]
+fn adaptive_samples() {
+    // Every pixel first gets the minimum
+    assert_eq!(ADAPTIVE.samples_for(&PixelStats::new()), 8);
+    assert_eq!(ADAPTIVE.samples_for(&noisy(3)), 5);
+
+    // Then only noisy pixels get more, a pass at a time
+    assert_eq!(ADAPTIVE.samples_for(&converged(8)), 0);
+    assert_eq!(ADAPTIVE.samples_for(&noisy(8)), 4);
+    assert_eq!(ADAPTIVE.samples_for(&noisy(40)), 4);
+
+    // Up to the maximum
+    assert_eq!(ADAPTIVE.samples_for(&noisy(62)), 2);
+    assert_eq!(ADAPTIVE.samples_for(&noisy(64)), 0);
+    assert_eq!(ADAPTIVE.samples_for(&noisy(80)), 0);
+}
+
+#[test]
+fn adaptive_arguments() {
+    let parse = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string())).map(|args| args.adaptive_sampling);
+
+    assert_eq!(parse(&[]), Ok(None));
+
+    let adaptive = parse(&["--adaptive", "0.02"]).unwrap().unwrap();
+    assert_eq!(adaptive.threshold, 0.02);
+    assert!(adaptive.min_spp <= adaptive.max_spp);
+
+    let adaptive = parse(&["--max-spp", "512", "--adaptive", "0.1"]).unwrap().unwrap();
+    assert_eq!((adaptive.threshold, adaptive.max_spp), (0.1, 512));
+
+    // The minimum never goes over the maximum
+    let adaptive = parse(&["--adaptive", "0.1", "--max-spp", "2"]).unwrap().unwrap();
+    assert_eq!((adaptive.min_spp, adaptive.max_spp), (2, 2));
+
+    assert!(parse(&["--max-spp", "64"]).is_err());
+    assert!(parse(&["--adaptive"]).is_err());
+    assert!(parse(&["--adaptive", "-1"]).is_err());
+    assert!(parse(&["--adaptive", "noisy"]).is_err());
+    assert!(parse(&["--adaptive", "0.1", "--max-spp", "0"]).is_err());
+}
@@ -0,0 +1,186 @@
+use crate::vec::*;
+use crate::image::*;
+
+use std::f32::consts::PI;
+
+
+// LDR-FLIP (Andersson et al. 2020), with its default viewing conditions of
+// a 0.7m wide 4K monitor seen from 0.7m away
+pub const PIXELS_PER_DEGREE: f32 = 67.0;
+
+// Exponents and compression parameters of the color and feature errors
+const COLOR_EXPONENT: f32 = 0.7;
+const FEATURE_EXPONENT: f32 = 0.5;
+const COMPRESSION_POINT: f32 = 0.4;
+const COMPRESSION_TARGET: f32 = 0.95;
+
+// Width, in degrees, of the edges and points the feature detection looks for
+const FEATURE_WIDTH: f32 = 0.082;
+
+// Contrast sensitivity functions as sums of Gaussians (a1, b1, a2, b2), for the Y, Cx and Cz channels
+const CSF_PARAMS: [(f32, f32, f32, f32); 3] = [
+    (1.0, 0.0047, 0.0, 1.0e-5),
+    (1.0, 0.0053, 0.0, 1.0e-5),
+    (34.1, 0.04, 13.5, 0.025),
+];
+
+
+// Per pixel FLIP error in [0, 1], of test against reference. Both images are linear and seen as an LDR display would, clamped to [0, 1]
+pub fn flip(test: &Image, reference: &Image, pixels_per_degree: f32) -> Image<f32> {
+    assert!(test.width() == reference.width() && test.height() == reference.height());
+
+    let (width, height) = (test.width(), test.height());
+    let test = to_ycxcz(test);
+    let reference = to_ycxcz(reference);
+
+    let test_lab = filtered_lab(&test, pixels_per_degree);
+    let reference_lab = filtered_lab(&reference, pixels_per_degree);
+
+    let test_features = features(&test[0], pixels_per_degree);
+    let reference_features = features(&reference[0], pixels_per_degree);
+
+    let max_error = hyab(hunt(linear_to_lab(Vec3::new(0.0, 1.0, 0.0))), hunt(linear_to_lab(Vec3::new(0.0, 0.0, 1.0)))).powf(COLOR_EXPONENT);
+
+    let pixels = (0..test_lab.len()).map(|i| {
+        let color_error = compress_color_error(hyab(test_lab[i], reference_lab[i]).powf(COLOR_EXPONENT), max_error);
+
+        let edge_diff = (test_features[i].0 - reference_features[i].0).abs();
+        let point_diff = (test_features[i].1 - reference_features[i].1).abs();
+        let feature_error = (edge_diff.max(point_diff) / 2.0f32.sqrt()).powf(FEATURE_EXPONENT);
+
+        color_error.powf(1.0 - feature_error)
+    }).collect();
+
+    Image::new(width, height, pixels)
+}
+
+// Large color differences are compressed so that they don't hide the smaller ones
+fn compress_color_error(error: f32, max_error: f32) -> f32 {
+    let point = COMPRESSION_POINT * max_error;
+    if error < point {
+        error * COMPRESSION_TARGET / point
+    } else {
+        COMPRESSION_TARGET + (error - point) / (max_error - point) * (1.0 - COMPRESSION_TARGET)
+    }
+}
+
+
+// Spatially filters the image like the eye would, and converts the result to the Hunt adjusted L*a*b* space
+fn filtered_lab(ycxcz: &[Image<f32>; 3], pixels_per_degree: f32) -> Vec<Vec3> {
+    let filtered = [0, 1, 2].map(|c| csf_filter(&ycxcz[c], CSF_PARAMS[c], pixels_per_degree));
+
+    (0..filtered[0].pixel_count()).map(|i| {
+        let ycxcz = Vec3::new(filtered[0].pixels()[i], filtered[1].pixels()[i], filtered[2].pixels()[i]);
+        let rgb = xyz_to_linear(ycxcz_to_xyz(ycxcz)).max(Vec3::from(0.0)).min(Vec3::from(1.0));
+        hunt(linear_to_lab(rgb))
+    }).collect()
+}
+
+fn csf_filter(channel: &Image<f32>, (a1, b1, a2, b2): (f32, f32, f32, f32), pixels_per_degree: f32) -> Image<f32> {
+    let radius = (3.0 * (b1.max(b2) / (2.0 * PI * PI)).sqrt() * pixels_per_degree).ceil() as i32;
+    let kernel = |b: f32| (-radius..(radius + 1)).map(|x| {
+        let d = x as f32 / pixels_per_degree;
+        (-PI * PI * d * d / b).exp()
+    }).collect::<Vec<_>>();
+
+    // Both Gaussians are separable, so they are applied one after the other and summed, normalized so the whole filter sums to 1
+    let terms = [(a1, kernel(b1), b1), (a2, kernel(b2), b2)];
+    let total = terms.iter().map(|(a, k, b)| a * (PI / b).sqrt() * k.iter().sum::<f32>().powi(2)).sum::<f32>();
+
+    let mut pixels = vec![0.0; channel.pixel_count()];
+    for (a, k, b) in terms.iter().filter(|(a, _, _)| *a > 0.0) {
+        let scale = a * (PI / b).sqrt() / total;
+        let filtered = channel.convolve_separable(k, k);
+        pixels.iter_mut().zip(filtered.pixels()).for_each(|(p, f)| *p += f * scale);
+    }
+
+    Image::new(channel.width(), channel.height(), pixels)
+}
+
+
+// Edge and point strength of every pixel, from the first and second derivatives of a Gaussian over the normalized luminance
+fn features(y: &Image<f32>, pixels_per_degree: f32) -> Vec<(f32, f32)> {
+    let luminance = y.map(|y| (y + 16.0) / 116.0);
+
+    let sigma = 0.5 * FEATURE_WIDTH * pixels_per_degree;
+    let radius = (3.0 * sigma).ceil() as i32;
+    let xs = (-radius..(radius + 1)).map(|x| x as f32).collect::<Vec<_>>();
+
+    let gaussian = xs.iter().map(|x| (-x * x / (2.0 * sigma * sigma)).exp()).collect::<Vec<_>>();
+    let edge = xs.iter().zip(&gaussian).map(|(x, g)| -x * g).collect::<Vec<_>>();
+    let point = xs.iter().zip(&gaussian).map(|(x, g)| (x * x / (sigma * sigma) - 1.0) * g).collect::<Vec<_>>();
+
+    let (gaussian, edge, point) = (normalize_kernel(&gaussian), normalize_kernel(&edge), normalize_kernel(&point));
+
+    let magnitude = |kernel: &[f32]| {
+        let dx = luminance.convolve_separable(kernel, &gaussian);
+        let dy = luminance.convolve_separable(&gaussian, kernel);
+        dx.pixels().iter().zip(dy.pixels()).map(|(x, y)| (x * x + y * y).sqrt()).collect::<Vec<_>>()
+    };
+
+    magnitude(&edge).into_iter().zip(magnitude(&point)).collect()
+}
+
+// Scales positive weights to sum to 1 and negative weights to sum to -1
+fn normalize_kernel(kernel: &[f32]) -> Vec<f32> {
+    let positive = kernel.iter().filter(|k| **k > 0.0).sum::<f32>();
+    let negative = -kernel.iter().filter(|k| **k < 0.0).sum::<f32>();
+    kernel.iter().map(|k| if *k > 0.0 { k / positive } else if *k < 0.0 { k / negative } else { 0.0 }).collect()
+}
+
+
+// Hybrid distance, Manhattan on lightness and Euclidean on chroma
+fn hyab(a: Vec3, b: Vec3) -> f32 {
+    let diff = a - b;
+    diff.x.abs() + (diff.y * diff.y + diff.z * diff.z).sqrt()
+}
+
+// Chroma becomes less noticeable as lightness decreases
+fn hunt(lab: Vec3) -> Vec3 {
+    Vec3::new(lab.x, 0.01 * lab.x * lab.y, 0.01 * lab.x * lab.z)
+}
+
+fn to_ycxcz(image: &Image) -> [Image<f32>; 3] {
+    let ycxcz = image.map(|c| xyz_to_ycxcz(linear_to_xyz(Vec3::from(c).min(Vec3::from(1.0)))));
+    [ycxcz.map(|v| v.x), ycxcz.map(|v| v.y), ycxcz.map(|v| v.z)]
+}
+
+
+fn white_point() -> Vec3 {
+    linear_to_xyz(Vec3::from(1.0))
+}
+
+fn linear_to_xyz(rgb: Vec3) -> Vec3 {
+    Vec3::new(
+        0.4124564 * rgb.x + 0.3575761 * rgb.y + 0.1804375 * rgb.z,
+        0.2126729 * rgb.x + 0.7151522 * rgb.y + 0.072175 * rgb.z,
+        0.0193339 * rgb.x + 0.119192 * rgb.y + 0.9503041 * rgb.z,
+    )
+}
+
+fn xyz_to_linear(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.969266 * xyz.x + 1.8760108 * xyz.y + 0.041556 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+    )
+}
+
+fn xyz_to_ycxcz(xyz: Vec3) -> Vec3 {
+    let xyz = xyz / white_point();
+    Vec3::new(116.0 * xyz.y - 16.0, 500.0 * (xyz.x - xyz.y), 200.0 * (xyz.y - xyz.z))
+}
+
+fn ycxcz_to_xyz(ycxcz: Vec3) -> Vec3 {
+    let y = (ycxcz.x + 16.0) / 116.0;
+    Vec3::new(ycxcz.y / 500.0 + y, y, y - ycxcz.z / 200.0) * white_point()
+}
+
+fn linear_to_lab(rgb: Vec3) -> Vec3 {
+    let delta = 6.0 / 29.0;
+    let f = |t: f32| if t > delta * delta * delta { t.cbrt() } else { t / (3.0 * delta * delta) + 4.0 / 29.0 };
+
+    let xyz = linear_to_xyz(rgb) / white_point();
+    let (fx, fy, fz) = (f(xyz.x), f(xyz.y), f(xyz.z));
+    Vec3::new(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
@@ -2,14 +2,27 @@ use crate::vec::*;
 use crate::ray::*;
 use crate::scene::*;
 use crate::material::*;
+use crate::color::*;
 
 #[derive(Clone, Copy)]
 pub struct HitRecord<'hit> {
     pub dist: f32,
     pub pos: Vec3,
-    pub norm: Vec3,
+    // Conservative bound on the absolute floating point error of pos, per axis
+    pub pos_error: Vec3,
+    // Interpolated from the vertices, used to shade
+    pub shading_norm: Vec3,
+    // Normal of the triangle's plane, used to tell both sides of the surface apart
+    pub geom_norm: Vec3,
+    pub front_face: bool,
+    // Interpolated vertex color, for meshes that have them
+    pub vertex_color: Option<Color>,
+    // Interpolated texture coordinates, for meshes that have them
+    pub uv: Option<[f32; 2]>,
     pub ray: Ray,
     pub obj: Option<&'hit SceneObject>,
+    // Index of obj in the scene, only known when the hit comes from tracing the scene
+    pub obj_index: Option<u32>,
 }
 
 
@@ -22,8 +35,16 @@ pub trait Hittable {
 
 
 impl<'hit> HitRecord<'hit> {
-    pub fn material(&self) -> Option<&'hit Material> {
-        self.obj.map(|o| o.material())
+    // The object's material, tinted by the vertex color
+    pub fn material(&self) -> Option<Material> {
+        self.obj.map(|o| match self.vertex_color {
+            Some(color) => Material { color: o.material().color * color, ..*o.material() },
+            None => *o.material(),
+        })
+    }
+
+    pub fn spawn_ray(&self, dir: Vec3) -> Ray {
+        Ray::spawn(self.pos, self.pos_error, self.geom_norm, dir)
     }
 }
 
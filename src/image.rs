@@ -1,16 +1,22 @@
 use crate::color::*;
+use crate::serialize::*;
+
+use std::path::Path;
+use std::io::{BufReader, BufWriter, Write};
+use std::fs::{self, File};
+use std::io;
 
 
 #[derive(Clone)]
-pub struct Image {
+pub struct Image<T = Color> {
     width: u32,
     height: u32,
-    pixels: Vec<Color>,
+    pixels: Vec<T>,
 }
 
 
-impl Image {
-    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Image {
+impl<T: Copy> Image<T> {
+    pub fn new(width: u32, height: u32, pixels: Vec<T>) -> Image<T> {
         assert!((width as usize) * (height as usize) == pixels.len());
 
         Image {
@@ -32,7 +38,7 @@ impl Image {
         self.height
     }
 
-    pub fn pixels(&self) -> &[Color] {
+    pub fn pixels(&self) -> &[T] {
         self.pixels.as_slice()
     }
 
@@ -40,15 +46,213 @@ impl Image {
         (y * self.width + x) as usize
     }
 
-    pub fn pixel_at(&self, x: u32, y: u32) -> Color {
+    pub fn pixel_at(&self, x: u32, y: u32) -> T {
         debug_assert!(x < self.width);
         debug_assert!(y < self.height);
         self.pixels[self.pixel_index(x, y)]
     }
 
-    pub fn pixel_at_clamped(&self, x: i32, y: i32) -> Color {
+    pub fn pixel_at_clamped(&self, x: i32, y: i32) -> T {
         let x = x.clamp(0, (self.width() - 1) as i32)as u32;
         let y = y.clamp(0, (self.height() - 1) as i32)as u32;
         self.pixel_at(x, y)
     }
-}
\ No newline at end of file
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width as i32 && y < self.height as i32
+    }
+
+    pub fn map<U: Copy, F: Fn(T) -> U>(&self, f: F) -> Image<U> {
+        Image::new(self.width, self.height, self.pixels.iter().map(|p| f(*p)).collect())
+    }
+}
+
+impl Image<f32> {
+    // Filters the image with a separable kernel, edges are extended
+    pub fn convolve_separable(&self, horizontal: &[f32], vertical: &[f32]) -> Image<f32> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let (h_radius, v_radius) = ((horizontal.len() / 2) as i32, (vertical.len() / 2) as i32);
+
+        let mut rows = Vec::with_capacity(self.pixel_count());
+        for y in 0..height {
+            for x in 0..width {
+                rows.push(horizontal.iter().enumerate().map(|(i, k)| k * self.pixel_at_clamped(x + i as i32 - h_radius, y)).sum::<f32>());
+            }
+        }
+        let rows = Image::new(self.width, self.height, rows);
+
+        let mut pixels = Vec::with_capacity(self.pixel_count());
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(vertical.iter().enumerate().map(|(i, k)| k * rows.pixel_at_clamped(x, y + i as i32 - v_radius)).sum::<f32>());
+            }
+        }
+
+        Image::new(self.width, self.height, pixels)
+    }
+}
+
+impl Image {
+    // Picks the format from the extension: PFM and EXR are linear, PNG is decoded from sRGB
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let path = path.as_ref();
+        match extension(path).as_str() {
+            "pfm" => Image::load_pfm(path),
+            "exr" => Image::load_exr(path),
+            "png" => Image::load_png(path),
+            _ => Err(unsupported_format(path)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        match extension(path).as_str() {
+            "pfm" => self.save_pfm(path),
+            "exr" => self.save_exr(path),
+            "png" => self.save_png(path),
+            _ => Err(unsupported_format(path)),
+        }
+    }
+
+    pub fn load_pfm<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let data = fs::read(path)?;
+
+        // The header is made of 4 whitespace separated tokens, followed by a single whitespace
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while tokens.len() < 4 {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid_data("truncated PFM header"));
+            }
+            tokens.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+        }
+        pos += 1;
+
+        let channels = match tokens[0].as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid_data("not a PFM file")),
+        };
+        let parse_size = |token: &str| token.parse::<u32>().map_err(|_| invalid_data("invalid PFM size"));
+        let (width, height) = (parse_size(&tokens[1])?, parse_size(&tokens[2])?);
+        let little_endian = tokens[3].parse::<f32>().map_err(|_| invalid_data("invalid PFM scale"))? < 0.0;
+
+        let values = data.get(pos..).unwrap_or(&[]).chunks_exact(4).map(|b| {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            if little_endian { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) }
+        }).collect::<Vec<_>>();
+
+        if values.len() < (width as usize) * (height as usize) * channels {
+            return Err(invalid_data("truncated PFM data"));
+        }
+
+        // PFM stores rows from bottom to top
+        let mut pixels = Vec::with_capacity((width as usize) * (height as usize));
+        for y in (0..height as usize).rev() {
+            for x in 0..width as usize {
+                let index = (y * width as usize + x) * channels;
+                let rgb = if channels == 3 { [values[index], values[index + 1], values[index + 2]] } else { [values[index]; 3] };
+                pixels.push(Color::new(rgb[0].max(0.0), rgb[1].max(0.0), rgb[2].max(0.0)));
+            }
+        }
+
+        Ok(Image::new(width, height, pixels))
+    }
+
+    pub fn load_exr<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| (resolution.width(), vec![Color::from(0.0); resolution.area()]),
+            |(width, pixels): &mut (usize, Vec<Color>), position, (r, g, b, _): (f32, f32, f32, f32)| {
+                pixels[position.y() * *width + position.x()] = Color::new(r.max(0.0), g.max(0.0), b.max(0.0));
+            },
+        ).map_err(exr_error)?;
+
+        let size = image.layer_data.size;
+        Ok(Image::new(size.width() as u32, size.height() as u32, image.layer_data.channel_data.pixels.1))
+    }
+
+    pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info().map_err(invalid_data)?;
+        let mut data = vec![0; reader.output_buffer_size().ok_or_else(|| invalid_data("PNG is too large"))?];
+        let info = reader.next_frame(&mut data).map_err(invalid_data)?;
+
+        let samples = info.color_type.samples();
+        let mut pixels = Vec::with_capacity((info.width as usize) * (info.height as usize));
+        for row in data.chunks_exact(info.line_size).take(info.height as usize) {
+            for p in row.chunks_exact(samples).take(info.width as usize) {
+                // Gray images have one or two samples, alpha is ignored
+                let srgb = if samples < 3 { SRgbColor { r: p[0], g: p[0], b: p[0] } } else { SRgbColor { r: p[0], g: p[1], b: p[2] } };
+                pixels.push(srgb.to_linear());
+            }
+        }
+
+        Ok(Image::new(info.width, info.height, pixels))
+    }
+
+    pub fn save_exr<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        exr::prelude::write_rgb_file(path, self.width as usize, self.height as usize, |x, y| {
+            let color = self.pixel_at(x as u32, y as u32);
+            (color.r, color.g, color.b)
+        }).map_err(exr_error)
+    }
+
+    // Values are clamped to [0, 1] and sRGB encoded, tonemapping is left to the caller
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data = self.pixels.iter().flat_map(|c| {
+            let srgb = c.to_srgb();
+            [srgb.r, srgb.g, srgb.b]
+        }).collect::<Vec<_>>();
+
+        let mut writer = encoder.write_header().map_err(invalid_data)?;
+        writer.write_image_data(&data).map_err(invalid_data)?;
+        writer.finish().map_err(invalid_data)
+    }
+
+    // Writes the image as little endian PFM, which keeps the full float range
+    pub fn save_pfm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut data = Vec::with_capacity(self.pixel_count() * 12 + 32);
+        write!(data, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+
+        // PFM stores rows from bottom to top
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let color = self.pixel_at(x, y);
+                for c in [color.r, color.g, color.b] {
+                    data.extend_from_slice(&c.to_le_bytes());
+                }
+            }
+        }
+
+        File::create(path)?.write_all(&data)
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_ascii_lowercase()
+}
+
+fn unsupported_format(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported image format \"{}\"", path.display()))
+}
+
+fn exr_error(err: exr::error::Error) -> io::Error {
+    match err {
+        exr::error::Error::Io(err) => err,
+        err => invalid_data(err),
+    }
+}
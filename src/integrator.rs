@@ -1,10 +1,12 @@
 
+use crate::vec::*;
 use crate::ray::*;
 use crate::hit::*;
 use crate::scene::*;
 use crate::camera::*;
 use crate::color::*;
 use crate::surface::*;
+use crate::aov::*;
 
 use rand::prelude::*;
 
@@ -13,6 +15,33 @@ use rand::prelude::*;
 pub struct Integrator {
 }
 
+// Which light paths are traced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegratorKind {
+    Path,
+    // Emission and next event estimation at the first hit only
+    Direct,
+}
+
+
+impl IntegratorKind {
+    pub fn parse(name: &str) -> Option<IntegratorKind> {
+        match name.to_ascii_lowercase().as_str() {
+            "path" => Some(IntegratorKind::Path),
+            "direct" => Some(IntegratorKind::Direct),
+            _ => None,
+        }
+    }
+
+    // The max_rays given to trace
+    pub fn max_rays(&self, max_bounces: usize) -> usize {
+        match self {
+            IntegratorKind::Path => max_bounces,
+            IntegratorKind::Direct => 1,
+        }
+    }
+}
+
 
 impl Integrator {
 	pub fn generate_ray<R: RngCore>(camera: &Camera, x: u32, y: u32, width: u32, height: u32, rng: &mut R) -> Ray {
@@ -37,28 +66,68 @@ impl Integrator {
 
         match scene.hit(ray) {
             Some(hit) => {
-                let mut acc = Color::from(0.0);
+                let (direct, indirect) = Self::shade(scene, &hit, no_hit, rng, max_rays);
+                direct + indirect
+            },
 
-                // Light contrib
-                {
-                    acc += Self::light_contrib(scene, &hit, rng);
-                }
+            None => no_hit(ray),
+        }
+    }
 
-                // Material contrib
-                if let Some(mat) = hit.material() {
-                    acc += mat.emissive;
+    // Same as trace, but also returns the first hit data. Consumes the rng exactly like trace so both give the same image
+    pub fn trace_with_aovs<R: RngCore, F: Fn(Ray) -> Color>(scene: &Scene, ray: Ray, no_hit: &F, rng: &mut R, max_rays: usize) -> (Color, Aovs) {
+        if max_rays == 0 {
+            return (Color::from(0.0), Aovs::background(Color::from(0.0)));
+        }
 
-                    let sample = mat.sample(ray.dir, hit.norm, rng);
-                    if !sample.color.is_zero() {
-                        acc += sample.color * Self::trace(scene, Ray::new_with_epsilon(hit.pos, sample.reflected_dir), no_hit, rng, max_rays - 1);
-                    }
-                }
+        match scene.hit(ray) {
+            Some(hit) => {
+                let (direct, indirect) = Self::shade(scene, &hit, no_hit, rng, max_rays);
+
+                let aovs = Aovs {
+                    albedo: hit.material().map(|mat| mat.color).unwrap_or(Color::from(0.0)),
+                    normal: hit.shading_norm,
+                    depth: hit.dist,
+                    position: hit.pos,
+                    object_id: hit.obj_index.map(|i| i + 1).unwrap_or(0),
+                    material_id: hit.obj_index.map(|i| scene.material_id(i) + 1).unwrap_or(0),
+                    direct: direct,
+                    indirect: indirect,
+                };
+
+                (direct + indirect, aovs)
+            },
 
-                acc
+            None => {
+                let color = no_hit(ray);
+                (color, Aovs::background(color))
             },
+        }
+    }
 
-            None => no_hit(ray),
+    // Returns the direct (emitted and next event estimation) and indirect contributions of a hit
+    fn shade<R: RngCore, F: Fn(Ray) -> Color>(scene: &Scene, hit: &HitRecord, no_hit: &F, rng: &mut R, max_rays: usize) -> (Color, Color) {
+        let mut direct = Color::from(0.0);
+        let mut indirect = Color::from(0.0);
+
+        // Light contrib
+        {
+            direct += Self::light_contrib(scene, hit, rng);
+        }
+
+        // Material contrib
+        if let Some(mat) = hit.material() {
+            direct += mat.emission(hit.front_face);
+
+            if let Some(sample) = mat.sample(-hit.ray.dir, hit.shading_norm, rng) {
+                let weight = sample.color * Self::normal_correction(hit, sample.dir);
+                if !weight.is_zero() {
+                    indirect += weight * Self::trace(scene, hit.spawn_ray(sample.dir), no_hit, rng, max_rays - 1);
+                }
+            }
         }
+
+        (direct, indirect)
     }
 
     fn light_contrib<R: RngCore>(scene: &Scene, hit: &HitRecord, rng: &mut R) -> Color {
@@ -71,14 +140,14 @@ impl Integrator {
                     return Color::from(0.0);
                 }*/
 
-                let refl = mat.eval(hit.norm, shadow_ray_dir, -hit.ray.dir);
+                let refl = mat.eval(hit.shading_norm, -hit.ray.dir, shadow_ray_dir) * Self::normal_correction(hit, shadow_ray_dir);
                 if refl.is_zero() {
                     return Color::from(0.0);
                 }
 
-                if let Some(shadow_hit) = scene.hit(Ray::new_with_epsilon(hit.pos, shadow_ray_dir)) {
+                if let Some(shadow_hit) = scene.hit(hit.spawn_ray(shadow_ray_dir)) {
                     if let Some(occluder) = shadow_hit.obj {
-                        if std::ptr::eq(occluder, emitter) {
+                        if std::ptr::eq(occluder, emitter) && emitter.material().emits_from(shadow_hit.front_face) {
                             return refl * radiance;
                         }
                     }
@@ -88,5 +157,24 @@ impl Integrator {
 
         Color::from(0.0)
     }
-}
 
+    // Factor applied to the BSDF of a hit for light going out (or coming in) along dir, to account for the shading normal not being the geometric one.
+    // Directions on different sides of the surface for both normals would leak light through it, so they are dropped.
+    // Otherwise, the shadowing term of Chiang et al. 2019 smoothly darkens the terminator of low poly smooth meshes instead of cutting it off
+    pub fn normal_correction(hit: &HitRecord, dir: Vec3) -> f32 {
+        let view_dir = -hit.ray.dir;
+        let geom_side = dir.dot(hit.geom_norm) * view_dir.dot(hit.geom_norm) > 0.0;
+        let shading_side = dir.dot(hit.shading_norm) * view_dir.dot(hit.shading_norm) > 0.0;
+        if geom_side != shading_side {
+            return 0.0;
+        }
+
+        let cos_shading = dir.dot(hit.shading_norm).abs() * hit.shading_norm.dot(hit.geom_norm).abs();
+        if cos_shading <= 0.0 {
+            return 1.0;
+        }
+
+        let g = (dir.dot(hit.geom_norm).abs() / cos_shading).min(1.0);
+        -g * g * g + g * g + g
+    }
+}
@@ -0,0 +1,73 @@
+// Checks the shading normal correction: neutral for flat shading, always in [0, 1], and vanishing where the geometric normal is grazing.
+
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::integrator::*;
+use crate::utils::*;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+
+// Hit at the origin of a ray coming from view_dir
+fn hit(view_dir: Vec3, geom_norm: Vec3, shading_norm: Vec3) -> HitRecord<'static> {
+    HitRecord {
+        dist: 1.0,
+        pos: Vec3::zero(),
+        pos_error: Vec3::zero(),
+        shading_norm: shading_norm.normalized(),
+        geom_norm: geom_norm.normalized(),
+        front_face: true,
+        vertex_color: None,
+        uv: None,
+        ray: Ray::new(view_dir, -view_dir),
+        obj: None,
+        obj_index: None,
+    }
+}
+
+
+#[test]
+fn flat_shading() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..1000 {
+        let norm = random_unit_vector(&mut rng);
+        let view_dir = random_in_hemisphere(norm, &mut rng);
+        let dir = random_unit_vector(&mut rng);
+        let correction = Integrator::normal_correction(&hit(view_dir, norm, norm), dir);
+        assert!((correction - 1.0).abs() < 1e-5, "{} for {:?}", correction, dir);
+    }
+}
+
+#[test]
+fn bounded() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for _ in 0..10000 {
+        let geom_norm = random_unit_vector(&mut rng);
+        let shading_norm = (geom_norm + random_unit_vector(&mut rng) * rng.gen::<f32>()).normalized();
+        let view_dir = random_in_hemisphere(geom_norm, &mut rng);
+        let dir = random_unit_vector(&mut rng);
+        let correction = Integrator::normal_correction(&hit(view_dir, geom_norm, shading_norm), dir);
+        assert!((0.0..=1.0).contains(&correction), "{} for {:?}", correction, dir);
+    }
+}
+
+#[test]
+fn grazing_geometric_normal() {
+    let (up, view_dir) = (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0));
+    // Tilted towards the light, so that it isn't grazing for the shading normal
+    let hit = hit(view_dir, up, Vec3::new(1.0, 0.0, 1.0));
+
+    let correction = |height: f32| Integrator::normal_correction(&hit, Vec3::new(1.0, 0.0, height).normalized());
+    let mut previous = correction(1.0);
+    for height in [0.5, 0.1, 0.01, 0.001] {
+        assert!(correction(height) < previous, "{} at {}", correction(height), height);
+        previous = correction(height);
+    }
+    assert!(correction(0.001) < 0.01);
+    assert_eq!(correction(0.0), 0.0);
+
+    // Under the geometric surface, light would leak through it
+    assert_eq!(correction(-0.1), 0.0);
+}
@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+#![allow(clippy::redundant_field_names)]
 
 
 extern crate gltf;
@@ -7,12 +8,16 @@ extern crate rayon;
 extern crate show_image;
 
 
-use std::time::Instant;
-use std::path::Path;
+use std::time::{Instant, Duration};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
 
 
 use show_image::{ImageView, ImageInfo, WindowOptions, create_window, event};
 use rayon::prelude::*;
+use rand::prelude::*;
+use rand::rngs::StdRng;
 
 
 mod vec;
@@ -32,6 +37,64 @@ mod surface;
 mod utils;
 mod denoise;
 mod image;
+mod atrous;
+mod tile;
+mod progress;
+mod film;
+mod serialize;
+mod checkpoint;
+mod aov;
+mod flip;
+mod compare;
+mod normals;
+mod obj;
+mod ply;
+mod scene_file;
+mod environment;
+mod pbrt;
+mod mitsuba;
+mod scene_cache;
+
+#[cfg(test)]
+mod regression;
+#[cfg(test)]
+mod material_tests;
+#[cfg(test)]
+mod test_files;
+#[cfg(test)]
+mod scene_tests;
+#[cfg(test)]
+mod obj_tests;
+#[cfg(test)]
+mod ply_tests;
+#[cfg(test)]
+mod scene_file_tests;
+#[cfg(test)]
+mod pbrt_tests;
+#[cfg(test)]
+mod mitsuba_tests;
+#[cfg(test)]
+mod scene_cache_tests;
+#[cfg(test)]
+mod denoise_tests;
+#[cfg(test)]
+mod compare_tests;
+#[cfg(test)]
+mod normals_tests;
+#[cfg(test)]
+mod checkpoint_tests;
+#[cfg(test)]
+mod tile_tests;
+#[cfg(test)]
+mod film_tests;
+#[cfg(test)]
+mod atrous_tests;
+#[cfg(test)]
+mod ray_tests;
+#[cfg(test)]
+mod mesh_tests;
+#[cfg(test)]
+mod integrator_tests;
 
 
 use crate::scene::*;
@@ -39,73 +102,488 @@ use crate::ray::*;
 use crate::color::*;
 use crate::integrator::*;
 use crate::image::*;
+use crate::tile::*;
+use crate::progress::*;
+use crate::film::*;
+use crate::checkpoint::*;
+use crate::aov::*;
+use crate::denoise::*;
+use crate::atrous::*;
+use crate::compare::*;
+use crate::normals::*;
+use crate::scene_cache::*;
+use crate::utils::*;
 
 
 
 const SPP: usize = 16;
+const ADAPTIVE_MIN_SPP: usize = 8;
+const ADAPTIVE_MAX_SPP: usize = 256;
+const SAMPLE_COUNT_FILE: &str = "sample_count.pfm";
+const PASS_SPP: usize = 4;
 const MAX_BOUNCES: usize = 4;
 const SCENE_FILE: &str = "assets/cornel.gltf";
 
+const HEIGHT: u32 = 768;
+const TILE_SIZE: u32 = 32;
+const TILE_ORDER: TileOrder = TileOrder::Spiral;
+
+const CHECKPOINT_FILE: &str = "render.checkpoint";
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
 const EXPOSURE: f32 = 0.25;
 
-fn load() -> Scene {
+// Reported by trace once the render is done
+struct RenderStats {
+    passes: usize,
+    elapsed: Duration,
+    average_spp: f64,
+    max_spp: usize,
+}
+
+enum ViewerUpdate {
+    Tile(Tile, Vec<Color>),
+    Frame(Image),
+}
+
+struct Args {
+    resume: bool,
+    // Checkpoints are only written when a file is given
+    checkpoint_file: Option<PathBuf>,
+    aov_file: Option<PathBuf>,
+    denoise: bool,
+    denoise_preview: bool,
+    smooth_normals: bool,
+    scene_file: PathBuf,
+    scene_cache: bool,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    time_budget: Option<Duration>,
+    // Stops the render after that many passes, so that it can be resumed from its checkpoint later
+    max_passes: Option<usize>,
+}
+
+impl Args {
+    // The denoiser is guided by the AOVs, so they are rendered even if they aren't saved
+    fn needs_aovs(&self) -> bool {
+        self.aov_file.is_some() || self.denoise || self.denoise_preview
+    }
+
+    fn normal_generation(&self) -> NormalGeneration {
+        if self.smooth_normals { NormalGeneration::Smooth } else { NormalGeneration::Flat }
+    }
+}
+
+struct CompareArgs {
+    test: PathBuf,
+    reference: PathBuf,
+    diff_file: Option<PathBuf>,
+    metric: Metric,
+    threshold: Option<f32>,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut it: I) -> Result<Args, String> {
+    let mut args = Args {
+        resume: false,
+        checkpoint_file: None,
+        aov_file: None,
+        denoise: false,
+        denoise_preview: false,
+        smooth_normals: false,
+        scene_file: PathBuf::from(SCENE_FILE),
+        scene_cache: true,
+        adaptive_sampling: None,
+        time_budget: None,
+        max_passes: None,
+    };
+
+    let mut max_spp = None;
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--resume" => args.resume = true,
+            "--denoise" => args.denoise = true,
+            "--denoise-preview" => args.denoise_preview = true,
+            "--smooth-normals" => args.smooth_normals = true,
+            "--no-scene-cache" => args.scene_cache = false,
+            "--scene" => args.scene_file = PathBuf::from(it.next().ok_or("missing path after --scene")?),
+            "--checkpoint" => args.checkpoint_file = Some(PathBuf::from(it.next().ok_or("missing path after --checkpoint")?)),
+            "--aovs" => args.aov_file = Some(PathBuf::from(it.next().ok_or("missing path after --aovs")?)),
+            "--adaptive" => {
+                let value = it.next().ok_or("missing threshold after --adaptive")?;
+                let threshold = value.parse::<f32>().ok().filter(|t| *t > 0.0 && t.is_finite()).ok_or_else(|| format!("invalid threshold \"{}\"", value))?;
+                args.adaptive_sampling = Some(AdaptiveSampling {
+                    min_spp: ADAPTIVE_MIN_SPP,
+                    max_spp: ADAPTIVE_MAX_SPP,
+                    pass_spp: PASS_SPP,
+                    threshold: threshold,
+                });
+            },
+            "--time-budget" => {
+                let value = it.next().ok_or("missing seconds after --time-budget")?;
+                let secs = value.parse::<f64>().ok().filter(|s| *s > 0.0 && s.is_finite()).ok_or_else(|| format!("invalid time budget \"{}\"", value))?;
+                args.time_budget = Some(Duration::from_secs_f64(secs));
+            },
+            "--max-passes" => {
+                let value = it.next().ok_or("missing pass count after --max-passes")?;
+                args.max_passes = Some(value.parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(|| format!("invalid pass count \"{}\"", value))?);
+            },
+            "--max-spp" => {
+                let value = it.next().ok_or("missing sample count after --max-spp")?;
+                max_spp = Some(value.parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(|| format!("invalid sample count \"{}\"", value))?);
+            },
+            _ => return Err(format!("unknown argument \"{}\"", arg)),
+        }
+    }
+
+    // Pixels always get the minimum number of samples, so a lower maximum lowers both
+    match (&mut args.adaptive_sampling, max_spp) {
+        (Some(adaptive), Some(max_spp)) => {
+            adaptive.max_spp = max_spp;
+            adaptive.min_spp = adaptive.min_spp.min(max_spp);
+        },
+        (None, Some(_)) => return Err("--max-spp needs --adaptive".to_owned()),
+        _ => {},
+    }
+
+    // Resuming without a file uses the default one, and keeps it up to date
+    if args.resume && args.checkpoint_file.is_none() {
+        args.checkpoint_file = Some(PathBuf::from(CHECKPOINT_FILE));
+    }
+
+    Ok(args)
+}
+
+// compare <test> <reference> [--diff <path>] [--metric <name>] [--threshold <value>]
+fn parse_compare_args<I: Iterator<Item = String>>(mut it: I) -> Result<CompareArgs, String> {
+    let mut paths = Vec::new();
+    let mut args = CompareArgs {
+        test: PathBuf::new(),
+        reference: PathBuf::new(),
+        diff_file: None,
+        metric: Metric::Flip,
+        threshold: None,
+    };
+
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--diff" => args.diff_file = Some(PathBuf::from(it.next().ok_or("missing path after --diff")?)),
+            "--metric" => {
+                let name = it.next().ok_or("missing name after --metric")?;
+                args.metric = Metric::parse(&name).ok_or_else(|| format!("unknown metric \"{}\"", name))?;
+            },
+            "--threshold" => {
+                let value = it.next().ok_or("missing value after --threshold")?;
+                args.threshold = Some(value.parse().map_err(|_| format!("invalid threshold \"{}\"", value))?);
+            },
+            _ if arg.starts_with("--") => return Err(format!("unknown argument \"{}\"", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    match <[PathBuf; 2]>::try_from(paths) {
+        Ok([test, reference]) => Ok(CompareArgs { test: test, reference: reference, ..args }),
+        Err(_) => Err("compare expects a test and a reference image".to_owned()),
+    }
+}
+
+// Returns whether the images are close enough, so scripts can rely on the exit code
+fn compare_images(args: &CompareArgs) -> Result<bool, Box<dyn std::error::Error>> {
+    let load = |path: &Path| Image::load(path).map_err(|err| format!("unable to load {}: {}", path.display(), err));
+    let test = load(&args.test)?;
+    let reference = load(&args.reference)?;
+
+    if (test.width(), test.height()) != (reference.width(), reference.height()) {
+        return Err(format!("image sizes don't match ({}x{} and {}x{})", test.width(), test.height(), reference.width(), reference.height()).into());
+    }
+
+    let (metrics, errors) = compare(&test, &reference);
+    println!("{}", metrics);
+
+    if let Some(diff_file) = &args.diff_file {
+        false_color(&errors).save(diff_file)?;
+        println!("Difference written to {}", diff_file.display());
+    }
+
+    match args.threshold {
+        Some(threshold) if metrics.exceeds(args.metric, threshold) => {
+            println!("{} of {} is over the threshold of {}", args.metric.name(), metrics.get(args.metric), threshold);
+            Ok(false)
+        },
+        _ => Ok(true),
+    }
+}
+
+fn load(args: &Args) -> Result<Scene, SceneError> {
     let start = Instant::now();
 
-    let scene_path = Path::new(SCENE_FILE);
-    let scene = import_scene(scene_path).expect("unable to import scene");
+    let normals = args.normal_generation();
+
+    let cache_file = scene_cache_path(&args.scene_file);
+    if args.scene_cache {
+        match load_scene_cache(&cache_file, normals) {
+            Ok(Some(scene)) => {
+                println!("Loaded from {} in {:?}", cache_file.display(), (Instant::now() - start));
+                return Ok(scene);
+            },
+            Ok(None) => {},
+            Err(err) => eprintln!("Unable to read scene cache {}: {}", cache_file.display(), err),
+        }
+    }
+
+    let scene = import_scene(&args.scene_file, normals)?;
 
     println!("Loaded in {:?}", (Instant::now() - start));
 
-    scene
+    if args.scene_cache {
+        if let Err(err) = save_scene_cache(&cache_file, &scene, &SceneSource::new(scene.source_files(), normals)) {
+            eprintln!("Unable to write scene cache {}: {}", cache_file.display(), err);
+        }
+    }
+
+    Ok(scene)
+}
+
+// Scene files can set their resolution, other scenes get the camera's aspect ratio
+fn image_size(scene: &Scene) -> (u32, u32) {
+    if let Some(resolution) = scene.settings().resolution {
+        return resolution;
+    }
+    let height = HEIGHT;
+    let width = (height as f32 * scene.camera().ratio()) as u32;
+    (width, height)
+}
+
+fn spp(scene: &Scene) -> usize {
+    scene.settings().spp.unwrap_or(SPP)
+}
+
+fn max_rays(scene: &Scene) -> usize {
+    let settings = scene.settings();
+    settings.integrator.unwrap_or(IntegratorKind::Path).max_rays(settings.max_bounces.unwrap_or(MAX_BOUNCES))
+}
+
+fn render_source(scene: &Scene, args: &Args) -> RenderSource {
+    RenderSource {
+        scene: SceneSource::new(scene.source_files(), args.normal_generation()),
+        spp: spp(scene),
+        max_rays: max_rays(scene),
+        adaptive_sampling: args.adaptive_sampling,
+        time_budget: args.time_budget,
+    }
+}
+
+// Loads the checkpoint to resume, if asked to, refusing those of other renders
+fn resume_checkpoint(scene: &Scene, args: &Args) -> Result<Option<Checkpoint>, Box<dyn std::error::Error>> {
+    let checkpoint_file = match (&args.checkpoint_file, args.resume) {
+        (Some(checkpoint_file), true) => checkpoint_file,
+        _ => return Ok(None),
+    };
+
+    let (width, height) = image_size(scene);
+    let checkpoint = Checkpoint::load(checkpoint_file)?;
+    if (checkpoint.film.width(), checkpoint.film.height()) != (width, height) {
+        return Err(format!("checkpoint resolution doesn't match the render ({}x{})", width, height).into());
+    }
+    if args.needs_aovs() && !checkpoint.film.has_aovs() {
+        return Err("checkpoint was rendered without AOVs".into());
+    }
+    if let Some(mismatch) = checkpoint.source.mismatch(&render_source(scene, args)) {
+        return Err(format!("checkpoint was rendered {}", mismatch).into());
+    }
+    println!("Resuming from {} after {} passes", checkpoint_file.display(), checkpoint.passes);
+    Ok(Some(checkpoint))
+}
+
+// One generator per pixel and pass, seeded from the pixel and the number of samples it had before the pass.
+// The image doesn't depend on scheduling, and a pass traces the same samples whether or not the render was resumed
+fn pixel_rng(x: u32, y: u32, width: u32, sample_index: usize) -> StdRng {
+    let pixel_index = (y as u64) * (width as u64) + (x as u64);
+    StdRng::seed_from_u64(hash_u64((pixel_index << 32) | (sample_index as u64)))
 }
 
-fn trace(scene: &Scene) -> Image {
+fn trace<F: Fn(&Tile, &[Color]) + Sync, P: Fn(&Image)>(scene: &Scene, args: &Args, resume_from: Option<Checkpoint>, on_tile: F, on_pass: P) -> (Image, Image, Option<AovImages>, RenderStats) {
     let start = Instant::now();
 
     let camera = scene.camera();
 
-    let height = 768;
-    let width = (height as f32 * camera.ratio()) as u32;
+    let (width, height) = image_size(scene);
+    let spp = spp(scene);
+    let max_rays = max_rays(scene);
 
-    let image = Image::new(width, height, (0..width * height).into_par_iter().map(|i| {
-        let mut rng = rand::thread_rng();
+    let tiles = generate_tiles(width, height, TILE_SIZE, TILE_ORDER);
 
-        let no_hit = |ray: Ray| {
-            let v = (ray.dir.y + 1.0) * 0.5;
-            Color::new(0.5, 0.7, 1.0) * v + (1.0 - v)
+    let (mut film, first_pass, previous_elapsed) = match resume_from {
+        Some(checkpoint) => (checkpoint.film, checkpoint.passes, checkpoint.elapsed),
+        None if args.needs_aovs() => (Film::with_aovs(width, height), 0, Duration::ZERO),
+        None => (Film::new(width, height), 0, Duration::ZERO),
+    };
+
+    debug_assert!(film.width() == width && film.height() == height);
+
+    let elapsed = || previous_elapsed + (Instant::now() - start);
+
+    let samples_for = |stats: &PixelStats| match (args.adaptive_sampling, args.time_budget) {
+        (Some(adaptive), _) => adaptive.samples_for(stats),
+        (None, Some(_)) => PASS_SPP,
+        (None, None) => spp.saturating_sub(stats.sample_count()).min(PASS_SPP),
+    };
+
+    let source = render_source(scene, args);
+    let save_checkpoint = |film: Film, passes: usize| {
+        let path = match &args.checkpoint_file {
+            Some(path) => path,
+            None => return film,
         };
+        let checkpoint = Checkpoint {
+            film: film,
+            passes: passes,
+            elapsed: elapsed(),
+            source: source.clone(),
+        };
+        if let Err(err) = checkpoint.save(path) {
+            eprintln!("Unable to write checkpoint: {}", err);
+        }
+        checkpoint.film
+    };
+
+    let mut last_checkpoint = Instant::now();
+    let mut last_pass_duration = Duration::ZERO;
+    let mut passes = first_pass;
+    for pass in first_pass.. {
+        if let Some(max_passes) = args.max_passes {
+            if pass - first_pass >= max_passes {
+                println!("Stopped after {} passes", max_passes);
+                break;
+            }
+        }
 
+        // Don't start a pass that is expected to end after the deadline
+        if let Some(budget) = args.time_budget {
+            if pass > first_pass && elapsed() + last_pass_duration > budget {
+                println!("Time budget of {:?} reached after {} passes", budget, pass);
+                break;
+            }
+        }
 
-        let no_hit = |_: Ray| Color::from(0.0);
+        let pass_start = Instant::now();
 
-        let mut color = Color::from(0.0);
-        for _ in 0..SPP {
-            let ray = Integrator::generate_ray(&camera, i % width, i / width, width, height, &mut rng);
-            color = color + Integrator::trace(&scene, ray, &no_hit, &mut rng, MAX_BOUNCES);
+        let pass_samples = film.pixels().iter().map(&samples_for).sum::<usize>();
+        if pass_samples == 0 {
+            break;
         }
 
-        assert!(color.r >= 0.0 && color.g >= 0.0 && color.b >= 0.0);
+        let progress = Progress::new(format!("Pass {} ({} samples)", pass, pass_samples), pass_samples);
+
+        // par_bridge pulls tiles in order so they are started following TILE_ORDER
+        let traced = tiles.iter().par_bridge().map(|tile| {
+            let no_hit = |ray: Ray| scene.environment().radiance(ray.dir);
+
+            let mut data = film.tile(tile);
+            let mut tile_samples = 0;
+            for (i, (x, y)) in tile.pixels().enumerate() {
+                let stats = &mut data.pixels[i];
+                let samples = samples_for(stats);
+                let mut rng = pixel_rng(x, y, width, stats.sample_count());
+                for _ in 0..samples {
+                    let ray = Integrator::generate_ray(&camera, x, y, width, height, &mut rng);
+                    let color = match &mut data.aovs {
+                        Some(aovs) => {
+                            let (color, sample_aovs) = Integrator::trace_with_aovs(scene, ray, &no_hit, &mut rng, max_rays);
+                            aovs[i].add(&sample_aovs);
+                            color
+                        },
+                        None => Integrator::trace(scene, ray, &no_hit, &mut rng, max_rays),
+                    };
+
+                    assert!(color.r >= 0.0 && color.g >= 0.0 && color.b >= 0.0);
+
+                    stats.add(color);
+                }
+                tile_samples += samples;
+            }
+
+            let colors = data.pixels.iter().map(|p| p.mean()).collect::<Vec<_>>();
+            on_tile(tile, &colors);
+            progress.advance(tile_samples);
+
+            (*tile, data)
+        }).collect::<Vec<_>>();
 
-        tonemap(color / SPP as f32)
-    }).collect::<Vec<_>>());
+        progress.finish();
 
-    let duration = Instant::now() - start;
-    println!("Traced in {:?}", duration);
-    println!("{:.2} MS/s", ((width * height) as usize * SPP) as f64 / 1_000_000.0 / duration.as_secs_f64());
+        for (tile, data) in traced {
+            film.set_tile(&tile, data);
+        }
+
+        if args.denoise_preview {
+            if let Some(aovs) = film.aov_images() {
+                let preview = denoise_atrous(&film.resolve(|c| c), &film.variance_image(), &aovs);
+                on_pass(&preview);
+            }
+        }
 
-    image
+        last_pass_duration = Instant::now() - pass_start;
+        passes = pass + 1;
+
+        if Instant::now() - last_checkpoint >= CHECKPOINT_INTERVAL {
+            film = save_checkpoint(film, passes);
+            last_checkpoint = Instant::now();
+        }
+    }
+
+    // Saved once more at the end, so the checkpoint holds the finished render rather than the last periodic state
+    let film = save_checkpoint(film, passes);
+
+    // Linear radiance, tonemapping is left to the display stage so denoising works on the HDR values
+    let image = film.resolve(|c| c);
+    let variance = film.buffer_variance_image();
+
+    let total_samples = film.total_samples();
+    let stats = RenderStats {
+        passes: passes,
+        elapsed: elapsed(),
+        average_spp: total_samples as f64 / (width * height) as f64,
+        max_spp: film.max_samples(),
+    };
+    println!("Traced in {:?}", stats.elapsed);
+    println!("{:.2} spp average, {} spp max", stats.average_spp, stats.max_spp);
+    println!("{:.2} MS/s", total_samples as f64 / 1_000_000.0 / stats.elapsed.as_secs_f64());
+
+    if args.adaptive_sampling.is_some() {
+        match film.sample_count_image().save_pfm(SAMPLE_COUNT_FILE) {
+            Ok(_) => println!("Sample count map written to {}", SAMPLE_COUNT_FILE),
+            Err(err) => eprintln!("Unable to write sample count map: {}", err),
+        }
+    }
+
+    let aovs = film.aov_images();
+    if let (Some(aovs), Some(aov_file)) = (&aovs, &args.aov_file) {
+        match aovs.save_exr(aov_file, &image) {
+            Ok(_) => println!("AOVs written to {}", aov_file.display()),
+            Err(err) => eprintln!("Unable to write AOVs: {}", err),
+        }
+    }
+
+    (image, variance, aovs, stats)
 }
 
-fn scene_name() -> String {
-    let scene_path = Path::new(SCENE_FILE);
-    let scene_name = String::from(Path::new(scene_path.file_name().unwrap()).file_stem().unwrap().to_str().unwrap());
-    format!("{} ({}spp)", scene_name, SPP)
+fn scene_name(args: &Args, spp: usize) -> String {
+    let scene_name = args.scene_file.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    match (args.adaptive_sampling, args.time_budget) {
+        (_, Some(budget)) => format!("{} ({:?} budget)", scene_name, budget),
+        (Some(adaptive), None) => format!("{} ({}-{}spp)", scene_name, adaptive.min_spp, adaptive.max_spp),
+        (None, None) => format!("{} ({}spp)", scene_name, spp),
+    }
+}
+
+// Final display stage, from linear radiance to tonemapped 8 bit sRGB
+fn display_color(color: Color) -> SRgbColor {
+    tonemap(color).to_srgb()
 }
 
 fn srgb_data(image: &Image) -> Vec<u8> {
     let mut pixel_data = Vec::with_capacity(image.pixel_count() * 3);
     for rgb in image.pixels() {
-        let srgb = rgb.to_srgb();
+        let srgb = display_color(*rgb);
         pixel_data.push(srgb.r);
         pixel_data.push(srgb.g);
         pixel_data.push(srgb.b);
@@ -125,20 +603,83 @@ fn tonemap(color: Color) -> Color {
 
 #[show_image::main]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let scene = load();
-    let image = trace(&scene);
-    //let image = denoise(&image);
+    let mut cli_args = std::env::args().skip(1).peekable();
+    if cli_args.peek().map(|arg| arg.as_str()) == Some("compare") {
+        let args = parse_compare_args(cli_args.skip(1))?;
+        if !compare_images(&args)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let args = parse_args(cli_args)?;
+
+    let scene = load(&args).map_err(|err| format!("unable to load {}: {}", args.scene_file.display(), err))?;
+    let (width, height) = image_size(&scene);
+
+    let resume_from = resume_checkpoint(&scene, &args)?;
+
+    let name = scene_name(&args, spp(&scene));
+
+    let options = WindowOptions::default()
+        .set_size([width, height])
+        .set_resizable(false)
+        .set_default_controls(false);
+
+    let window = create_window(name.clone(), options).unwrap();
+
+    let denoise = args.denoise;
+
+    let (update_sender, update_receiver) = mpsc::channel();
+    let render_thread = thread::spawn(move || {
+        let on_tile = |tile: &Tile, colors: &[Color]| {
+            let _ = update_sender.send(ViewerUpdate::Tile(*tile, colors.to_vec()));
+        };
+        let on_pass = |image: &Image| {
+            let _ = update_sender.send(ViewerUpdate::Frame(image.clone()));
+        };
+        trace(&scene, &args, resume_from, on_tile, on_pass)
+    });
 
     {
-        let name = scene_name();
+        let mut pixel_data = vec![0; (width * height) as usize * 3];
+        let mut has_frame = false;
+        while let Ok(first) = update_receiver.recv() {
+            // Batch every update received since the last one to avoid re-uploading the image for each of them
+            for update in std::iter::once(first).chain(update_receiver.try_iter()) {
+                match update {
+                    // Once denoised frames come in, raw tiles would only add noise back
+                    ViewerUpdate::Tile(_, _) if has_frame => {},
+                    ViewerUpdate::Tile(tile, colors) => {
+                        for ((x, y), color) in tile.pixels().zip(colors) {
+                            let srgb = display_color(color);
+                            let index = (y * width + x) as usize * 3;
+                            pixel_data[index..index + 3].copy_from_slice(&[srgb.r, srgb.g, srgb.b]);
+                        }
+                    },
+                    ViewerUpdate::Frame(image) => {
+                        pixel_data = srgb_data(&image);
+                        has_frame = true;
+                    },
+                }
+            }
+            window.set_image(name.clone(), ImageView::new(ImageInfo::rgb8(width, height), pixel_data.as_slice())).unwrap();
+        }
+    }
 
-        let options = WindowOptions::default()
-            .set_size([image.width(), image.height()])
-            .set_resizable(false)
-            .set_default_controls(false);
+    let (image, variance, aovs, _) = render_thread.join().expect("render thread panicked");
 
-        let window = create_window(name.clone(), options).unwrap();
+    let image = match aovs {
+        Some(aovs) if denoise => {
+            let start = Instant::now();
+            let image = denoise_guided(&image, &variance, &aovs);
+            println!("Denoised in {:?}", Instant::now() - start);
+            image
+        },
+        _ => image,
+    };
 
+    {
         let pixel_data = srgb_data(&image);
         window.set_image(name, ImageView::new(ImageInfo::rgb8(image.width(), image.height()), pixel_data.as_slice())).unwrap();
 
@@ -1,50 +1,169 @@
 use crate::color::*;
 use crate::vec::*;
 use crate::utils::*;
+use crate::serialize::*;
 
 use rand::prelude::*;
 
 use std::default::*;
+use std::io::{self, Read, Write};
 
 
-#[derive(Debug, Clone, Copy)]
+// Keeps perfectly smooth surfaces from producing infinite GGX densities
+const MIN_ALPHA: f32 = 0.001;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Material {
     pub roughness: f32,
     pub metallic: f32,
     pub color: Color,
     pub emissive: Color,
+    pub transmission: f32,
+    pub ior: f32,
+    pub double_sided: bool,
+    // Emissive surfaces only emit from their front side, and are dark from behind
+    pub one_sided_emission: bool,
 }
 
 pub struct MaterialSample {
+    // eval / pdf of the sampled direction
     pub color: Color,
-    pub reflected_dir: Vec3,
+    // Reflected or transmitted
+    pub dir: Vec3,
+}
+
+// Directions expressed in the tangent space of the normal, which is +Z
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    norm: Vec3,
 }
 
 
+// The material is a mix of three lobes: diffuse, metal (GGX conductor) and rough dielectric (GGX transmission).
+// All directions point away from the surface: view_dir towards the viewer and light_dir towards the light.
+// eval returns the BSDF times the cosine of light_dir, so that sample's color is eval / pdf.
 impl Material {
     pub fn is_emissive(&self) -> bool {
         !self.emissive.is_zero()
     }
 
-    pub fn pdf(&self, norm: Vec3, in_dir: Vec3, out_dir: Vec3) -> f32 {
-        let cos_theta = norm.dot(out_dir);
-        if cos_theta <= 0.0 || norm.dot(in_dir) <= 0.0 { 
-            return 0.0;
+    pub fn emits_from(&self, front_face: bool) -> bool {
+        front_face || !self.one_sided_emission
+    }
+
+    pub fn emission(&self, front_face: bool) -> Color {
+        if self.emits_from(front_face) { self.emissive } else { Color::from(0.0) }
+    }
+
+    // Rays go through the back of single sided surfaces, except for transmissive ones which need their inside to be hit
+    pub fn culls_back_faces(&self) -> bool {
+        !self.double_sided && self.transmission <= 0.0
+    }
+
+    pub fn eval(&self, norm: Vec3, view_dir: Vec3, light_dir: Vec3) -> Color {
+        let frame = Frame::new(norm);
+        let (wo, wi) = (frame.local(view_dir), frame.local(light_dir));
+        let [diffuse, metal, dielectric] = self.lobe_weights();
+
+        let mut color = Color::from(0.0);
+        if diffuse > 0.0 {
+            color += self.color * (diffuse * diffuse_eval(wo, wi));
+        }
+        if metal > 0.0 {
+            color += metal_eval(self.color, self.alpha(), wo, wi) * metal;
+        }
+        if dielectric > 0.0 {
+            let (reflection, transmission) = dielectric_eval(self.ior, self.alpha(), wo, wi);
+            // Only the transmitted light is tinted, like glTF's KHR_materials_transmission
+            color += (Color::from(reflection) + self.color * transmission) * dielectric;
+        }
+        color
+    }
+
+    pub fn pdf(&self, norm: Vec3, view_dir: Vec3, light_dir: Vec3) -> f32 {
+        let frame = Frame::new(norm);
+        let (wo, wi) = (frame.local(view_dir), frame.local(light_dir));
+        let [diffuse, metal, dielectric] = self.lobe_weights();
+
+        let mut pdf = 0.0;
+        if diffuse > 0.0 {
+            pdf += diffuse * diffuse_pdf(wo, wi);
+        }
+        if metal > 0.0 {
+            pdf += metal * metal_pdf(self.alpha(), wo, wi);
         }
-        cos_theta * INV_PI
+        if dielectric > 0.0 {
+            pdf += dielectric * dielectric_pdf(self.ior, self.alpha(), wo, wi);
+        }
+        pdf
     }
 
-    pub fn sample<R: RngCore>(&self, _in_dir: Vec3, norm: Vec3, rng: &mut R) -> MaterialSample {
-        // TODO: importance sampling
-        let reflected_dir = random_in_hemisphere(norm, rng);
-        MaterialSample {
-            color: self.color * INV_PI,
-            reflected_dir: reflected_dir,
+    // Picks a lobe, samples it, and weights the direction with the pdf of the whole mix.
+    // Returns None if the sampled direction isn't valid (below the surface or total internal reflection)
+    pub fn sample<R: RngCore>(&self, view_dir: Vec3, norm: Vec3, rng: &mut R) -> Option<MaterialSample> {
+        let frame = Frame::new(norm);
+        let wo = frame.local(view_dir);
+        let [diffuse, metal, _] = self.lobe_weights();
+
+        let lobe = rng.gen::<f32>();
+        let u = (rng.gen::<f32>(), rng.gen::<f32>());
+        let wi = if lobe < diffuse {
+            diffuse_sample(wo, u)
+        } else if lobe < diffuse + metal {
+            metal_sample(self.alpha(), wo, u)
+        } else {
+            dielectric_sample(self.ior, self.alpha(), wo, u, rng.gen::<f32>())
+        }?;
+
+        let dir = frame.world(wi);
+        let pdf = self.pdf(norm, view_dir, dir);
+        if pdf.is_nan() || pdf <= 0.0 {
+            return None;
         }
+
+        Some(MaterialSample {
+            color: self.eval(norm, view_dir, dir) / pdf,
+            dir: dir,
+        })
+    }
+
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_f32(writer, self.roughness)?;
+        write_f32(writer, self.metallic)?;
+        write_color(writer, self.color)?;
+        write_color(writer, self.emissive)?;
+        write_f32(writer, self.transmission)?;
+        write_f32(writer, self.ior)?;
+        write_u32(writer, self.double_sided as u32)?;
+        write_u32(writer, self.one_sided_emission as u32)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Material> {
+        Ok(Material {
+            roughness: read_f32(reader)?,
+            metallic: read_f32(reader)?,
+            color: read_color(reader)?,
+            emissive: read_color(reader)?,
+            transmission: read_f32(reader)?,
+            ior: read_f32(reader)?,
+            double_sided: read_u32(reader)? != 0,
+            one_sided_emission: read_u32(reader)? != 0,
+        })
+    }
+
+
+    fn lobe_weights(&self) -> [f32; 3] {
+        let metallic = self.metallic.clamp(0.0, 1.0);
+        let transmission = self.transmission.clamp(0.0, 1.0);
+        [(1.0 - metallic) * (1.0 - transmission), metallic, (1.0 - metallic) * transmission]
     }
 
-    pub fn eval(&self, norm: Vec3, in_dir: Vec3, out_dir: Vec3) -> Color {
-        self.color * self.pdf(norm, in_dir, out_dir)
+    fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
     }
 }
 
@@ -56,6 +175,274 @@ impl Default for Material {
             metallic: 0.0,
             color: Color::from(0.75),
             emissive: Color::from(0.0),
+            transmission: 0.0,
+            ior: 1.5,
+            double_sided: false,
+            one_sided_emission: false,
         }
     }
-}
\ No newline at end of file
+}
+
+
+// Reflectance at normal incidence of a conductor, from its complex index of refraction
+pub fn conductor_f0(eta: Color, k: Color) -> Color {
+    let f0 = |n: f32, k: f32| ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+    Color::new(f0(eta.r, k.r), f0(eta.g, k.g), f0(eta.b, k.b))
+}
+
+// Linear sRGB reflectances at normal incidence of common metals, by chemical symbol
+pub fn metal_reflectance(symbol: &str) -> Option<Color> {
+    match symbol {
+        "Ag" => Some(Color::new(0.972, 0.960, 0.915)),
+        "Al" => Some(Color::new(0.913, 0.922, 0.924)),
+        "Au" => Some(Color::new(1.000, 0.766, 0.336)),
+        "Cu" => Some(Color::new(0.955, 0.638, 0.538)),
+        "CuZn" => Some(Color::new(0.910, 0.778, 0.423)),
+        _ => None,
+    }
+}
+
+
+impl Frame {
+    fn new(norm: Vec3) -> Frame {
+        let (tangent, bitangent) = orthonormal_basis(norm);
+        Frame {
+            tangent: tangent,
+            bitangent: bitangent,
+            norm: norm,
+        }
+    }
+
+    fn local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.norm))
+    }
+
+    fn world(&self, v: Vec3) -> Vec3 {
+        self.tangent * v.x + self.bitangent * v.y + self.norm * v.z
+    }
+}
+
+
+fn same_hemisphere(a: Vec3, b: Vec3) -> bool {
+    a.z * b.z > 0.0
+}
+
+// Opaque lobes are two sided, mirrors both directions so that the view is above the surface
+fn upper_hemisphere(wo: Vec3, wi: Vec3) -> (Vec3, Vec3) {
+    if wo.z < 0.0 {
+        (-wo, -wi)
+    } else {
+        (wo, wi)
+    }
+}
+
+fn reflect(wo: Vec3, wm: Vec3) -> Vec3 {
+    wm * (2.0 * wo.dot(wm)) - wo
+}
+
+// Refracts wo through a microfacet of normal wm, eta being the inner over outer index of refraction
+fn refract(wo: Vec3, wm: Vec3, eta: f32) -> Option<Vec3> {
+    let (cos_o, wm, eta) = match wo.dot(wm) {
+        cos if cos < 0.0 => (-cos, -wm, 1.0 / eta),
+        cos => (cos, wm, eta),
+    };
+
+    let sin2_t = (1.0 - cos_o * cos_o).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo / eta + wm * (cos_o / eta - cos_t))
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, cos_i can be on either side
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) * 0.5
+}
+
+fn fresnel_schlick(f0: Color, cos: f32) -> Color {
+    let w = (1.0 - cos.abs()).max(0.0).powi(5);
+    let channel = |f: f32| f + (1.0 - f) * w;
+    Color::new(channel(f0.r), channel(f0.g), channel(f0.b))
+}
+
+
+fn diffuse_eval(wo: Vec3, wi: Vec3) -> f32 {
+    if same_hemisphere(wo, wi) { wi.z.abs() * INV_PI } else { 0.0 }
+}
+
+fn diffuse_pdf(wo: Vec3, wi: Vec3) -> f32 {
+    diffuse_eval(wo, wi)
+}
+
+// Cosine weighted, by projecting uniform disk samples on the hemisphere
+fn diffuse_sample(wo: Vec3, (u, v): (f32, f32)) -> Option<Vec3> {
+    let r = u.sqrt();
+    let phi = 2.0 * PI * v;
+    let z = (1.0 - u).max(0.0).sqrt();
+    Some(Vec3::new(r * phi.cos(), r * phi.sin(), if wo.z < 0.0 { -z } else { z }))
+}
+
+
+// GGX (Trowbridge-Reitz) microfacet distribution, with the height correlated Smith masking
+fn ggx_d(alpha: f32, wm: Vec3) -> f32 {
+    let a2 = alpha * alpha;
+    let d = wm.z * wm.z * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+fn ggx_lambda(alpha: f32, w: Vec3) -> f32 {
+    let cos2 = w.z * w.z;
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    ((1.0 + alpha * alpha * tan2).sqrt() - 1.0) * 0.5
+}
+
+fn ggx_g1(alpha: f32, w: Vec3) -> f32 {
+    1.0 / (1.0 + ggx_lambda(alpha, w))
+}
+
+fn ggx_g(alpha: f32, wo: Vec3, wi: Vec3) -> f32 {
+    1.0 / (1.0 + ggx_lambda(alpha, wo) + ggx_lambda(alpha, wi))
+}
+
+// Density of the microfacet normals visible from w, wm being above the surface
+fn ggx_visible_pdf(alpha: f32, w: Vec3, wm: Vec3) -> f32 {
+    ggx_g1(alpha, w) / w.z.abs() * ggx_d(alpha, wm) * w.dot(wm).abs()
+}
+
+// Samples the microfacet normals visible from w (Heitz 2018)
+fn ggx_sample_visible(alpha: f32, w: Vec3, (u, v): (f32, f32)) -> Vec3 {
+    let w = if w.z < 0.0 { -w } else { w };
+    let wh = Vec3::new(alpha * w.x, alpha * w.y, w.z).normalized();
+
+    let len2 = wh.x * wh.x + wh.y * wh.y;
+    let t1 = if len2 > 0.0 { Vec3::new(-wh.y, wh.x, 0.0) / len2.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+    let t2 = wh.cross(t1);
+
+    let r = u.sqrt();
+    let phi = 2.0 * PI * v;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + wh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+
+    let nh = t1 * p1 + t2 * p2 + wh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    Vec3::new(alpha * nh.x, alpha * nh.y, nh.z.max(1.0e-6)).normalized()
+}
+
+
+fn metal_eval(f0: Color, alpha: f32, wo: Vec3, wi: Vec3) -> Color {
+    if !same_hemisphere(wo, wi) {
+        return Color::from(0.0);
+    }
+
+    let (wo, wi) = upper_hemisphere(wo, wi);
+    let wm = (wo + wi).normalized();
+    fresnel_schlick(f0, wo.dot(wm)) * (ggx_d(alpha, wm) * ggx_g(alpha, wo, wi) / (4.0 * wo.z))
+}
+
+fn metal_pdf(alpha: f32, wo: Vec3, wi: Vec3) -> f32 {
+    if !same_hemisphere(wo, wi) {
+        return 0.0;
+    }
+
+    let (wo, wi) = upper_hemisphere(wo, wi);
+    let wm = (wo + wi).normalized();
+    ggx_visible_pdf(alpha, wo, wm) / (4.0 * wo.dot(wm).abs())
+}
+
+fn metal_sample(alpha: f32, wo: Vec3, u: (f32, f32)) -> Option<Vec3> {
+    let wm = ggx_sample_visible(alpha, wo, u);
+    let wm = if wo.z < 0.0 { -wm } else { wm };
+    let wi = reflect(wo, wm);
+    if same_hemisphere(wo, wi) { Some(wi) } else { None }
+}
+
+
+// Microfacet normal of a pair of directions, facing up, and the relative index of refraction of the transmission.
+// Returns None for configurations no microfacet can produce (Walter et al. 2007)
+fn dielectric_half_vector(ior: f32, wo: Vec3, wi: Vec3) -> Option<(Vec3, f32)> {
+    if wo.z == 0.0 || wi.z == 0.0 {
+        return None;
+    }
+
+    let reflection = same_hemisphere(wo, wi);
+    let etap = if reflection { 1.0 } else if wo.z > 0.0 { ior } else { 1.0 / ior };
+
+    let wm = wi * etap + wo;
+    if wm.length2() == 0.0 {
+        return None;
+    }
+    let wm = wm.normalized();
+    let wm = if wm.z < 0.0 { -wm } else { wm };
+
+    // Microfacets seen from their back
+    if wm.dot(wi) * wi.z < 0.0 || wm.dot(wo) * wo.z < 0.0 {
+        return None;
+    }
+
+    Some((wm, etap))
+}
+
+// Reflected and transmitted parts of the BSDF times the cosine.
+// Radiance isn't scaled by the squared relative index of refraction, it cancels out across closed objects.
+fn dielectric_eval(ior: f32, alpha: f32, wo: Vec3, wi: Vec3) -> (f32, f32) {
+    let (wm, etap) = match dielectric_half_vector(ior, wo, wi) {
+        Some(h) => h,
+        None => return (0.0, 0.0),
+    };
+
+    let d = ggx_d(alpha, wm);
+    let g = ggx_g(alpha, wo, wi);
+    let f = fresnel_dielectric(wo.dot(wm), ior);
+
+    if same_hemisphere(wo, wi) {
+        (d * g * f / (4.0 * wo.z.abs()), 0.0)
+    } else {
+        let denom = wi.dot(wm) + wo.dot(wm) / etap;
+        let t = d * g * (1.0 - f) * (wi.dot(wm) * wo.dot(wm) / (wo.z * denom * denom)).abs();
+        (0.0, t)
+    }
+}
+
+fn dielectric_pdf(ior: f32, alpha: f32, wo: Vec3, wi: Vec3) -> f32 {
+    let (wm, etap) = match dielectric_half_vector(ior, wo, wi) {
+        Some(h) => h,
+        None => return 0.0,
+    };
+
+    let f = fresnel_dielectric(wo.dot(wm), ior);
+    let visible = ggx_visible_pdf(alpha, wo, wm);
+
+    if same_hemisphere(wo, wi) {
+        visible / (4.0 * wo.dot(wm).abs()) * f
+    } else {
+        let denom = wi.dot(wm) + wo.dot(wm) / etap;
+        visible * wi.dot(wm).abs() / (denom * denom) * (1.0 - f)
+    }
+}
+
+// Reflects or refracts on a visible microfacet, picked proportionally to the Fresnel reflectance
+fn dielectric_sample(ior: f32, alpha: f32, wo: Vec3, u: (f32, f32), choice: f32) -> Option<Vec3> {
+    let wm = ggx_sample_visible(alpha, wo, u);
+    let f = fresnel_dielectric(wo.dot(wm), ior);
+
+    let wi = if choice < f {
+        reflect(wo, wm)
+    } else {
+        refract(wo, wm, ior)?
+    };
+
+    // Reflections must stay on the side of wo, transmissions cross the surface
+    if same_hemisphere(wo, wi) == (choice < f) && wi.z != 0.0 { Some(wi) } else { None }
+}
@@ -0,0 +1,262 @@
+// Numerical checks of the material lobes: eval, pdf and sample must agree with each other, and no configuration may reflect more energy than it receives.
+
+use crate::vec::*;
+use crate::color::*;
+use crate::material::*;
+use crate::utils::*;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+
+const NORM: Vec3 = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+
+const SAMPLE_COUNT: usize = 200_000;
+
+// Albedos are estimated twice, by sampling the material and by integrating eval over uniform directions.
+// Both estimates must agree within a few standard errors, and stay below 1
+const MAX_ERROR_SIGMAS: f64 = 5.0;
+const ENERGY_TOLERANCE: f64 = 0.01;
+
+// The sphere is split in THETA_BINS x PHI_BINS, each bin integrating the pdf over BIN_SUBDIVISIONS^2 points
+const THETA_BINS: usize = 32;
+const PHI_BINS: usize = 32;
+const BIN_SUBDIVISIONS: usize = 16;
+const MIN_EXPECTED_COUNT: f64 = 5.0;
+const SIGNIFICANCE: f64 = 0.01;
+
+
+fn configurations() -> Vec<(&'static str, Material)> {
+    let white = Material {
+        color: Color::from(1.0),
+        ..Material::default()
+    };
+
+    let metal = |roughness| Material { metallic: 1.0, roughness: roughness, ..white };
+    let dielectric = |roughness| Material { transmission: 1.0, roughness: roughness, ..white };
+
+    vec![
+        ("diffuse", white),
+        ("diffuse tinted", Material { color: Color::new(0.8, 0.4, 0.1), ..white }),
+        ("metal 0.3", metal(0.3)),
+        ("metal 0.6", metal(0.6)),
+        ("metal 1.0", metal(1.0)),
+        ("dielectric 0.3", dielectric(0.3)),
+        ("dielectric 0.6", dielectric(0.6)),
+        ("dielectric 0.6 ior 1.33", Material { ior: 1.33, ..dielectric(0.6) }),
+        ("half metal", Material { metallic: 0.5, ..white }),
+        ("half transmissive", Material { transmission: 0.5, ..white }),
+    ]
+}
+
+// Views from inside the surface only make sense for transmissive materials
+fn view_dirs(material: &Material) -> Vec<Vec3> {
+    let cosines: &[f32] = if material.transmission > 0.0 { &[0.95, 0.6, 0.2, -0.6, -0.2] } else { &[0.95, 0.6, 0.2] };
+    cosines.iter().map(|cos| Vec3::new((1.0 - cos * cos).sqrt(), 0.0, *cos)).collect()
+}
+
+fn sphere_dir(cos_theta: f32, phi: f32) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn rng_for(name: &str, view_index: usize) -> StdRng {
+    let hash = name.bytes().fold(view_index as u64, |h, b| hash_u64(h ^ b as u64));
+    StdRng::seed_from_u64(hash)
+}
+
+
+// Mean and standard error of a set of samples
+fn mean_and_error<I: Iterator<Item = f64>>(values: I) -> (f64, f64) {
+    let (mut count, mut sum, mut sum2) = (0.0, 0.0, 0.0);
+    for v in values {
+        count += 1.0;
+        sum += v;
+        sum2 += v * v;
+    }
+
+    let mean = sum / count;
+    let variance = (sum2 / count - mean * mean).max(0.0);
+    (mean, (variance / count).sqrt())
+}
+
+// Regularized upper incomplete gamma function Q(a, x), series and continued fraction from Numerical Recipes
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+
+    let ln_gamma_a = ln_gamma(a);
+    if x < a + 1.0 {
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        while term.abs() > sum.abs() * 1e-15 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+        }
+        1.0 - sum * (-x + a * x.ln() - ln_gamma_a).exp()
+    } else {
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..10_000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            d = if d.abs() < tiny { tiny } else { d };
+            c = b + an / c;
+            c = if c.abs() < tiny { tiny } else { c };
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (-x + a * x.ln() - ln_gamma_a).exp() * h
+    }
+}
+
+// Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFS: [f64; 6] = [76.18009172947146, -86.50532032941677, 24.01409824083091, -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let series = COEFFS.iter().enumerate().fold(1.000000000190015, |s, (i, c)| s + c / (x + 1.0 + i as f64));
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+
+#[test]
+fn sample_matches_eval_and_pdf() {
+    let mut failures = Vec::new();
+    for (name, material) in configurations() {
+        for (v, view) in view_dirs(&material).into_iter().enumerate() {
+            let mut rng = rng_for(name, v);
+            for _ in 0..SAMPLE_COUNT / 10 {
+                let sample = match material.sample(view, NORM, &mut rng) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+
+                let pdf = material.pdf(NORM, view, sample.dir);
+                let expected = material.eval(NORM, view, sample.dir) / pdf;
+                let close = |a: f32, b: f32| (a - b).abs() <= 1e-3 * b.abs().max(1.0);
+                if pdf.is_nan() || pdf <= 0.0 || !close(sample.color.r, expected.r) || !close(sample.color.g, expected.g) || !close(sample.color.b, expected.b) {
+                    failures.push(format!("{} (view {}): sampled {:?} with weight {:?} but eval / pdf is {:?} (pdf = {})", name, view, sample.dir, sample.color, expected, pdf));
+                    break;
+                }
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn white_furnace() {
+    let mut failures = Vec::new();
+    for (name, material) in configurations() {
+        for (v, view) in view_dirs(&material).into_iter().enumerate() {
+            let mut rng = rng_for(name, v);
+
+            // Under a uniform white environment, the reflected radiance is the integral of eval over the sphere
+            let (sampled, sampled_error) = mean_and_error((0..SAMPLE_COUNT).map(|_| {
+                material.sample(view, NORM, &mut rng).map(|s| s.color.r as f64).unwrap_or(0.0)
+            }));
+
+            let (integrated, integrated_error) = mean_and_error((0..SAMPLE_COUNT).map(|_| {
+                let dir = sphere_dir(rng.gen::<f32>() * 2.0 - 1.0, rng.gen::<f32>() * 2.0 * PI);
+                material.eval(NORM, view, dir).r as f64 * 4.0 * PI as f64
+            }));
+
+            let sigmas = (sampled - integrated).abs() / (sampled_error * sampled_error + integrated_error * integrated_error).sqrt().max(1e-9);
+            if sigmas > MAX_ERROR_SIGMAS {
+                failures.push(format!("{} (view {}): sampled albedo is {:.4} but eval integrates to {:.4} ({:.1} sigmas)", name, view, sampled, integrated, sigmas));
+            }
+
+            if sampled > 1.0 + ENERGY_TOLERANCE {
+                failures.push(format!("{} (view {}): albedo of {:.4} creates energy", name, view, sampled));
+            }
+
+            // Nothing is absorbed by a white diffuse surface
+            if name == "diffuse" && (sampled - 1.0).abs() > ENERGY_TOLERANCE {
+                failures.push(format!("{} (view {}): albedo of {:.4} instead of 1", name, view, sampled));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn chi_square() {
+    let bin_of = |dir: Vec3| {
+        let theta = dir.z.clamp(-1.0, 1.0).acos();
+        let phi = dir.y.atan2(dir.x).rem_euclid(2.0 * PI);
+        let t = ((theta / PI * THETA_BINS as f32) as usize).min(THETA_BINS - 1);
+        let p = ((phi / (2.0 * PI) * PHI_BINS as f32) as usize).min(PHI_BINS - 1);
+        t * PHI_BINS + p
+    };
+
+    let tests = configurations().iter().map(|(_, m)| view_dirs(m).len()).sum::<usize>();
+    // Šidák correction, so that the whole suite has the requested significance
+    let significance = 1.0 - (1.0 - SIGNIFICANCE).powf(1.0 / tests as f64);
+
+    let mut failures = Vec::new();
+    for (name, material) in configurations() {
+        for (v, view) in view_dirs(&material).into_iter().enumerate() {
+            let mut rng = rng_for(name, v);
+
+            let mut observed = vec![0.0; THETA_BINS * PHI_BINS];
+            for _ in 0..SAMPLE_COUNT {
+                if let Some(sample) = material.sample(view, NORM, &mut rng) {
+                    observed[bin_of(sample.dir)] += 1.0;
+                }
+            }
+
+            // Midpoint integration of the pdf over every bin, in (theta, phi)
+            let (theta_step, phi_step) = (PI / THETA_BINS as f32, 2.0 * PI / PHI_BINS as f32);
+            let sub = BIN_SUBDIVISIONS as f32;
+            let expected = (0..THETA_BINS * PHI_BINS).map(|bin| {
+                let (t, p) = ((bin / PHI_BINS) as f32, (bin % PHI_BINS) as f32);
+                let mut integral = 0.0;
+                for i in 0..BIN_SUBDIVISIONS {
+                    for j in 0..BIN_SUBDIVISIONS {
+                        let theta = (t + (i as f32 + 0.5) / sub) * theta_step;
+                        let phi = (p + (j as f32 + 0.5) / sub) * phi_step;
+                        let dir = sphere_dir(theta.cos(), phi);
+                        integral += (material.pdf(NORM, view, dir) * theta.sin()) as f64;
+                    }
+                }
+                integral * (theta_step * phi_step / (sub * sub)) as f64 * SAMPLE_COUNT as f64
+            }).collect::<Vec<_>>();
+
+            // Bins with too few expected samples are pooled together, as the statistic is only valid for large counts
+            let (mut chi2, mut dof) = (0.0, 0);
+            let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+            for (o, e) in observed.iter().zip(&expected) {
+                if *e < MIN_EXPECTED_COUNT {
+                    pooled_observed += o;
+                    pooled_expected += e;
+                } else {
+                    chi2 += (o - e) * (o - e) / e;
+                    dof += 1;
+                }
+            }
+            if pooled_expected > 0.0 {
+                chi2 += (pooled_observed - pooled_expected) * (pooled_observed - pooled_expected) / pooled_expected;
+                dof += 1;
+            }
+
+            let p_value = gamma_q((dof - 1) as f64 / 2.0, chi2 / 2.0);
+            if p_value < significance {
+                failures.push(format!("{} (view {}): chi2 = {:.1} for {} degrees of freedom, p = {:.2e}", name, view, chi2, dof - 1, p_value));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -6,19 +6,29 @@ use crate::hit::*;
 use crate::bvh::*;
 use crate::surface::*;
 use crate::material::*;
+use crate::color::*;
 use crate::utils::*;
+use crate::transform::*;
+use crate::serialize::*;
 
 use rand::prelude::*;
 
+use std::io::{self, Read, Write};
+
 
 const MAX_TRI_PER_NODE: usize = 8;
 
 
 pub struct Mesh {
-    bvh: Bvh<[u32; 3]>,
+    bvh: Bvh,
 
+    // In the order of the BVH leaves
     triangles: Vec<[u32; 3]>,
     vertices: Vec<Vertex>,
+    // One per vertex, or empty
+    colors: Vec<Color>,
+    // Texture coordinates, one per vertex or empty
+    uvs: Vec<[f32; 2]>,
 
     material: Material,
 
@@ -34,6 +44,8 @@ impl Mesh {
             bvh: Bvh::new(triangles.as_mut_slice(), triangle_aabb, MAX_TRI_PER_NODE),
             triangles: triangles,
             vertices: vertices,
+            colors: Vec::new(),
+            uvs: Vec::new(),
             material: material,
             area: 0.0,
             triangle_areas: Vec::new(),
@@ -44,6 +56,62 @@ impl Mesh {
         mesh
     }
 
+    pub fn with_colors(self, colors: Vec<Color>) -> Mesh {
+        debug_assert!(colors.len() == self.vertices.len());
+        Mesh {
+            colors: colors,
+            ..self
+        }
+    }
+
+    pub fn with_uvs(self, uvs: Vec<[f32; 2]>) -> Mesh {
+        debug_assert!(uvs.len() == self.vertices.len());
+        Mesh {
+            uvs: uvs,
+            ..self
+        }
+    }
+
+    pub fn with_material(self, material: Material) -> Mesh {
+        Mesh {
+            material: material,
+            ..self
+        }
+    }
+
+    // Mirroring transforms reverse the winding, so it is reversed back to keep the front faces outside
+    pub fn transformed(self, tr: &Transform) -> Mesh {
+        let vertices = self.vertices.iter().map(|v| Vertex {
+            pos: tr.transform_pos(v.pos),
+            norm: tr.transform_normal(v.norm).normalized(),
+        }).collect();
+
+        let triangles = if tr.determinant() < 0.0 {
+            self.triangles.iter().map(|tri| [tri[0], tri[2], tri[1]]).collect()
+        } else {
+            self.triangles
+        };
+
+        // Vertices keep their order, so per vertex attributes stay valid
+        Mesh {
+            colors: self.colors,
+            uvs: self.uvs,
+            ..Mesh::new(vertices, triangles, self.material)
+        }
+    }
+
+    // Turns the surface around: the back faces become the front ones
+    pub fn flipped(self) -> Mesh {
+        let vertices = self.vertices.iter().map(|v| Vertex { pos: v.pos, norm: -v.norm }).collect();
+        let triangles = self.triangles.iter().map(|tri| [tri[0], tri[2], tri[1]]).collect();
+
+        Mesh {
+            colors: self.colors,
+            uvs: self.uvs,
+            ..Mesh::new(vertices, triangles, self.material)
+        }
+    }
+
     pub fn aabb(&self) -> Aabb {
         self.bvh.aabb()
     }
@@ -52,6 +120,83 @@ impl Mesh {
         &self.material
     }
 
+    // The BVH is written too, so that reading doesn't rebuild it
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u64(writer, self.vertices.len() as u64)?;
+        for v in &self.vertices {
+            write_vec3(writer, v.pos)?;
+            write_vec3(writer, v.norm)?;
+        }
+
+        write_u64(writer, self.triangles.len() as u64)?;
+        for tri in &self.triangles {
+            tri.iter().try_for_each(|i| write_u32(writer, *i))?;
+        }
+
+        write_u32(writer, !self.colors.is_empty() as u32)?;
+        for c in &self.colors {
+            write_color(writer, *c)?;
+        }
+
+        write_u32(writer, !self.uvs.is_empty() as u32)?;
+        for uv in &self.uvs {
+            write_f32(writer, uv[0])?;
+            write_f32(writer, uv[1])?;
+        }
+
+        self.material.write_to(writer)?;
+        self.bvh.write_to(writer)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Mesh> {
+        let vertex_count = read_u64(reader)?;
+        let vertices = (0..vertex_count).map(|_| Ok(Vertex {
+            pos: read_vec3(reader)?,
+            norm: read_vec3(reader)?,
+        })).collect::<io::Result<Vec<_>>>()?;
+
+        // Triangles are read at once, as there are many
+        let triangle_count = read_u64(reader)? as usize;
+        let triangles = read_bytes(reader, triangle_count.checked_mul(12).ok_or_else(|| invalid_data("too many triangles"))?)?;
+        let triangles = triangles.chunks_exact(12).map(|mut tri| {
+            let tri = [read_u32(&mut tri)?, read_u32(&mut tri)?, read_u32(&mut tri)?];
+            if tri.iter().any(|i| *i as u64 >= vertex_count) {
+                return Err(invalid_data("triangle index out of range"));
+            }
+            Ok(tri)
+        }).collect::<io::Result<Vec<_>>>()?;
+
+        let colors = if read_u32(reader)? != 0 {
+            (0..vertex_count).map(|_| read_color(reader)).collect::<io::Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+
+        let uvs = if read_u32(reader)? != 0 {
+            (0..vertex_count).map(|_| Ok([read_f32(reader)?, read_f32(reader)?])).collect::<io::Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+
+        let material = Material::read_from(reader)?;
+        let bvh = Bvh::read_from(reader, triangles.len())?;
+
+        let mut mesh = Mesh {
+            bvh: bvh,
+            triangles: triangles,
+            vertices: vertices,
+            colors: colors,
+            uvs: uvs,
+            material: material,
+            area: 0.0,
+            triangle_areas: Vec::new(),
+        };
+
+        mesh.build_surface();
+
+        Ok(mesh)
+    }
+
 
     fn build_surface(&mut self) {
         let mut triangle_areas = self.triangles.iter().map(|index| {
@@ -86,6 +231,14 @@ impl Mesh {
             ];
 
             if let Some(bary) = tri.hit(ray) {
+                let geom_norm = (tri[1].pos - tri[0].pos).cross(tri[2].pos - tri[0].pos).normalized();
+
+                // Triangles are counter clockwise when seen from their front
+                let front_face = ray.dir.dot(geom_norm) < 0.0;
+                if !front_face && self.material.culls_back_faces() {
+                    continue;
+                }
+
                 let pos =
                     tri[0].pos * bary[0] +
                     tri[1].pos * bary[1] +
@@ -96,15 +249,50 @@ impl Mesh {
                     tri[1].norm * bary[1] +
                     tri[2].norm * bary[2];
 
+                // Error of the barycentric interpolation (PBRT 6.8.5)
+                let pos_error = (
+                    (tri[0].pos * bary[0]).abs() +
+                    (tri[1].pos * bary[1]).abs() +
+                    (tri[2].pos * bary[2]).abs()
+                ) * gamma(7);
+
+                // The back of a double sided opaque surface is shaded like its front.
+                // Transmissive surfaces keep their normals, which tell their inside from their outside
+                let (norm, geom_norm) = if !front_face && self.material.transmission <= 0.0 {
+                    (-norm, -geom_norm)
+                } else {
+                    (norm, geom_norm)
+                };
+
+                let color = if self.colors.is_empty() {
+                    None
+                } else {
+                    let c = index.map(|i| self.colors[i as usize]);
+                    Some(c[0] * bary[0] + c[1] * bary[1] + c[2] * bary[2])
+                };
+
+                let uv = if self.uvs.is_empty() {
+                    None
+                } else {
+                    let t = index.map(|i| self.uvs[i as usize]);
+                    Some([0, 1].map(|k| t[0][k] * bary[0] + t[1][k] * bary[1] + t[2][k] * bary[2]))
+                };
+
                 let dist = ray.orig.distance(pos);
 
                 ray = ray.with_max(dist);
                 hit = Some(HitRecord {
                     dist: dist,
                     pos: pos,
-                    norm: norm.normalized(),
+                    pos_error: pos_error,
+                    shading_norm: norm.normalized(),
+                    geom_norm: geom_norm,
+                    front_face: front_face,
+                    vertex_color: color,
+                    uv: uv,
                     ray: ray,
                     obj: Some(self),
+                    obj_index: None,
                 });
             }
         }
@@ -167,7 +355,7 @@ impl<'mesh> Hittable for &'mesh Mesh {
     type Result = HitRecord<'mesh>;
 
     fn hit(&self, ray: Ray) -> Option<Self::Result> {
-        self.bvh.trace(ray, |r, tris| self.hit_triangles(r, tris))
+        self.bvh.trace(ray, &self.triangles, |r, tris| self.hit_triangles(r, tris))
     }
 }
 
@@ -180,43 +368,91 @@ impl Hittable for [Vertex; 3] {
     }
 }
 
+// Watertight intersection (Woop et al. 2013, with the conservative distance bound of PBRT 6.8.4), reporting hits on both sides.
+// Rays through an edge or vertex shared by several triangles hit exactly one of them
 impl Hittable for [Vec3; 3] {
     type Result = [f32; 3];
 
     fn hit(&self, ray: Ray) -> Option<Self::Result> {
-        let edge1 = self[1] - self[0];
-        let edge2 = self[2] - self[0];
-
-        let pvec = ray.dir.cross(edge2);
+        // Translate to the ray origin and permute so that the ray's largest component is z
+        let abs_dir = ray.dir.abs();
+        let kz = if abs_dir.x > abs_dir.y { if abs_dir.x > abs_dir.z { 0 } else { 2 } } else if abs_dir.y > abs_dir.z { 1 } else { 2 };
+        let (kx, ky) = ((kz + 1) % 3, (kz + 2) % 3);
+
+        let d = [ray.dir[kx], ray.dir[ky], ray.dir[kz]];
+        let mut p = [0, 1, 2].map(|i| {
+            let p = self[i] - ray.orig;
+            [p[kx], p[ky], p[kz]]
+        });
+
+        // Shear so that the ray points along +z, z is only scaled once the hit is known
+        let (sx, sy, sz) = (-d[0] / d[2], -d[1] / d[2], 1.0 / d[2]);
+        for p in &mut p {
+            p[0] += sx * p[2];
+            p[1] += sy * p[2];
+        }
 
-        let det = edge1.dot(pvec);
+        let edge = |a: &[f32; 3], b: &[f32; 3]| {
+            let e = a[0] * b[1] - a[1] * b[0];
+            // Exactly zero is ambiguous, so it is computed again in double precision
+            if e == 0.0 {
+                (a[0] as f64 * b[1] as f64 - a[1] as f64 * b[0] as f64) as f32
+            } else {
+                e
+            }
+        };
+        let e = [edge(&p[1], &p[2]), edge(&p[2], &p[0]), edge(&p[0], &p[1])];
 
-        if det < EPSILON {
+        if (e[0] < 0.0 || e[1] < 0.0 || e[2] < 0.0) && (e[0] > 0.0 || e[1] > 0.0 || e[2] > 0.0) {
             return None;
         }
 
-        let inv_det = 1.0 / det;
-
-        let tvec = ray.orig - self[0];
+        let det = e[0] + e[1] + e[2];
+        if det == 0.0 {
+            return None;
+        }
 
-        let u = tvec.dot(pvec) * inv_det;
-        if u < 0.0 || u > 1.0 {
+        // A ray exactly through an edge would hit every triangle sharing it, so an edge only belongs to the triangle on one of its sides.
+        // Walked in the orientation det gives the triangle, neighbours go through their shared edge in opposite directions
+        let owns = |a: &[f32; 3], b: &[f32; 3]| {
+            let (dx, dy) = if det > 0.0 { (b[0] - a[0], b[1] - a[1]) } else { (a[0] - b[0], a[1] - b[1]) };
+            dy > 0.0 || (dy == 0.0 && dx < 0.0)
+        };
+        if (e[0] == 0.0 && !owns(&p[1], &p[2])) || (e[1] == 0.0 && !owns(&p[2], &p[0])) || (e[2] == 0.0 && !owns(&p[0], &p[1])) {
             return None;
         }
 
-        let qvec = tvec.cross(edge1);
+        for p in &mut p {
+            p[2] *= sz;
+        }
 
-        let v = ray.dir.dot(qvec) * inv_det;
-        if v < 0.0 || u + v > 1.0 {
+        // Distance test before the division
+        let t_scaled = e[0] * p[0][2] + e[1] * p[1][2] + e[2] * p[2][2];
+        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.max * det) {
+            return None;
+        }
+        if det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.max * det) {
             return None;
         }
 
-        let t = edge2.dot(qvec) * inv_det;
-        if t < 0.0 || t > ray.max {
+        let inv_det = 1.0 / det;
+        let t = t_scaled * inv_det;
+
+        // Reject hits whose distance isn't certainly positive given the rounding errors above
+        let max_of = |v: [f32; 3]| v[0].abs().max(v[1].abs()).max(v[2].abs());
+        let max_x = max_of([p[0][0], p[1][0], p[2][0]]);
+        let max_y = max_of([p[0][1], p[1][1], p[2][1]]);
+        let max_z = max_of([p[0][2], p[1][2], p[2][2]]);
+        let delta_x = gamma(5) * (max_x + max_z);
+        let delta_y = gamma(5) * (max_y + max_z);
+        let delta_z = gamma(3) * max_z;
+        let delta_e = 2.0 * (gamma(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+        let max_e = max_of(e);
+        let delta_t = 3.0 * (gamma(3) * max_e * max_z + delta_e * max_z + delta_z * max_e) * inv_det.abs();
+        if t <= delta_t {
             return None;
         }
 
-        let w = 1.0 - u - v;
-        return Some([w, u, v]);
+        Some([e[0] * inv_det, e[1] * inv_det, e[2] * inv_det])
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,152 @@
+// Checks the watertight triangle intersection: shared edges and vertices are hit exactly once, degenerate triangles never, and the sides a mesh reports.
+
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::mesh::*;
+use crate::vertex::*;
+use crate::material::*;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+
+fn v(x: f32, y: f32, z: f32) -> Vec3 {
+    Vec3::new(x, y, z)
+}
+
+fn hit_count(triangles: &[[Vec3; 3]], ray: Ray) -> usize {
+    triangles.iter().filter(|tri| tri.hit(ray).is_some()).count()
+}
+
+// Regular polygon around center, split into a fan of triangles sharing center, counter clockwise when seen from +z
+fn fan(center: Vec3, radius: f32, sides: usize) -> Vec<[Vec3; 3]> {
+    let corner = |i: usize| {
+        let angle = (i % sides) as f32 / sides as f32 * std::f32::consts::TAU + 0.3;
+        center + v(angle.cos(), angle.sin(), 0.0) * radius
+    };
+    (0..sides).map(|i| [center, corner(i), corner(i + 1)]).collect()
+}
+
+fn single_triangle(material: Material) -> Mesh {
+    let vertices = [v(-1.0, -1.0, 0.0), v(1.0, -1.0, 0.0), v(0.0, 1.0, 0.0)].map(|p| Vertex { pos: p, norm: v(0.0, 0.0, 1.0) });
+    Mesh::new(vertices.to_vec(), vec![[0, 1, 2]], material)
+}
+
+
+#[test]
+fn shared_edge() {
+    let quad = [[v(-1.0, -1.0, 0.0), v(1.0, -1.0, 0.0), v(1.0, 1.0, 0.0)], [v(-1.0, -1.0, 0.0), v(1.0, 1.0, 0.0), v(-1.0, 1.0, 0.0)]];
+    let flipped = [quad[0], [quad[1][0], quad[1][2], quad[1][1]]];
+    let split = [[v(0.0, -1.0, 0.0), v(0.0, 1.0, 0.0), v(-1.0, 0.0, 0.0)], [v(0.0, 1.0, 0.0), v(0.0, -1.0, 0.0), v(1.0, 0.0, 0.0)]];
+
+    // Axis aligned rays through the edge, where the edge functions are exactly 0
+    for t in [-0.5, 0.0, 0.25, 0.7] {
+        for dir in [-1.0, 1.0] {
+            let ray = Ray::new(v(t, t, dir * -2.0), v(0.0, 0.0, dir));
+            assert_eq!(hit_count(&quad, ray), 1, "diagonal at {} towards {}", t, dir);
+            assert_eq!(hit_count(&flipped, ray), 1, "inconsistent winding at {} towards {}", t, dir);
+
+            let ray = Ray::new(v(0.0, t, dir * -2.0), v(0.0, 0.0, dir));
+            assert_eq!(hit_count(&split, ray), 1, "vertical edge at {} towards {}", t, dir);
+        }
+    }
+
+    // Oblique rays through rounded points of the edge
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..1000 {
+        let t = rng.gen::<f32>() * 1.8 - 0.9;
+        let orig = v(rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5, 1.0 + rng.gen::<f32>()) * 3.0;
+        let ray = Ray::new(orig, v(t, t, 0.0) - orig);
+        assert_eq!(hit_count(&quad, ray), 1, "{:?}", ray);
+        assert_eq!(hit_count(&flipped, ray), 1, "{:?}", ray);
+    }
+}
+
+#[test]
+fn shared_vertex() {
+    for sides in [3, 4, 6, 7] {
+        for center in [v(0.0, 0.0, 0.0), v(0.25, -0.5, 0.0)] {
+            let triangles = fan(center, 1.0, sides);
+            for dir in [-1.0, 1.0] {
+                let ray = Ray::new(center + v(0.0, 0.0, dir * -2.0), v(0.0, 0.0, dir));
+                assert_eq!(hit_count(&triangles, ray), 1, "{} triangles around {:?} towards {}", sides, center, dir);
+            }
+
+            let mut rng = StdRng::seed_from_u64(sides as u64);
+            for _ in 0..200 {
+                let orig = center + v(rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5, 1.0) * 3.0;
+                let ray = Ray::new(orig, center - orig);
+                assert_eq!(hit_count(&triangles, ray), 1, "{} triangles around {:?}: {:?}", sides, center, ray);
+            }
+        }
+    }
+}
+
+#[test]
+fn degenerate_triangles() {
+    let degenerate = [
+        [v(0.0, 0.0, 0.0), v(0.0, 0.0, 0.0), v(0.0, 0.0, 0.0)],
+        [v(-1.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(1.0, 0.0, 0.0)],
+        [v(-1.0, 0.0, 0.0), v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0)],
+        [v(-1.0, -1.0, 0.0), v(0.0, 0.0, 0.0), v(1.0, 1.0, 0.0)],
+    ];
+
+    let mut rng = StdRng::seed_from_u64(2);
+    for tri in degenerate {
+        for target in [v(0.0, 0.0, 0.0), v(0.5, 0.0, 0.0), v(0.5, 0.5, 0.0)] {
+            for _ in 0..100 {
+                let orig = v(rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5) * 4.0;
+                assert!(tri.hit(Ray::new(orig, target - orig)).is_none(), "{:?} hit from {:?}", tri, orig);
+            }
+        }
+    }
+}
+
+#[test]
+fn back_faces() {
+    let from_front = Ray::new(v(0.0, 0.0, 2.0), v(0.0, 0.0, -1.0));
+    let from_back = Ray::new(v(0.0, 0.0, -2.0), v(0.0, 0.0, 1.0));
+
+    let single_sided = single_triangle(Material::default());
+    assert!((&single_sided).hit(from_front).is_some());
+    assert!((&single_sided).hit(from_back).is_none());
+
+    let double_sided = single_triangle(Material { double_sided: true, ..Material::default() });
+    assert!((&double_sided).hit(from_back).is_some());
+
+    // Transmissive surfaces are never culled, their back is where rays leave them
+    let glass = single_triangle(Material { transmission: 1.0, ..Material::default() });
+    assert!((&glass).hit(from_back).is_some());
+}
+
+#[test]
+fn orientation() {
+    let from_front = Ray::new(v(0.0, 0.0, 2.0), v(0.0, 0.0, -1.0));
+    let from_back = Ray::new(v(0.0, 0.0, -2.0), v(0.0, 0.0, 1.0));
+    let up = v(0.0, 0.0, 1.0);
+
+    let double_sided = single_triangle(Material { double_sided: true, ..Material::default() });
+    let hit = (&double_sided).hit(from_front).unwrap();
+    assert!(hit.front_face);
+    assert_eq!((hit.geom_norm, hit.shading_norm), (up, up));
+    assert_eq!(hit.dist, 2.0);
+
+    // Opaque back faces are shaded like front faces, so their normals face the ray
+    let hit = (&double_sided).hit(from_back).unwrap();
+    assert!(!hit.front_face);
+    assert_eq!((hit.geom_norm, hit.shading_norm), (-up, -up));
+
+    // Transmissive ones keep the normals of their front, to tell leaving the surface from entering it
+    let glass = single_triangle(Material { transmission: 1.0, ..Material::default() });
+    let hit = (&glass).hit(from_back).unwrap();
+    assert!(!hit.front_face);
+    assert_eq!((hit.geom_norm, hit.shading_norm), (up, up));
+
+    // The barycentric coordinates sum to 1 and weight the vertices of the hit point
+    let tri = [v(-1.0, -1.0, 0.0), v(1.0, -1.0, 0.0), v(0.0, 1.0, 0.0)];
+    let bary = tri.hit(Ray::new(v(0.0, -0.5, 3.0), v(0.0, 0.0, -1.0))).unwrap();
+    assert!((bary[0] + bary[1] + bary[2] - 1.0).abs() < 1e-6);
+    let pos = tri[0] * bary[0] + tri[1] * bary[1] + tri[2] * bary[2];
+    assert!((pos - v(0.0, -0.5, 0.0)).length() < 1e-6, "{:?}", pos);
+}
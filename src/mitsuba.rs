@@ -0,0 +1,712 @@
+use crate::vec::*;
+use crate::color::*;
+use crate::material::*;
+use crate::scene::*;
+use crate::normals::*;
+use crate::transform::*;
+use crate::camera::*;
+use crate::environment::*;
+use crate::integrator::*;
+use crate::image::*;
+use crate::obj::*;
+use crate::ply::*;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+
+// An XML element, text between elements is dropped
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    line: usize,
+}
+
+struct MitsubaSensor {
+    to_world: Transform,
+    // In degrees, along fov_axis
+    fov: f32,
+    fov_axis: String,
+}
+
+struct MitsubaImporter<'a> {
+    builder: &'a mut SceneBuilder,
+    normal_generation: NormalGeneration,
+    // Relative paths are relative to the main file, even in included ones
+    dir: PathBuf,
+    // Files being parsed, the main one first, to catch files including themselves
+    open_files: Vec<PathBuf>,
+    // Values of the $name references in attributes, longest names first
+    defaults: Vec<(String, String)>,
+    bsdfs: HashMap<String, Material>,
+
+    sensor: Option<MitsubaSensor>,
+    resolution: (u32, u32),
+    settings: RenderSettings,
+    environment: Option<Environment>,
+
+    warnings: HashSet<String>,
+}
+
+
+// Imports the parts of Mitsuba 3 scenes this renderer can show (https://mitsuba.readthedocs.io/en/stable/src/key_topics/scene_format.html):
+// perspective sensors with their film size and sample count, path depth, OBJ, PLY, rectangle and sphere shapes,
+// diffuse, conductor, dielectric and plastic BSDFs, area lights, and environment maps.
+// Anything else is skipped with a warning. Unset render settings take Mitsuba's defaults, except for the unlimited path depth
+pub fn import_mitsuba(path: &Path, builder: &mut SceneBuilder, normal_generation: NormalGeneration) -> Result<(), SceneError> {
+    let mut importer = MitsubaImporter {
+        builder: builder,
+        normal_generation: normal_generation,
+        dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        open_files: Vec::new(),
+        defaults: Vec::new(),
+        bsdfs: HashMap::new(),
+
+        sensor: None,
+        resolution: (768, 576),
+        settings: RenderSettings {
+            spp: Some(4),
+            max_bounces: None,
+            resolution: None,
+            integrator: Some(IntegratorKind::Path),
+        },
+        environment: None,
+
+        warnings: HashSet::new(),
+    };
+
+    importer.parse_file(path)?;
+    importer.finish();
+    Ok(())
+}
+
+
+impl<'a> MitsubaImporter<'a> {
+    fn parse_file(&mut self, path: &Path) -> Result<(), SceneError> {
+        self.builder.add_source_file(path);
+        let text = std::fs::read_to_string(path)?;
+        let error = |line: usize, msg: String| SceneError::Parse(format!("{}:{}: {}", path.display(), line, msg));
+
+        let root = parse_xml(&text).map_err(|(line, msg)| error(line, msg))?;
+        if root.name != "scene" {
+            return Err(error(root.line, format!("expected a <scene>, got <{}>", root.name)));
+        }
+        // Older scenes name their parameters differently
+        if root.attr("version").is_some_and(|version| version.starts_with("0.")) {
+            return Err(error(root.line, "scenes of Mitsuba 0.x must be upgraded to version 3".to_string()));
+        }
+
+        self.open_files.push(std::fs::canonicalize(path)?);
+        let result = root.children.iter().try_for_each(|element| {
+            let element = element.substituted(&self.defaults);
+            self.element(&element).map_err(|err| match err {
+                SceneError::Parse(msg) => error(element.line, msg),
+                SceneError::InvalidGeometry { location, reason } => SceneError::InvalidGeometry {
+                    location: format!("{}:{} ({})", path.display(), element.line, location),
+                    reason: reason,
+                },
+                err => err,
+            })
+        });
+        self.open_files.pop();
+        result
+    }
+
+    fn warn(&mut self, msg: String) {
+        if self.warnings.insert(msg.clone()) {
+            eprintln!("Skipping {}", msg);
+        }
+    }
+
+    // Errors are Parse messages without their location, added by parse_file
+    fn element(&mut self, element: &Element) -> Result<(), SceneError> {
+        match element.name.as_str() {
+            "default" => {
+                let name = element.required("name").map_err(SceneError::Parse)?;
+                let value = element.required("value").map_err(SceneError::Parse)?;
+                if !self.defaults.iter().any(|(n, _)| n == name) {
+                    self.defaults.push((name.to_string(), value.to_string()));
+                    self.defaults.sort_by_key(|(n, _)| std::cmp::Reverse(n.len()));
+                }
+            },
+            "include" => {
+                let file = self.dir.join(element.required("filename").map_err(SceneError::Parse)?);
+                if std::fs::canonicalize(&file).is_ok_and(|file| self.open_files.contains(&file)) {
+                    return Err(SceneError::Parse(format!("{} includes itself", file.display())));
+                }
+                self.parse_file(&file).map_err(|err| match err {
+                    SceneError::Io(err) => SceneError::Parse(format!("unable to read {}: {}", file.display(), err)),
+                    err => err,
+                })?;
+            },
+            "integrator" => self.integrator(element).map_err(SceneError::Parse)?,
+            "sensor" => self.sensor(element).map_err(SceneError::Parse)?,
+            "bsdf" => {
+                self.bsdf(element).map_err(SceneError::Parse)?;
+            },
+            "shape" => self.shape(element)?,
+            "emitter" => self.emitter(element).map_err(SceneError::Parse)?,
+            "texture" | "medium" | "phase" | "volume" | "spectrum" | "rgb" => self.warn(format!("<{}> elements", element.name)),
+            name => return Err(SceneError::Parse(format!("unexpected <{}>", name))),
+        }
+
+        Ok(())
+    }
+
+    // Mitsuba's depth counts the camera ray, and -1 is unlimited
+    fn integrator(&mut self, integrator: &Element) -> Result<(), String> {
+        match integrator.ty() {
+            "path" | "volpath" | "volpathmis" | "prb" => self.settings.integrator = Some(IntegratorKind::Path),
+            "direct" => self.settings.integrator = Some(IntegratorKind::Direct),
+            ty => self.warn(format!("{} integrator, using a path tracer", ty)),
+        }
+        if let Some(depth) = integrator.integer("max_depth")? {
+            if depth > 0 {
+                self.settings.max_bounces = Some((depth - 1).max(1) as usize);
+            }
+        }
+        Ok(())
+    }
+
+    fn sensor(&mut self, sensor: &Element) -> Result<(), String> {
+        if sensor.ty() != "perspective" {
+            self.warn(format!("{} sensor, using a perspective one", sensor.ty()));
+        }
+
+        // Focal lengths are for a 36x24mm film, along its diagonal
+        let (fov, fov_axis) = match (sensor.float("fov")?, sensor.string("focal_length")?) {
+            (Some(_), Some(_)) => return Err("sensor has both a fov and a focal_length".to_string()),
+            (Some(fov), None) => (fov, sensor.string("fov_axis")?.unwrap_or("x").to_lowercase()),
+            (None, focal_length) => {
+                let focal_length = focal_length.unwrap_or("50mm");
+                let mm = focal_length.strip_suffix("mm").unwrap_or(focal_length).parse::<f32>()
+                    .map_err(|_| format!("invalid focal_length \"{}\"", focal_length))?;
+                let diagonal = (36.0_f32 * 36.0 + 24.0 * 24.0).sqrt();
+                (2.0 * (diagonal / (2.0 * mm)).atan().to_degrees(), "diagonal".to_string())
+            },
+        };
+        if !matches!(fov_axis.as_str(), "x" | "y" | "diagonal" | "smaller" | "larger") {
+            return Err(format!("invalid fov_axis \"{}\"", fov_axis));
+        }
+
+        if let Some(film) = sensor.children.iter().find(|c| c.name == "film") {
+            let width = film.integer("width")?.unwrap_or(self.resolution.0 as i64);
+            let height = film.integer("height")?.unwrap_or(self.resolution.1 as i64);
+            if width <= 0 || height <= 0 {
+                return Err(format!("invalid film size {}x{}", width, height));
+            }
+            self.resolution = (width as u32, height as u32);
+        }
+        if let Some(sampler) = sensor.children.iter().find(|c| c.name == "sampler") {
+            if let Some(count) = sampler.integer("sample_count")? {
+                self.settings.spp = Some(count.max(1) as usize);
+            }
+        }
+
+        self.sensor = Some(MitsubaSensor {
+            to_world: sensor.transform("to_world")?.unwrap_or(Transform::identity()),
+            fov: fov,
+            fov_axis: fov_axis,
+        });
+        Ok(())
+    }
+
+    // BSDFs with an id can be referred to by shapes. Two sided ones are the BSDF they wrap,
+    // as our materials are double sided
+    fn bsdf(&mut self, bsdf: &Element) -> Result<Material, String> {
+        let material = match bsdf.ty() {
+            "twosided" => match self.nested_bsdf(bsdf)? {
+                Some(material) => material,
+                None => mitsuba_material("diffuse", bsdf)?,
+            },
+            ty => {
+                if bsdf.children.iter().any(|c| c.name == "texture") {
+                    self.warn("textures, they aren't supported".to_string());
+                }
+                if !matches!(ty, "diffuse" | "conductor" | "roughconductor" | "dielectric" | "roughdielectric" | "thindielectric" | "plastic" | "roughplastic") {
+                    self.warn(format!("{} BSDFs, using a diffuse one", ty));
+                }
+                if let Some(name) = bsdf.string("material")?.filter(|name| *name != "none" && metal_reflectance(name).is_none()) {
+                    self.warn(format!("conductor material \"{}\", using a perfect mirror", name));
+                }
+                mitsuba_material(ty, bsdf)?
+            },
+        };
+
+        if let Some(id) = bsdf.attr("id") {
+            self.bsdfs.insert(id.to_string(), material);
+        }
+        Ok(material)
+    }
+
+    // The BSDF defined in the element, or referred to by id
+    fn nested_bsdf(&mut self, parent: &Element) -> Result<Option<Material>, String> {
+        for child in &parent.children {
+            match (child.name.as_str(), child.attr("name")) {
+                ("bsdf", _) => return Ok(Some(self.bsdf(child)?)),
+                ("ref", None) | ("ref", Some("bsdf")) => {
+                    let id = child.required("id")?;
+                    return match self.bsdfs.get(id) {
+                        Some(material) => Ok(Some(*material)),
+                        None => Err(format!("unknown BSDF \"{}\"", id)),
+                    };
+                },
+                _ => {},
+            }
+        }
+        Ok(None)
+    }
+
+    // Environment maps are equirectangular, with -Z at their left edge instead of at their center
+    fn emitter(&mut self, emitter: &Element) -> Result<(), String> {
+        let environment = match emitter.ty() {
+            "envmap" => {
+                let file = self.dir.join(emitter.string("filename")?.ok_or("envmap has no filename")?);
+                self.builder.add_source_file(&file);
+                let image = Image::load(&file).map_err(|err| format!("unable to load {}: {}", file.display(), err))?;
+                if image.pixel_count() == 0 {
+                    return Err(format!("{} is empty", file.display()));
+                }
+                let to_world = emitter.transform("to_world")?.unwrap_or(Transform::identity());
+                Environment::Image {
+                    image: image,
+                    strength: emitter.float("scale")?.unwrap_or(1.0),
+                    mapping: EnvironmentMapping::Equirectangular,
+                    to_map: Transform::rotation(Vec3::new(0.0, 1.0, 0.0), 180.0).then(to_world.inverse()),
+                }
+            },
+            "constant" => Environment::Color(emitter.color("radiance")?.unwrap_or(Color::from(1.0))),
+            ty => {
+                self.warn(format!("{} emitters, only area, envmap and constant ones are supported", ty));
+                return Ok(());
+            },
+        };
+
+        if self.environment.replace(environment).is_some() {
+            self.warn("environment emitters after the first one".to_string());
+        }
+        Ok(())
+    }
+
+    fn shape(&mut self, shape: &Element) -> Result<(), SceneError> {
+        let to_world = shape.transform("to_world").map_err(SceneError::Parse)?.unwrap_or(Transform::identity());
+        let flip = shape.boolean("flip_normals").map_err(SceneError::Parse)?.unwrap_or(false);
+        // OBJ meshes keep the normals of their file
+        let face_normals = shape.boolean("face_normals").map_err(SceneError::Parse)?.unwrap_or(false);
+        let normal_generation = if face_normals { NormalGeneration::Flat } else { self.normal_generation };
+
+        let mut material = match self.nested_bsdf(shape).map_err(SceneError::Parse)? {
+            Some(material) => material,
+            None => mitsuba_material("diffuse", shape).map_err(SceneError::Parse)?,
+        };
+        for emitter in shape.children.iter().filter(|c| c.name == "emitter") {
+            if emitter.ty() == "area" {
+                material.emissive = emitter.color("radiance").map_err(SceneError::Parse)?.unwrap_or(Color::from(1.0));
+                // Area lights only emit from their front side
+                material.one_sided_emission = true;
+            } else {
+                self.warn(format!("{} emitters on shapes", emitter.ty()));
+            }
+        }
+
+        let (positions, normals, triangles) = match shape.ty() {
+            "obj" => {
+                let file = self.file(shape)?;
+                let mut included = SceneBuilder::new();
+                import_obj(&file, &mut included, normal_generation).map_err(|err| match err {
+                    SceneError::Io(err) => SceneError::Parse(format!("unable to read {}: {}", file.display(), err)),
+                    err => err,
+                })?;
+                self.builder.add_source_files(included.take_source_files());
+                for mesh in included.take_objects() {
+                    let mesh = mesh.with_material(material).transformed(&to_world);
+                    self.builder.push(if flip { mesh.flipped() } else { mesh });
+                }
+                return Ok(());
+            },
+            "ply" => {
+                let file = self.file(shape)?;
+                self.builder.add_source_file(&file);
+                let ply = read_ply(&file).map_err(|err| match err {
+                    SceneError::Io(err) => SceneError::Parse(format!("unable to read {}: {}", file.display(), err)),
+                    err => err,
+                })?;
+                let normals = if face_normals { None } else { ply.normals };
+                (ply.positions, normals, ply.triangles)
+            },
+            // The [-1, 1] square of the XY plane, facing +Z
+            "rectangle" => {
+                let positions = vec![Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0)];
+                (positions, Some(vec![Vec3::new(0.0, 0.0, 1.0); 4]), vec![[0, 1, 2], [0, 2, 3]])
+            },
+            "sphere" => {
+                let center = shape.point("center").map_err(SceneError::Parse)?.unwrap_or(Vec3::zero());
+                let radius = shape.float("radius").map_err(SceneError::Parse)?.unwrap_or(1.0);
+                let (normals, triangles) = uv_sphere();
+                let positions = normals.iter().map(|n| center + *n * radius).collect();
+                (positions, Some(normals), triangles)
+            },
+            ty => {
+                self.warn(format!("{} shapes, only obj, ply, rectangle and sphere ones are supported", ty));
+                return Ok(());
+            },
+        };
+
+        // Like Mesh::transformed, with flip_normals turning the surface around
+        let positions = positions.iter().map(|p| to_world.transform_pos(*p)).collect();
+        let sign = if flip { -1.0 } else { 1.0 };
+        let normals = normals.map(|normals| normals.iter().map(|n| to_world.transform_normal(*n).normalized() * sign).collect());
+        let triangles = if (to_world.determinant() < 0.0) != flip {
+            triangles.iter().map(|tri| [tri[0], tri[2], tri[1]]).collect()
+        } else {
+            triangles
+        };
+
+        match build_mesh(positions, normals, None, None, triangles, material, normal_generation) {
+            Ok(Some(mesh)) => self.builder.push(mesh),
+            Ok(None) => {},
+            Err(reason) => return Err(SceneError::InvalidGeometry { location: format!("{} shape", shape.ty()), reason: reason }),
+        }
+        Ok(())
+    }
+
+    fn file(&self, shape: &Element) -> Result<PathBuf, SceneError> {
+        match shape.string("filename").map_err(SceneError::Parse)? {
+            Some(filename) => Ok(self.dir.join(filename)),
+            None => Err(SceneError::Parse(format!("{} shape has no filename", shape.ty()))),
+        }
+    }
+
+    fn finish(self) {
+        let (width, height) = self.resolution;
+        let ratio = width as f32 / height as f32;
+
+        // Mitsuba's sensors look down +Z with +Y up, and +X on the left of the image
+        if let Some(sensor) = self.sensor {
+            let tan_half_fov = (sensor.fov.to_radians() * 0.5).tan();
+            let tan_half_vfov = match sensor.fov_axis.as_str() {
+                "diagonal" => tan_half_fov / (1.0 + ratio * ratio).sqrt(),
+                "y" => tan_half_fov,
+                "smaller" if ratio >= 1.0 => tan_half_fov,
+                "larger" if ratio < 1.0 => tan_half_fov,
+                _ => tan_half_fov / ratio,
+            };
+
+            let [x, y, z] = *sensor.to_world.basis();
+            let transform = Transform::from_basis(-x.normalized(), y.normalized(), -z.normalized()).with_pos(sensor.to_world.position());
+            self.builder.set_camera(Camera::new(transform, 2.0 * tan_half_vfov.atan(), ratio));
+        } else {
+            self.builder.set_camera(Camera::default().with_ratio(ratio));
+        }
+
+        if let Some(environment) = self.environment {
+            self.builder.set_environment(environment);
+        }
+        self.builder.set_render_settings(RenderSettings { resolution: Some(self.resolution), ..self.settings });
+    }
+}
+
+// The material Mitsuba's BSDF would give, with its default values. Rough BSDFs use alpha as is,
+// without their distribution, and plastics lose their coating, as our diffuse lobe has none
+fn mitsuba_material(ty: &str, bsdf: &Element) -> Result<Material, String> {
+    let alpha = match (bsdf.float("alpha")?, bsdf.float("alpha_u")?, bsdf.float("alpha_v")?) {
+        (Some(alpha), _, _) => alpha,
+        (None, Some(u), Some(v)) => (u + v) * 0.5,
+        _ if ty.starts_with("rough") => 0.1,
+        _ => 0.0,
+    };
+    // Our alpha is the square of our roughness
+    let roughness = alpha.max(0.0).sqrt();
+
+    let diffuse = |name| -> Result<Material, String> {
+        Ok(Material {
+            roughness: 1.0,
+            color: bsdf.color(name)?.unwrap_or(Color::from(0.5)),
+            double_sided: true,
+            ..Material::default()
+        })
+    };
+
+    match ty {
+        "conductor" | "roughconductor" => {
+            let f0 = match (bsdf.color("eta")?, bsdf.color("k")?) {
+                (Some(eta), Some(k)) => conductor_f0(eta, k),
+                _ => bsdf.string("material")?.and_then(metal_reflectance).unwrap_or(Color::from(1.0)),
+            };
+            Ok(Material {
+                roughness: roughness,
+                metallic: 1.0,
+                color: f0 * bsdf.color("specular_reflectance")?.unwrap_or(Color::from(1.0)),
+                double_sided: true,
+                ..Material::default()
+            })
+        },
+        "dielectric" | "roughdielectric" | "thindielectric" => Ok(Material {
+            roughness: roughness,
+            color: Color::from(1.0),
+            transmission: 1.0,
+            ior: ior(bsdf, "int_ior", 1.5046)? / ior(bsdf, "ext_ior", 1.000277)?,
+            double_sided: true,
+            ..Material::default()
+        }),
+        "plastic" | "roughplastic" => diffuse("diffuse_reflectance"),
+        _ => diffuse("reflectance"),
+    }
+}
+
+// Indices of refraction are numbers, or the names of Mitsuba's tabulated materials
+fn ior(bsdf: &Element, name: &str, default: f32) -> Result<f32, String> {
+    let named = match bsdf.property(name) {
+        Some(property) if property.name == "string" => property.value()?,
+        Some(_) => return Ok(bsdf.float(name)?.unwrap_or(default)),
+        None => return Ok(default),
+    };
+
+    match named {
+        "vacuum" => Ok(1.0),
+        "air" => Ok(1.000277),
+        "water" => Ok(1.3330),
+        "water ice" => Ok(1.31),
+        "fused quartz" => Ok(1.458),
+        "pyrex" => Ok(1.470),
+        "acrylic glass" | "polypropylene" => Ok(1.49),
+        "bk7" => Ok(1.5046),
+        "sodium chloride" => Ok(1.544),
+        "amber" => Ok(1.55),
+        "pet" => Ok(1.5750),
+        "diamond" => Ok(2.419),
+        _ => Err(format!("unknown index of refraction \"{}\"", named)),
+    }
+}
+
+// Mitsuba's lookat places things at origin, with +Z towards target and +Y up
+fn look_at(origin: Vec3, target: Vec3, up: Vec3) -> Result<Transform, String> {
+    let dir = (target - origin).normalized();
+    let left = up.normalized().cross(dir).normalized();
+    if left.length().is_nan() {
+        return Err("lookat with an up vector along the view direction".to_string());
+    }
+    Ok(Transform::from_basis(left, dir.cross(left), dir).with_pos(origin))
+}
+
+// Lists of numbers are separated by commas or spaces
+fn parse_numbers(text: &str) -> Result<Vec<f32>, String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<f32>().map_err(|_| format!("invalid number \"{}\"", s)))
+        .collect()
+}
+
+fn parse_vec3(text: &str) -> Result<Vec3, String> {
+    match parse_numbers(text)?.as_slice() {
+        [x, y, z] => Ok(Vec3::new(*x, *y, *z)),
+        _ => Err(format!("expected 3 numbers, got \"{}\"", text)),
+    }
+}
+
+// Vectors are a value, which can be a single number for all three, or x, y and z attributes
+fn parse_vector(element: &Element, default: f32) -> Result<Vec3, String> {
+    if let Some(value) = element.attr("value") {
+        return match parse_numbers(value)?.as_slice() {
+            [v] => Ok(Vec3::from(*v)),
+            [x, y, z] => Ok(Vec3::new(*x, *y, *z)),
+            _ => Err(format!("<{}> must have 1 or 3 numbers", element.name)),
+        };
+    }
+    let number = |name| match element.attr(name) {
+        Some(v) => v.trim().parse::<f32>().map_err(|_| format!("invalid number \"{}\"", v)),
+        None => Ok(default),
+    };
+    Ok(Vec3::new(number("x")?, number("y")?, number("z")?))
+}
+
+// The operations of a transform apply one after the other
+fn parse_transform(transform: &Element) -> Result<Transform, String> {
+    let mut result = Transform::identity();
+
+    for op in &transform.children {
+        let step = match op.name.as_str() {
+            "translate" => Transform::translation(parse_vector(op, 0.0)?),
+            "scale" => Transform::scaling(parse_vector(op, 1.0)?),
+            "rotate" => {
+                let axis = parse_vector(op, 0.0)?;
+                if axis.length() == 0.0 {
+                    return Err("<rotate> has no axis".to_string());
+                }
+                let angle = op.required("angle")?;
+                Transform::rotation(axis, angle.trim().parse::<f32>().map_err(|_| format!("invalid angle \"{}\"", angle))?)
+            },
+            // Row by row, the translation is the last column
+            "matrix" => {
+                let m = parse_numbers(op.required("value")?)?;
+                match m.len() {
+                    16 => Transform::from_basis(Vec3::new(m[0], m[4], m[8]), Vec3::new(m[1], m[5], m[9]), Vec3::new(m[2], m[6], m[10]))
+                        .with_pos(Vec3::new(m[3], m[7], m[11])),
+                    9 => Transform::from_basis(Vec3::new(m[0], m[3], m[6]), Vec3::new(m[1], m[4], m[7]), Vec3::new(m[2], m[5], m[8])),
+                    n => return Err(format!("<matrix> must have 9 or 16 numbers, got {}", n)),
+                }
+            },
+            "lookat" => {
+                let up = match op.attr("up") {
+                    Some(up) => parse_vec3(up)?,
+                    None => Vec3::new(0.0, 1.0, 0.0),
+                };
+                look_at(parse_vec3(op.required("origin")?)?, parse_vec3(op.required("target")?)?, up)?
+            },
+            name => return Err(format!("unexpected <{}> in a transform", name)),
+        };
+        result = step.then(result);
+    }
+
+    Ok(result)
+}
+
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn required(&self, name: &str) -> Result<&str, String> {
+        self.attr(name).ok_or_else(|| format!("<{}> has no {} attribute", self.name, name))
+    }
+
+    fn ty(&self) -> &str {
+        self.attr("type").unwrap_or("")
+    }
+
+    fn value(&self) -> Result<&str, String> {
+        self.required("value")
+    }
+
+    // Replaces $name in attribute values by the default value of name
+    fn substituted(&self, defaults: &[(String, String)]) -> Element {
+        let substitute = |value: &str| defaults.iter().fold(value.to_string(), |value, (name, default)| value.replace(&format!("${}", name), default));
+        Element {
+            name: self.name.clone(),
+            attributes: self.attributes.iter().map(|(n, v)| (n.clone(), substitute(v))).collect(),
+            children: self.children.iter().map(|c| c.substituted(defaults)).collect(),
+            line: self.line,
+        }
+    }
+
+    // The child element giving a value to a parameter of the plugin
+    fn property(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.attr("name") == Some(name))
+    }
+
+    // The property, checked to be one of the given types
+    fn typed_property(&self, name: &str, types: &[&str]) -> Result<Option<&Element>, String> {
+        match self.property(name) {
+            Some(property) if types.contains(&property.name.as_str()) => Ok(Some(property)),
+            Some(property) => Err(format!("\"{}\" must be {}, not <{}>", name, types.join(" or "), property.name)),
+            None => Ok(None),
+        }
+    }
+
+    fn float(&self, name: &str) -> Result<Option<f32>, String> {
+        match self.typed_property(name, &["float", "integer"])? {
+            Some(property) => {
+                let value = property.value()?;
+                value.trim().parse::<f32>().map(Some).map_err(|_| format!("\"{}\": invalid number \"{}\"", name, value))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn integer(&self, name: &str) -> Result<Option<i64>, String> {
+        match self.typed_property(name, &["integer"])? {
+            Some(property) => {
+                let value = property.value()?;
+                value.trim().parse::<i64>().map(Some).map_err(|_| format!("\"{}\": invalid integer \"{}\"", name, value))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn boolean(&self, name: &str) -> Result<Option<bool>, String> {
+        match self.typed_property(name, &["boolean"])? {
+            Some(property) => match property.value()? {
+                "true" => Ok(Some(true)),
+                "false" => Ok(Some(false)),
+                value => Err(format!("\"{}\": expected true or false, got \"{}\"", name, value)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn string(&self, name: &str) -> Result<Option<&str>, String> {
+        match self.typed_property(name, &["string"])? {
+            Some(property) => Ok(Some(property.value()?)),
+            None => Ok(None),
+        }
+    }
+
+    fn point(&self, name: &str) -> Result<Option<Vec3>, String> {
+        match self.typed_property(name, &["point", "vector"])? {
+            Some(property) => Ok(Some(parse_vector(property, 0.0)?)),
+            None => Ok(None),
+        }
+    }
+
+    // RGB values, and spectra, which are averaged into a grey. Textures and spectrum files give None
+    fn color(&self, name: &str) -> Result<Option<Color>, String> {
+        let property = match self.property(name) {
+            Some(property) => property,
+            None => return Ok(None),
+        };
+
+        match property.name.as_str() {
+            "rgb" => match parse_numbers(property.value()?)?.as_slice() {
+                [v] => Ok(Some(Color::from(*v))),
+                [r, g, b] => Ok(Some(Color::new(*r, *g, *b))),
+                _ => Err(format!("\"{}\" must have 1 or 3 numbers", name)),
+            },
+            "float" => Ok(self.float(name)?.map(Color::from)),
+            "spectrum" if property.attr("value").is_some() => {
+                let value = property.value()?;
+                if !value.contains(':') {
+                    return Ok(Some(Color::from(value.trim().parse::<f32>().map_err(|_| format!("\"{}\": invalid number \"{}\"", name, value))?)));
+                }
+                // Wavelength:value pairs
+                let values = value.split(',').map(|pair| match pair.split_once(':') {
+                    Some((_, v)) => v.trim().parse::<f32>().map_err(|_| format!("\"{}\": invalid value \"{}\"", name, pair)),
+                    None => Err(format!("\"{}\": expected a wavelength:value pair, got \"{}\"", name, pair)),
+                }).collect::<Result<Vec<_>, _>>()?;
+                Ok(Some(Color::from(values.iter().sum::<f32>() / values.len() as f32)))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn transform(&self, name: &str) -> Result<Option<Transform>, String> {
+        match self.typed_property(name, &["transform"])? {
+            Some(property) => Ok(Some(parse_transform(property)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+
+// Elements and their attributes, with entities and character references resolved.
+// Text, CDATA sections, comments, processing instructions and DOCTYPEs are dropped
+fn parse_xml(text: &str) -> Result<Element, (usize, String)> {
+    let options = roxmltree::ParsingOptions { allow_dtd: true, ..roxmltree::ParsingOptions::default() };
+    let document = roxmltree::Document::parse_with_options(text, options).map_err(|err| {
+        // The message ends with the position, which is already in front of it
+        let msg = err.to_string();
+        (err.pos().row as usize, msg.trim_end_matches(&format!(" at {}", err.pos())).to_string())
+    })?;
+    Ok(to_element(&document, document.root_element()))
+}
+
+fn to_element(document: &roxmltree::Document, node: roxmltree::Node) -> Element {
+    Element {
+        name: node.tag_name().name().to_string(),
+        attributes: node.attributes().map(|attr| (attr.name().to_string(), attr.value().to_string())).collect(),
+        children: node.children().filter(|child| child.is_element()).map(|child| to_element(document, child)).collect(),
+        line: document.text_pos_at(node.range().start).row as usize,
+    }
+}
@@ -0,0 +1,194 @@
+// Imports Mitsuba 3 scenes: sensor and integrator settings, emitters and environment maps, BSDFs, referenced meshes, and the errors of malformed XML.
+
+use crate::scene::*;
+use crate::normals::*;
+use crate::surface::*;
+use crate::color::*;
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::integrator::*;
+use crate::image::*;
+use crate::ply_tests::ply;
+use crate::test_files::*;
+
+
+const MITSUBA: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<!-- Every supported kind of shape, and a cube which isn't -->
+<scene version="3.0.0">
+    <default name="spp" value="32"/>
+    <default name="width" value="200"/>
+
+    <integrator type="path">
+        <integer name="max_depth" value="4"/>
+    </integrator>
+
+    <sensor type="perspective">
+        <float name="fov" value="90"/>
+        <transform name="to_world">
+            <lookat origin="0, 0, 5" target="0, 0, 0" up="0, 1, 0"/>
+        </transform>
+        <sampler type="independent">
+            <integer name="sample_count" value="$spp"/>
+        </sampler>
+        <film type="hdrfilm">
+            <integer name="width" value="$width"/>
+            <integer name="height" value="100"/>
+        </film>
+    </sensor>
+
+    <bsdf type="twosided" id="gold">
+        <bsdf type="roughconductor">
+            <string name="material" value="Au"/>
+            <float name="alpha" value="0.04"/>
+        </bsdf>
+    </bsdf>
+
+    <emitter type="constant">
+        <rgb name="radiance" value="0.1, 0.2, 0.3"/>
+    </emitter>
+
+    <shape type="rectangle">
+        <transform name="to_world">
+            <rotate x="1" angle="90"/>
+            <translate y="2"/>
+        </transform>
+        <emitter type="area">
+            <spectrum name="radiance" value="4"/>
+        </emitter>
+    </shape>
+
+    <shape type="ply">
+        <string name="filename" value="quad.ply"/>
+        <ref id="gold"/>
+        <transform name="to_world">
+            <scale value="2"/>
+        </transform>
+    </shape>
+
+    <shape type="obj">
+        <string name="filename" value="triangle.obj"/>
+        <boolean name="flip_normals" value="true"/>
+        <bsdf type="dielectric">
+            <string name="int_ior" value="water"/>
+        </bsdf>
+    </shape>
+
+    <shape type="sphere">
+        <point name="center" x="0" y="0" z="-3"/>
+        <float name="radius" value="0.5"/>
+        <bsdf type="roughplastic">
+            <rgb name="diffuse_reflectance" value="0.2, 0.4, 0.6"/>
+        </bsdf>
+    </shape>
+
+    <shape type="cube"/>
+</scene>
+"#;
+
+#[test]
+fn mitsuba_scene() {
+    let path = write_temp("xml", MITSUBA);
+    write_next_to(&path, "quad.ply", ply("ascii"));
+    write_next_to(&path, "triangle.obj", "v -1 -1 0\nv 1 -1 0\nv 0 1 0\nf 1 2 3\n");
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+
+    assert_eq!(scene.settings(), RenderSettings {
+        spp: Some(32),
+        max_bounces: Some(3),
+        resolution: Some((200, 100)),
+        integrator: Some(IntegratorKind::Path),
+    });
+    assert_eq!(scene.environment().radiance(Vec3::new(0.0, 1.0, 0.0)), Color::new(0.1, 0.2, 0.3));
+
+    // The fov is horizontal, and Mitsuba's images aren't mirrored
+    let camera = scene.camera();
+    assert!(camera.position().distance(Vec3::new(0.0, 0.0, 5.0)) < 1e-5);
+    assert!(camera.forward().distance(Vec3::new(0.0, 0.0, -1.0)) < 1e-5);
+    assert!(camera.right().distance(Vec3::new(1.0, 0.0, 0.0)) < 1e-5);
+    assert_eq!(camera.ratio(), 2.0);
+    let edge = camera.generate_ray(1.0, 0.5).dir;
+    assert!((edge.x + edge.z).abs() < 1e-5);
+
+    // The cube is skipped
+    let objects = scene.objects();
+    assert_eq!(objects.len(), 4);
+
+    // The area light faces down
+    assert!((objects[0].area() - 4.0).abs() < 1e-5);
+    assert_eq!(objects[0].material().emissive, Color::from(4.0));
+    let hit = (&objects[0]).hit(Ray::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0))).unwrap();
+    assert!(hit.front_face);
+    let hit = (&objects[0]).hit(Ray::new(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0))).unwrap();
+    assert!(hit.material().unwrap().emission(hit.front_face).is_zero());
+
+    let gold = objects[1].material();
+    assert_eq!(objects[1].area(), 16.0);
+    assert_eq!(gold.metallic, 1.0);
+    assert!(gold.color.r > gold.color.g && gold.color.g > gold.color.b);
+    assert!((gold.roughness - 0.2).abs() < 1e-6);
+
+    // flip_normals turns the triangle to -Z
+    let water = objects[2].material();
+    assert!((water.ior - 1.333 / 1.000277).abs() < 1e-6);
+    assert_eq!(water.transmission, 1.0);
+    let hit = (&objects[2]).hit(Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0))).unwrap();
+    assert!(hit.front_face);
+
+    // Plastics keep their diffuse base
+    assert_eq!(objects[3].material().color, Color::new(0.2, 0.4, 0.6));
+    assert!((objects[3].area() - std::f32::consts::PI).abs() < 0.05);
+}
+
+#[test]
+fn mitsuba_envmap() {
+    // Columns of increasing radiance, rotated a quarter turn around +Y
+    let pixels = (0..8).map(|i| Color::from((i % 4 + 1) as f32)).collect();
+    let path = write_temp("xml", r#"
+        <scene version="3.0.0">
+            <emitter type="envmap">
+                <string name="filename" value="sky.exr"/>
+                <float name="scale" value="2"/>
+                <transform name="to_world">
+                    <rotate y="1" angle="90"/>
+                </transform>
+            </emitter>
+            <shape type="rectangle"/>
+        </scene>"#);
+    Image::new(4, 2, pixels).save(path.with_file_name("sky.exr")).unwrap();
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+
+    // Mitsuba puts +X at u = 0.25, +Z at u = 0.5 and -X at u = 0.75, before the rotation
+    let environment = scene.environment();
+    assert_eq!(environment.radiance(Vec3::new(0.0, 0.0, -1.0)), Color::from(4.0));
+    assert_eq!(environment.radiance(Vec3::new(1.0, 0.0, 0.0)), Color::from(6.0));
+    assert_eq!(environment.radiance(Vec3::new(0.0, 0.0, 1.0)), Color::from(8.0));
+}
+
+#[test]
+fn mitsuba_doctype_and_cdata() {
+    let path = write_temp("xml", "<?xml version=\"1.0\"?>\n\
+        <!DOCTYPE scene [ <!ENTITY radius \"2\"> ]>\n\
+        <scene version=\"3.0.0\">\n\
+            <shape type=\"sphere\"><![CDATA[ <not> an element ]]><float name=\"radius\" value=\"&radius;\"/></shape>\n\
+        </scene>\n");
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    let sphere_area = 4.0 * std::f32::consts::PI * 2.0 * 2.0;
+    assert!(scene.objects()[0].area() < sphere_area && scene.objects()[0].area() > sphere_area * 0.98);
+}
+
+#[test]
+fn mitsuba_errors() {
+    let error = |name: &str, xml: &str| match import_scene(write_temp("xml", xml), NormalGeneration::Flat) {
+        Err(err) => err.to_string(),
+        Ok(_) => panic!("{} should be rejected", name),
+    };
+
+    assert!(error("mitsuba_unclosed", "<scene version=\"3.0.0\">\n<shape type=\"sphere\">\n</scene>\n").ends_with(":3: expected 'shape' tag, not 'scene'"));
+    assert!(error("mitsuba_element", "<scene version=\"3.0.0\">\n\n<camera/>\n</scene>\n").ends_with(":3: unexpected <camera>"));
+    assert!(error("mitsuba_ref", "<scene version=\"3.0.0\">\n<shape type=\"sphere\"><ref id=\"missing\"/></shape>\n</scene>\n").ends_with(":2: unknown BSDF \"missing\""));
+    assert!(error("mitsuba_version", "<scene version=\"0.6.0\"/>").contains("must be upgraded"));
+    assert!(error("mitsuba_float", "<scene version=\"3.0.0\"><shape type=\"sphere\"><float name=\"radius\" value=\"$r\"/></shape></scene>").contains("invalid number \"$r\""));
+    assert!(error("mitsuba_include", "<scene version=\"3.0.0\"><include filename=\"missing.xml\"/></scene>").contains("unable to read"));
+    assert!(error("mitsuba_cycle", "<scene version=\"3.0.0\"><include filename=\"scene.xml\"/></scene>").ends_with("scene.xml includes itself"));
+}
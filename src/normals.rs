@@ -0,0 +1,85 @@
+use crate::vec::*;
+use crate::vertex::*;
+
+use std::collections::HashMap;
+
+
+// How normals are generated for meshes that don't have any
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalGeneration {
+    Flat,
+    Smooth,
+}
+
+
+// Also returns the input vertex each output vertex comes from, so that other vertex attributes can follow
+pub fn generate_normals(positions: &[Vec3], triangles: &[[u32; 3]], generation: NormalGeneration) -> (Vec<Vertex>, Vec<[u32; 3]>, Vec<u32>) {
+    match generation {
+        NormalGeneration::Flat => flat_normals(positions, triangles),
+        NormalGeneration::Smooth => (smooth_normals(positions, triangles), triangles.to_vec(), (0..positions.len() as u32).collect()),
+    }
+}
+
+// Normal of the triangle's plane, on the side it is counter clockwise from. None if the triangle is degenerate
+pub fn face_normal(p: [Vec3; 3]) -> Option<Vec3> {
+    let cross = (p[1] - p[0]).cross(p[2] - p[0]);
+    let len = cross.length();
+    if len > 0.0 && len.is_finite() {
+        Some(cross / len)
+    } else {
+        None
+    }
+}
+
+// Every triangle gets its own vertices, with its face normal. Degenerate triangles can't be hit and are dropped
+pub fn flat_normals(positions: &[Vec3], triangles: &[[u32; 3]]) -> (Vec<Vertex>, Vec<[u32; 3]>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(triangles.len() * 3);
+    let mut flat_triangles = Vec::with_capacity(triangles.len());
+    let mut sources = Vec::with_capacity(triangles.len() * 3);
+
+    for tri in triangles {
+        let p = tri.map(|i| positions[i as usize]);
+        if let Some(norm) = face_normal(p) {
+            let base = vertices.len() as u32;
+            vertices.extend(p.iter().map(|pos| Vertex { pos: *pos, norm: norm }));
+            flat_triangles.push([base, base + 1, base + 2]);
+            sources.extend_from_slice(tri);
+        }
+    }
+
+    (vertices, flat_triangles, sources)
+}
+
+// Face normals averaged around each vertex, weighted by the angle of the triangle at that vertex (Thürmer and Wüthrich 1998).
+// Vertices sharing a position are welded first, so that meshes split along seams or without indices are smooth too
+pub fn smooth_normals(positions: &[Vec3], triangles: &[[u32; 3]]) -> Vec<Vertex> {
+    // Adding 0 turns -0 into +0, so both get the same bits
+    let key = |p: Vec3| [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()];
+
+    let mut welded = HashMap::new();
+    let welded_index = positions.iter().map(|p| {
+        let next = welded.len();
+        *welded.entry(key(*p)).or_insert(next)
+    }).collect::<Vec<_>>();
+
+    let mut normals = vec![Vec3::zero(); welded.len()];
+    for tri in triangles {
+        let p = tri.map(|i| positions[i as usize]);
+        if let Some(norm) = face_normal(p) {
+            for k in 0..3 {
+                let e1 = p[(k + 1) % 3] - p[k];
+                let e2 = p[(k + 2) % 3] - p[k];
+                let angle = e1.cross(e2).length().atan2(e1.dot(e2));
+                let w = welded_index[tri[k] as usize];
+                normals[w] = normals[w] + norm * angle;
+            }
+        }
+    }
+
+    positions.iter().zip(welded_index).map(|(pos, w)| {
+        // Only vertices of degenerate triangles are left without a normal, and those can't be hit
+        let len = normals[w].length();
+        let norm = if len > 0.0 { normals[w] / len } else { Vec3::new(0.0, 1.0, 0.0) };
+        Vertex { pos: *pos, norm: norm }
+    }).collect()
+}
@@ -0,0 +1,134 @@
+// Checks the generated normals of a cube, in both modes and with and without shared vertices.
+
+use crate::vec::*;
+use crate::vertex::*;
+use crate::normals::*;
+
+use std::collections::HashSet;
+
+
+const EPSILON: f32 = 1e-6;
+
+
+// Unit cube around the origin with 8 shared corners, two counter clockwise triangles per face seen from outside
+fn cube() -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    let positions = (0..8).map(|i| Vec3::new(
+        if i & 1 != 0 { 1.0 } else { -1.0 },
+        if i & 2 != 0 { 1.0 } else { -1.0 },
+        if i & 4 != 0 { 1.0 } else { -1.0 },
+    )).collect();
+
+    let faces: [[u32; 4]; 6] = [
+        [1, 3, 7, 5], [0, 4, 6, 2],
+        [2, 6, 7, 3], [0, 1, 5, 4],
+        [4, 5, 7, 6], [0, 2, 3, 1],
+    ];
+    let triangles = faces.iter().flat_map(|f| [[f[0], f[1], f[2]], [f[0], f[2], f[3]]]).collect();
+
+    (positions, triangles)
+}
+
+// The same cube with every triangle using its own 3 vertices, like a non indexed glTF primitive
+fn unindexed_cube() -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    let (positions, triangles) = cube();
+    let positions = triangles.iter().flatten().map(|i| positions[*i as usize]).collect::<Vec<_>>();
+    let triangles = (0..positions.len() as u32 / 3).map(|t| [t * 3, t * 3 + 1, t * 3 + 2]).collect();
+    (positions, triangles)
+}
+
+fn close(a: Vec3, b: Vec3) -> bool {
+    (a - b).length() < EPSILON
+}
+
+// Normals are unit vectors, and every triangle is counter clockwise around them
+fn check_facing(vertices: &[Vertex], triangles: &[[u32; 3]]) {
+    for tri in triangles {
+        let p = tri.map(|i| vertices[i as usize].pos);
+        let face = face_normal(p).unwrap();
+        for i in tri {
+            let norm = vertices[*i as usize].norm;
+            assert!((norm.length() - 1.0).abs() < EPSILON);
+            assert!(norm.dot(face) > 0.0, "{:?} faces away from {:?}", norm, face);
+        }
+    }
+}
+
+
+#[test]
+fn flat_cube() {
+    let (positions, triangles) = cube();
+    let (vertices, flat_triangles, sources) = generate_normals(&positions, &triangles, NormalGeneration::Flat);
+
+    assert_eq!(flat_triangles.len(), 12);
+    assert_eq!(vertices.len(), 36);
+    check_facing(&vertices, &flat_triangles);
+
+    // Every corner is on 3 faces, so there are 24 different vertices once the ones of a same face are merged
+    let key = |v: &Vertex| [v.pos.x, v.pos.y, v.pos.z, v.norm.x, v.norm.y, v.norm.z].map(f32::to_bits);
+    assert_eq!(vertices.iter().map(key).collect::<HashSet<_>>().len(), 24);
+
+    for (vertex, source) in vertices.iter().zip(&sources) {
+        assert_eq!(vertex.pos, positions[*source as usize]);
+        // Axis aligned, pointing out of the cube
+        let norm = vertex.norm;
+        assert_eq!(norm.x.abs() + norm.y.abs() + norm.z.abs(), 1.0);
+        assert_eq!(norm.dot(vertex.pos), 1.0);
+    }
+}
+
+#[test]
+fn flat_drops_degenerate_triangles() {
+    let positions = vec![Vec3::zero(), Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+    let (vertices, triangles, sources) = generate_normals(&positions, &[[0, 1, 2], [0, 1, 3]], NormalGeneration::Flat);
+
+    assert_eq!(triangles, vec![[0, 1, 2]]);
+    assert_eq!(sources, vec![0, 1, 3]);
+    assert!(vertices.iter().all(|v| close(v.norm, Vec3::new(0.0, 0.0, 1.0))));
+}
+
+// Each corner of the cube has a 90 degree angle on each of its 3 faces, whether the face's diagonal
+// splits that angle or not, so the angle weighted normal is the diagonal
+#[test]
+fn smooth_cube() {
+    for (name, (positions, triangles)) in [("indexed", cube()), ("unindexed", unindexed_cube())] {
+        let (vertices, smooth_triangles, sources) = generate_normals(&positions, &triangles, NormalGeneration::Smooth);
+
+        assert_eq!(smooth_triangles, triangles, "{}", name);
+        assert_eq!(sources, (0..positions.len() as u32).collect::<Vec<_>>(), "{}", name);
+        for vertex in &vertices {
+            assert!(close(vertex.norm, vertex.pos.normalized()), "{}: {:?} at {:?}", name, vertex.norm, vertex.pos);
+        }
+    }
+}
+
+// Both triangles of a flat quad, sharing its diagonal, get the quad's normal
+#[test]
+fn smooth_quad() {
+    let positions = vec![Vec3::zero(), Vec3::new(2.0, 0.0, 0.0), Vec3::new(2.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+    let triangles = vec![[0, 1, 2], [0, 2, 3]];
+    let (vertices, _, _) = generate_normals(&positions, &triangles, NormalGeneration::Smooth);
+
+    assert_eq!(vertices.len(), 4);
+    check_facing(&vertices, &triangles);
+    assert!(vertices.iter().all(|v| close(v.norm, Vec3::new(0.0, 0.0, 1.0))));
+}
+
+// A fold between two quads at 90 degrees: the shared edge gets the average of both faces
+#[test]
+fn smooth_fold() {
+    let positions = vec![
+        Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 1.0),
+        Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 1.0),
+    ];
+    // Facing +Y on the floor and +X on the wall
+    let triangles = vec![[0, 1, 3], [0, 3, 2], [0, 4, 5], [0, 5, 1]];
+    let (vertices, _, _) = generate_normals(&positions, &triangles, NormalGeneration::Smooth);
+
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let side = Vec3::new(1.0, 0.0, 0.0);
+    assert!(close(vertices[2].norm, up) && close(vertices[3].norm, up));
+    assert!(close(vertices[4].norm, side) && close(vertices[5].norm, side));
+    assert!(close(vertices[0].norm, (up + side).normalized()));
+    assert!(close(vertices[1].norm, (up + side).normalized()));
+}
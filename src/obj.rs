@@ -0,0 +1,357 @@
+use crate::vec::*;
+use crate::color::*;
+use crate::material::*;
+use crate::scene::*;
+use crate::normals::*;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+
+// Index of a face corner's position, texture coordinates and normal
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// Faces of one object or group that use the same material.
+// OBJ indexes positions, texture coordinates and normals separately, so every distinct triple becomes a vertex
+struct ObjMesh {
+    name: String,
+    material: String,
+    positions: Vec<Vec3>,
+    uvs: Vec<[f32; 2]>,
+    all_uvs: bool,
+    normals: Vec<Vec3>,
+    all_normals: bool,
+    triangles: Vec<[u32; 3]>,
+    vertex_ids: HashMap<Corner, u32>,
+}
+
+// The MTL parameters we map onto Material
+#[derive(Default)]
+struct MtlMaterial {
+    diffuse: Option<Color>,
+    specular: Option<Color>,
+    shininess: Option<f32>,
+    emissive: Option<Color>,
+    ior: Option<f32>,
+    transmission_filter: Option<Color>,
+    illum: Option<u32>,
+    roughness: Option<f32>,
+    metallic: Option<f32>,
+}
+
+
+// Reads positions, normals, texture coordinates, objects and groups, and polygons, which are triangulated as fans.
+// Lines and points are skipped
+pub fn import_obj(path: &Path, builder: &mut SceneBuilder, normal_generation: NormalGeneration) -> Result<(), SceneError> {
+    builder.add_source_file(path);
+    let text = std::fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut materials = HashMap::new();
+    let mut meshes = Vec::new();
+    let mut current = ObjMesh::new("default", "");
+    let mut skipped = 0;
+
+    for (line_number, line) in logical_lines(&text) {
+        let parse_error = |msg: String| SceneError::Parse(format!("{}:{}: {}", path.display(), line_number, msg));
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let rest = line.trim()[keyword.len()..].trim();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(tokens).map_err(parse_error)?),
+            "vn" => normals.push(parse_vec3(tokens).map_err(parse_error)?),
+            // The optional w is ignored, and v is 0 if it is missing too
+            "vt" => {
+                let uv = parse_floats(tokens, 1, 3).map_err(parse_error)?;
+                uvs.push([uv[0], uv.get(1).copied().unwrap_or(0.0)]);
+            },
+
+            "f" => {
+                let mut corners = Vec::new();
+                for corner in tokens {
+                    let mut indices = corner.split('/');
+                    let pos = resolve_index(indices.next(), positions.len()).map_err(parse_error)?;
+                    let uv = resolve_index(indices.next(), uvs.len()).map_err(parse_error)?;
+                    let norm = resolve_index(indices.next(), normals.len()).map_err(parse_error)?;
+                    match pos {
+                        Some(pos) => corners.push((pos, uv, norm)),
+                        None => return Err(parse_error(format!("face corner \"{}\" has no position", corner))),
+                    }
+                }
+
+                if corners.len() < 3 {
+                    return Err(parse_error(format!("face with {} vertices", corners.len())));
+                }
+                current.add_polygon(&corners, &positions, &uvs, &normals);
+            },
+
+            "o" | "g" => {
+                let material = current.material.clone();
+                meshes.push(std::mem::replace(&mut current, ObjMesh::new(rest, &material)));
+            },
+
+            "usemtl" => {
+                let name = current.name.clone();
+                meshes.push(std::mem::replace(&mut current, ObjMesh::new(&name, rest)));
+            },
+
+            // Several libraries can be listed, separated by spaces
+            "mtllib" => {
+                for file in tokens {
+                    let mtl_path = dir.join(file);
+                    builder.add_source_file(&mtl_path);
+                    match parse_mtl(&mtl_path) {
+                        Ok(mtl) => materials.extend(mtl),
+                        Err(SceneError::Io(err)) => eprintln!("Unable to read {}: {}, using default materials", mtl_path.display(), err),
+                        Err(err) => return Err(err),
+                    }
+                }
+            },
+
+            "l" | "p" => skipped += 1,
+
+            // Smoothing groups and unsupported statements
+            _ => {},
+        }
+    }
+    meshes.push(current);
+
+    if skipped > 0 {
+        eprintln!("Skipping {} lines and points in {}, only faces are rendered", skipped, path.display());
+    }
+
+    let mut missing = Vec::new();
+    for mesh in meshes {
+        if mesh.triangles.is_empty() {
+            continue;
+        }
+
+        let material = match materials.get(&mesh.material) {
+            Some(mtl) => mtl_to_material(mtl),
+            None => {
+                if !mesh.material.is_empty() && !missing.contains(&mesh.material) {
+                    eprintln!("Unknown material \"{}\" in {}, using the default one", mesh.material, path.display());
+                    missing.push(mesh.material.clone());
+                }
+                Material { double_sided: true, ..Material::default() }
+            },
+        };
+
+        let location = format!("object \"{}\" of {}", mesh.name, path.display());
+        let normals = if mesh.all_normals { Some(mesh.normals) } else { None };
+        let uvs = if mesh.all_uvs { Some(mesh.uvs) } else { None };
+        match build_mesh(mesh.positions, normals, None, uvs, mesh.triangles, material, normal_generation) {
+            Ok(Some(mesh)) => builder.push(mesh),
+            Ok(None) => eprintln!("Skipping {}: no triangles", location),
+            Err(reason) => return Err(SceneError::InvalidGeometry { location: location, reason: reason }),
+        }
+    }
+
+    Ok(())
+}
+
+
+impl ObjMesh {
+    fn new(name: &str, material: &str) -> ObjMesh {
+        ObjMesh {
+            name: name.to_string(),
+            material: material.to_string(),
+            positions: Vec::new(),
+            uvs: Vec::new(),
+            all_uvs: true,
+            normals: Vec::new(),
+            all_normals: true,
+            triangles: Vec::new(),
+            vertex_ids: HashMap::new(),
+        }
+    }
+
+    fn add_polygon(&mut self, corners: &[Corner], positions: &[Vec3], uvs: &[[f32; 2]], normals: &[Vec3]) {
+        let ids = corners.iter().map(|corner| self.vertex_id(*corner, positions, uvs, normals)).collect::<Vec<_>>();
+        for i in 1..ids.len() - 1 {
+            self.triangles.push([ids[0], ids[i], ids[i + 1]]);
+        }
+    }
+
+    // Attributes only some vertices have are dropped for the whole mesh
+    fn vertex_id(&mut self, (pos, uv, norm): Corner, positions: &[Vec3], uvs: &[[f32; 2]], normals: &[Vec3]) -> u32 {
+        if let Some(id) = self.vertex_ids.get(&(pos, uv, norm)) {
+            return *id;
+        }
+
+        let id = self.positions.len() as u32;
+        self.positions.push(positions[pos]);
+        match uv {
+            Some(uv) => self.uvs.push(uvs[uv]),
+            None => self.all_uvs = false,
+        }
+        match norm {
+            Some(norm) => self.normals.push(normals[norm].normalized()),
+            None => self.all_normals = false,
+        }
+        self.vertex_ids.insert((pos, uv, norm), id);
+        id
+    }
+}
+
+
+// Lines without comments, with the ones ending in a backslash joined to the next, numbered from 1
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let (number, mut joined) = pending.take().unwrap_or((i + 1, String::new()));
+        match line.trim_end().strip_suffix('\\') {
+            Some(start) => {
+                joined.push_str(start);
+                joined.push(' ');
+                pending = Some((number, joined));
+            },
+            None => {
+                joined.push_str(line);
+                lines.push((number, joined));
+            },
+        }
+    }
+    lines.extend(pending);
+    lines
+}
+
+fn parse_floats<'a, I: Iterator<Item = &'a str>>(tokens: I, min: usize, max: usize) -> Result<Vec<f32>, String> {
+    let values = tokens.map(|t| t.parse::<f32>().map_err(|_| format!("invalid number \"{}\"", t))).collect::<Result<Vec<_>, _>>()?;
+    if values.len() < min || values.len() > max {
+        return Err(format!("expected {} to {} numbers, got {}", min, max, values.len()));
+    }
+    Ok(values)
+}
+
+// Vertices can have a fourth (w) component, which we ignore
+fn parse_vec3<'a, I: Iterator<Item = &'a str>>(tokens: I) -> Result<Vec3, String> {
+    let v = parse_floats(tokens, 3, 4)?;
+    Ok(Vec3::new(v[0], v[1], v[2]))
+}
+
+fn parse_color<'a, I: Iterator<Item = &'a str>>(tokens: I) -> Result<Color, String> {
+    let v = parse_floats(tokens, 1, 3)?;
+    // A single value is a grey
+    Ok(if v.len() == 3 { Color::new(v[0], v[1], v[2]) } else { Color::from(v[0]) })
+}
+
+// Indices start at 1, negative ones count back from the last element. Empty ones (as in "1//2") are None
+fn resolve_index(token: Option<&str>, count: usize) -> Result<Option<usize>, String> {
+    let token = match token {
+        Some(token) if !token.is_empty() => token,
+        _ => return Ok(None),
+    };
+
+    let index = token.parse::<i64>().map_err(|_| format!("invalid index \"{}\"", token))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("index {} is out of the {} elements defined so far", index, count));
+    }
+    Ok(Some(resolved as usize))
+}
+
+
+fn parse_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, SceneError> {
+    let text = std::fs::read_to_string(path)?;
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+    for (line_number, line) in logical_lines(&text) {
+        let parse_error = |msg: String| SceneError::Parse(format!("{}:{}: {}", path.display(), line_number, msg));
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            materials.extend(current.take());
+            current = Some((tokens.collect::<Vec<_>>().join(" "), MtlMaterial::default()));
+            continue;
+        }
+
+        let mtl = match &mut current {
+            Some((_, mtl)) => mtl,
+            None => return Err(parse_error(format!("\"{}\" before any newmtl", keyword))),
+        };
+
+        let single = |tokens| parse_floats(tokens, 1, 1).map(|v| v[0]).map_err(parse_error);
+        match keyword {
+            "Kd" => mtl.diffuse = Some(parse_color(tokens).map_err(parse_error)?),
+            "Ks" => mtl.specular = Some(parse_color(tokens).map_err(parse_error)?),
+            "Ke" => mtl.emissive = Some(parse_color(tokens).map_err(parse_error)?),
+            "Ns" => mtl.shininess = Some(single(tokens)?),
+            "Ni" => mtl.ior = Some(single(tokens)?),
+            "Tf" => mtl.transmission_filter = Some(parse_color(tokens).map_err(parse_error)?),
+            "illum" => {
+                let illum = tokens.next().unwrap_or("");
+                mtl.illum = Some(illum.parse().map_err(|_| parse_error(format!("invalid illumination model \"{}\"", illum)))?);
+            },
+            // Dissolve is the opacity of the surface, like an alpha mask, not its transmission
+            "d" | "Tr" => {
+                let opaque = if keyword == "d" { 1.0 } else { 0.0 };
+                if single(tokens)? != opaque {
+                    eprintln!("{}:{}: ignoring \"{}\", surfaces can't be partially transparent", path.display(), line_number, line.trim());
+                }
+            },
+            // PBR extension of the format
+            "Pr" => mtl.roughness = Some(single(tokens)?),
+            "Pm" => mtl.metallic = Some(single(tokens)?),
+            // Illumination models, texture maps and unsupported statements
+            _ => {},
+        }
+    }
+    materials.extend(current);
+
+    Ok(materials)
+}
+
+// Without the Pr and Pm extensions, the Phong exponent gives the roughness (alpha = sqrt(2 / (Ns + 2)), Walter et al. 2007),
+// and a specular color brighter than the diffuse one is taken as a metal.
+// Only the illumination models with refraction (4 to 7) transmit light, filtered by Tf.
+// OBJ has no notion of back faces, and exported windings are often inconsistent, so materials are double sided
+fn mtl_to_material(mtl: &MtlMaterial) -> Material {
+    let default = Material::default();
+    let diffuse = mtl.diffuse.unwrap_or(default.color);
+    let specular = mtl.specular.unwrap_or(Color::from(0.0));
+
+    let roughness = match (mtl.roughness, mtl.shininess) {
+        (Some(roughness), _) => roughness,
+        (None, Some(shininess)) => (2.0 / (shininess.max(0.0) + 2.0)).powf(0.25),
+        (None, None) => default.roughness,
+    };
+
+    let (metallic, color) = match mtl.metallic {
+        Some(metallic) => (metallic, diffuse),
+        None if specular.luminance() > diffuse.luminance() => (1.0, specular),
+        None => (0.0, diffuse),
+    };
+
+    let transmission = match mtl.illum {
+        Some(4..=7) => mtl.transmission_filter.map(|filter| filter.luminance()).unwrap_or(1.0).clamp(0.0, 1.0),
+        _ => 0.0,
+    };
+
+    Material {
+        roughness: roughness,
+        metallic: metallic,
+        color: color,
+        emissive: mtl.emissive.unwrap_or(default.emissive),
+        transmission: transmission,
+        ior: mtl.ior.unwrap_or(default.ior),
+        double_sided: true,
+        ..default
+    }
+}
@@ -0,0 +1,143 @@
+// Imports OBJ files with their material libraries: polygons and materials, texture coordinates, and malformed files.
+
+use crate::scene::*;
+use crate::normals::*;
+use crate::surface::*;
+use crate::color::*;
+use crate::material::*;
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::test_files::*;
+
+
+const OBJ: &str = "\
+mtllib materials.mtl
+# A 2x2 quad and a unit pentagon-ish polygon, in two groups
+v -1 0 -1
+v 1 0 -1
+v 1 0 1
+v -1 0 1
+vn 0 1 0
+vt 0 0
+g floor
+usemtl white
+f 4//1 3//1 2//1 1//1
+g lamp
+usemtl light
+v 0 1 0
+v 1 1 0
+v 1 1 1
+v 0.5 1 1.5
+v 0 1 1
+f -5 -4 -3 \\
+  -2 -1
+usemtl missing
+f 1/1 2/1 3/1
+l 1 2
+";
+
+const MTL: &str = "\
+newmtl white
+Kd 0.8 0.8 0.8
+Ns 30
+newmtl light
+Kd 0 0 0
+Ks 0.9 0.6 0.3
+Ke 4 4 4
+Ni 1.4
+d 0.25
+";
+
+#[test]
+fn obj_with_materials() {
+    let path = write_temp("obj", OBJ);
+    write_next_to(&path, "materials.mtl", MTL);
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    let objects = scene.objects();
+    assert_eq!(objects.len(), 3);
+
+    // Quad, pentagon and triangle, triangulated as fans
+    assert_eq!(objects[0].area(), 4.0);
+    assert_eq!(objects[1].area(), 1.25);
+    assert_eq!(objects[2].area(), 2.0);
+
+    let white = objects[0].material();
+    assert_eq!(white.color, Color::from(0.8));
+    assert!((white.roughness - (2.0_f32 / 32.0).powf(0.25)).abs() < 1e-6);
+    assert_eq!(white.metallic, 0.0);
+
+    // Brighter specular than diffuse makes a metal
+    let light = objects[1].material();
+    assert_eq!(light.metallic, 1.0);
+    assert_eq!(light.color, Color::new(0.9, 0.6, 0.3));
+    assert_eq!(light.emissive, Color::from(4.0));
+    assert_eq!(light.ior, 1.4);
+    // Dissolve is opacity, not transmission
+    assert_eq!(light.transmission, 0.0);
+
+    assert_eq!(*objects[2].material(), Material { double_sided: true, ..Material::default() });
+}
+
+// Only the illumination models with refraction transmit light, filtered by Tf
+#[test]
+fn obj_transmission() {
+    let mtl = "newmtl glass\nillum 7\nTf 0.8 0.8 0.8\nNi 1.5\n\
+        newmtl clear\nillum 4\n\
+        newmtl opaque\nillum 2\nTf 0.5 0.5 0.5\n\
+        newmtl dissolved\nd 0.5\nTr 0.5\n";
+    let obj = "mtllib materials.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+        o glass\nusemtl glass\nf 1 2 3\n\
+        o clear\nusemtl clear\nf 1 2 3\n\
+        o opaque\nusemtl opaque\nf 1 2 3\n\
+        o dissolved\nusemtl dissolved\nf 1 2 3\n";
+    let path = write_temp("obj", obj);
+    write_next_to(&path, "materials.mtl", mtl);
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+
+    let transmission = scene.objects().iter().map(|obj| obj.material().transmission).collect::<Vec<_>>();
+    assert_eq!(transmission.len(), 4);
+    assert!((transmission[0] - 0.8).abs() < 1e-6, "{}", transmission[0]);
+    assert_eq!(transmission[1..], [1.0, 0.0, 0.0]);
+    assert_eq!(scene.objects()[0].material().ior, 1.5);
+
+    let path = write_temp("obj", "mtllib materials.mtl\n");
+    write_next_to(&path, "materials.mtl", "newmtl glass\nillum glass\n");
+    assert!(matches!(import_scene(&path, NormalGeneration::Flat), Err(SceneError::Parse(_))));
+}
+
+#[test]
+fn obj_errors() {
+    let path = write_temp("obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n");
+    match import_scene(&path, NormalGeneration::Flat) {
+        Err(SceneError::Parse(msg)) => assert!(msg.ends_with(":4: index 4 is out of the 3 elements defined so far"), "{}", msg),
+        _ => panic!("out of range indices should be rejected"),
+    }
+
+    let path = write_temp("obj", "v 0 zero 0\n");
+    assert!(matches!(import_scene(&path, NormalGeneration::Flat), Err(SceneError::Parse(_))));
+}
+
+#[test]
+fn obj_texture_coordinates() {
+    // A unit quad facing +Z with UVs matching its XY position, its material in a second library
+    let obj = "mtllib materials.mtl extra.mtl\n\
+        v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+        usemtl red\nf 1/1 2/2 3/3 4/4\n";
+    let path = write_temp("obj", obj);
+    write_next_to(&path, "materials.mtl", MTL);
+    write_next_to(&path, "extra.mtl", "newmtl red\nKd 1 0 0\n");
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    assert_eq!(scene.objects()[0].material().color, Color::new(1.0, 0.0, 0.0));
+
+    let hit = (&scene).hit(Ray::new(Vec3::new(0.25, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+    let uv = hit.uv.unwrap();
+    assert!((uv[0] - 0.25).abs() < 1e-6 && (uv[1] - 0.75).abs() < 1e-6, "{:?}", uv);
+
+    // Dropped when some corners don't have any
+    let path = write_temp("obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nf 1/1 2 3\n");
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    let hit = (&scene).hit(Ray::new(Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+    assert_eq!(hit.uv, None);
+}
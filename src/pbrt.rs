@@ -0,0 +1,777 @@
+use crate::vec::*;
+use crate::color::*;
+use crate::material::*;
+use crate::scene::*;
+use crate::normals::*;
+use crate::transform::*;
+use crate::camera::*;
+use crate::environment::*;
+use crate::integrator::*;
+use crate::image::*;
+use crate::ply::*;
+use crate::utils::*;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Str(String),
+    Num(f64),
+    Word(String),
+    Open,
+    Close,
+}
+
+// A directive and the tokens that follow it, up to the next one
+struct Directive {
+    line: usize,
+    name: String,
+    args: Vec<Token>,
+}
+
+// A "type name" value parameter
+struct Param {
+    ty: String,
+    name: String,
+    values: Vec<Token>,
+}
+
+// Attributes saved by AttributeBegin, or only the transform by TransformBegin
+#[derive(Clone)]
+struct GraphicsState {
+    transform: Transform,
+    reverse_orientation: bool,
+    // None for interface materials, whose shapes only bound media and aren't rendered
+    material: Option<Material>,
+    // Radiance and whether both sides emit
+    area_light: Option<(Color, bool)>,
+}
+
+struct PbrtCamera {
+    camera_from_world: Transform,
+    fov: f32,
+}
+
+struct PbrtImporter<'a> {
+    builder: &'a mut SceneBuilder,
+    normal_generation: NormalGeneration,
+    // Relative paths are relative to the main file, even in included ones
+    dir: PathBuf,
+    // Files being parsed, the main one first, to catch files including themselves
+    open_files: Vec<PathBuf>,
+
+    state: GraphicsState,
+    stack: Vec<(GraphicsState, bool)>,
+    named_transforms: HashMap<String, Transform>,
+    named_materials: HashMap<String, Option<Material>>,
+    // Shapes of object definitions aren't rendered, as instancing isn't supported
+    object_depth: usize,
+
+    camera: Option<PbrtCamera>,
+    resolution: (u32, u32),
+    settings: RenderSettings,
+    environment: Option<Environment>,
+
+    warnings: HashSet<String>,
+}
+
+
+// Imports the parts of the pbrt-v4 format this renderer can show (https://pbrt.org/fileformat-v4):
+// perspective cameras, film resolution, sample count and path depth, triangle, bilinear and PLY meshes,
+// diffuse, coated diffuse, conductor and dielectric materials, diffuse area lights and infinite lights.
+// Anything else is skipped with a warning. Unset render settings take pbrt's defaults, so that both renders match
+pub fn import_pbrt(path: &Path, builder: &mut SceneBuilder, normal_generation: NormalGeneration) -> Result<(), SceneError> {
+    let mut importer = PbrtImporter {
+        builder: builder,
+        normal_generation: normal_generation,
+        dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        open_files: Vec::new(),
+
+        state: GraphicsState {
+            transform: Transform::identity(),
+            reverse_orientation: false,
+            material: Some(pbrt_material("diffuse", &[])),
+            area_light: None,
+        },
+        stack: Vec::new(),
+        named_transforms: HashMap::new(),
+        named_materials: HashMap::new(),
+        object_depth: 0,
+
+        camera: None,
+        resolution: (1280, 720),
+        settings: RenderSettings {
+            spp: Some(16),
+            max_bounces: Some(5),
+            resolution: None,
+            integrator: Some(IntegratorKind::Path),
+        },
+        environment: None,
+
+        warnings: HashSet::new(),
+    };
+
+    importer.parse_file(path)?;
+    importer.finish();
+    Ok(())
+}
+
+
+impl<'a> PbrtImporter<'a> {
+    fn parse_file(&mut self, path: &Path) -> Result<(), SceneError> {
+        self.builder.add_source_file(path);
+        let text = std::fs::read_to_string(path)?;
+        let directives = parse_directives(&text).map_err(|(line, msg)| SceneError::Parse(format!("{}:{}: {}", path.display(), line, msg)))?;
+
+        self.open_files.push(std::fs::canonicalize(path)?);
+        let result = self.run_directives(path, directives);
+        self.open_files.pop();
+        result
+    }
+
+    fn run_directives(&mut self, path: &Path, directives: Vec<Directive>) -> Result<(), SceneError> {
+        for directive in directives {
+            let error = |msg: String| SceneError::Parse(format!("{}:{}: {}", path.display(), directive.line, msg));
+            match directive.name.as_str() {
+                "Include" | "Import" => {
+                    let file = self.dir.join(positional_string(&directive.args, 0).map_err(error)?);
+                    if std::fs::canonicalize(&file).is_ok_and(|file| self.open_files.contains(&file)) {
+                        return Err(error(format!("{} includes itself", file.display())));
+                    }
+                    self.parse_file(&file).map_err(|err| match err {
+                        SceneError::Io(err) => error(format!("unable to read {}: {}", file.display(), err)),
+                        err => err,
+                    })?;
+                },
+                _ => self.directive(&directive).map_err(|err| match err {
+                    SceneError::Parse(msg) => error(msg),
+                    SceneError::InvalidGeometry { location, reason } => SceneError::InvalidGeometry {
+                        location: format!("{}:{} ({})", path.display(), directive.line, location),
+                        reason: reason,
+                    },
+                    err => err,
+                })?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn warn(&mut self, msg: String) {
+        if self.warnings.insert(msg.clone()) {
+            eprintln!("Skipping {}", msg);
+        }
+    }
+
+    // Errors are Parse messages without their location, added by parse_file
+    fn directive(&mut self, directive: &Directive) -> Result<(), SceneError> {
+        let args = &directive.args;
+
+        match directive.name.as_str() {
+            "Identity" => self.state.transform = Transform::identity(),
+            "Translate" => {
+                let v = numbers(args, 3).map_err(SceneError::Parse)?;
+                self.concat(Transform::translation(Vec3::new(v[0], v[1], v[2])));
+            },
+            "Scale" => {
+                let v = numbers(args, 3).map_err(SceneError::Parse)?;
+                self.concat(Transform::scaling(Vec3::new(v[0], v[1], v[2])));
+            },
+            "Rotate" => {
+                let v = numbers(args, 4).map_err(SceneError::Parse)?;
+                self.concat(Transform::rotation(Vec3::new(v[1], v[2], v[3]), v[0]));
+            },
+            "LookAt" => {
+                let v = numbers(args, 9).map_err(SceneError::Parse)?;
+                let (eye, target, up) = (Vec3::new(v[0], v[1], v[2]), Vec3::new(v[3], v[4], v[5]), Vec3::new(v[6], v[7], v[8]));
+                self.concat(look_at(eye, target, up).map_err(SceneError::Parse)?);
+            },
+            "Transform" => self.state.transform = matrix(args).map_err(SceneError::Parse)?,
+            "ConcatTransform" => self.concat(matrix(args).map_err(SceneError::Parse)?),
+            "CoordinateSystem" => {
+                let name = positional_string(args, 0).map_err(SceneError::Parse)?;
+                self.named_transforms.insert(name.to_string(), self.state.transform);
+            },
+            "CoordSysTransform" => {
+                let name = positional_string(args, 0).map_err(SceneError::Parse)?;
+                match self.named_transforms.get(name) {
+                    Some(transform) => self.state.transform = *transform,
+                    None => self.warn(format!("unknown coordinate system \"{}\"", name)),
+                }
+            },
+            "ReverseOrientation" => self.state.reverse_orientation = !self.state.reverse_orientation,
+
+            "AttributeBegin" => self.stack.push((self.state.clone(), false)),
+            "TransformBegin" => self.stack.push((self.state.clone(), true)),
+            "AttributeEnd" | "TransformEnd" => match self.stack.pop() {
+                Some((state, true)) => self.state.transform = state.transform,
+                Some((state, false)) => self.state = state,
+                None => return Err(SceneError::Parse(format!("unmatched {}", directive.name))),
+            },
+            "ObjectBegin" => {
+                self.warn("object instances, instancing isn't supported".to_string());
+                self.stack.push((self.state.clone(), false));
+                self.object_depth += 1;
+            },
+            "ObjectEnd" => {
+                self.state = self.stack.pop().ok_or_else(|| SceneError::Parse("unmatched ObjectEnd".to_string()))?.0;
+                self.object_depth = self.object_depth.saturating_sub(1);
+            },
+            "ObjectInstance" => {},
+
+            "Camera" => {
+                let (ty, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                if ty != "perspective" {
+                    self.warn(format!("{} camera, using a perspective one", ty));
+                }
+                self.camera = Some(PbrtCamera {
+                    camera_from_world: self.state.transform,
+                    fov: float(&params, "fov").map_err(SceneError::Parse)?.unwrap_or(90.0),
+                });
+                self.named_transforms.insert("camera".to_string(), self.state.transform.inverse());
+            },
+            "Film" => {
+                let (_, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                let x = integer(&params, "xresolution").map_err(SceneError::Parse)?.unwrap_or(self.resolution.0 as usize);
+                let y = integer(&params, "yresolution").map_err(SceneError::Parse)?.unwrap_or(self.resolution.1 as usize);
+                if x == 0 || y == 0 {
+                    return Err(SceneError::Parse(format!("invalid film resolution {}x{}", x, y)));
+                }
+                self.resolution = (x as u32, y as u32);
+            },
+            "Sampler" => {
+                let (_, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                if let Some(spp) = integer(&params, "pixelsamples").map_err(SceneError::Parse)? {
+                    self.settings.spp = Some(spp.max(1));
+                }
+            },
+            "Integrator" => {
+                let (ty, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                if !matches!(ty, "path" | "volpath" | "bdpt" | "mlt" | "sppm") {
+                    self.warn(format!("{} integrator, using a path tracer", ty));
+                }
+                if let Some(depth) = integer(&params, "maxdepth").map_err(SceneError::Parse)? {
+                    self.settings.max_bounces = Some(depth);
+                }
+            },
+            "WorldBegin" => {
+                self.state.transform = Transform::identity();
+                self.named_transforms.insert("world".to_string(), Transform::identity());
+            },
+            "WorldEnd" | "Option" | "ColorSpace" | "PixelFilter" | "Accelerator" => {},
+
+            "Material" => {
+                let (ty, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                self.state.material = self.material(ty, &params);
+            },
+            "MakeNamedMaterial" => {
+                let (name, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                let ty = string(&params, "type").map_err(SceneError::Parse)?.ok_or_else(|| SceneError::Parse(format!("material \"{}\" has no type", name)))?;
+                let material = self.material(ty, &params);
+                self.named_materials.insert(name.to_string(), material);
+            },
+            "NamedMaterial" => {
+                let name = positional_string(args, 0).map_err(SceneError::Parse)?;
+                self.state.material = *self.named_materials.get(name).ok_or_else(|| SceneError::Parse(format!("unknown material \"{}\"", name)))?;
+            },
+            "Texture" => self.warn("textures, they aren't supported".to_string()),
+
+            "AreaLightSource" => {
+                let (ty, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                if ty != "diffuse" {
+                    self.warn(format!("{} area lights", ty));
+                    return Ok(());
+                }
+                let radiance = color(&params, "L").map_err(SceneError::Parse)?.unwrap_or(Color::from(1.0)) * float(&params, "scale").map_err(SceneError::Parse)?.unwrap_or(1.0);
+                let two_sided = boolean(&params, "twosided").map_err(SceneError::Parse)?.unwrap_or(false);
+                self.state.area_light = Some((radiance, two_sided));
+            },
+            "LightSource" => {
+                let (ty, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                if ty == "infinite" {
+                    let environment = self.infinite_light(&params)?;
+                    if self.environment.replace(environment).is_some() {
+                        self.warn("infinite lights after the first one".to_string());
+                    }
+                } else {
+                    self.warn(format!("{} lights, only area and infinite lights are supported", ty));
+                }
+            },
+
+            "Shape" => {
+                let (ty, params) = type_and_params(args).map_err(SceneError::Parse)?;
+                if self.object_depth == 0 {
+                    self.shape(ty, &params)?;
+                }
+            },
+
+            "MakeNamedMedium" | "MediumInterface" => self.warn("participating media".to_string()),
+            "Attribute" => self.warn("Attribute directives".to_string()),
+
+            name => return Err(SceneError::Parse(format!("unknown directive {}", name))),
+        }
+
+        Ok(())
+    }
+
+    fn concat(&mut self, transform: Transform) {
+        self.state.transform = self.state.transform.then(transform);
+    }
+
+    // Parameters pbrt's materials have but ours can't use, like textures, are ignored.
+    // Coated diffuse becomes diffuse, as our diffuse lobe has no coating
+    fn material(&mut self, ty: &str, params: &[Param]) -> Option<Material> {
+        if ty == "interface" {
+            return None;
+        }
+        if params.iter().any(|p| p.ty == "texture") {
+            self.warn("textures, they aren't supported".to_string());
+        }
+        if !matches!(ty, "diffuse" | "coateddiffuse" | "conductor" | "dielectric" | "thindielectric") {
+            self.warn(format!("{} materials, using a diffuse one", ty));
+        }
+        Some(pbrt_material(ty, params))
+    }
+
+    // pbrt-v4 maps images with its equal area octahedral mapping, and they must be square
+    fn infinite_light(&mut self, params: &[Param]) -> Result<Environment, SceneError> {
+        let scale = float(params, "scale").map_err(SceneError::Parse)?.unwrap_or(1.0);
+        match string(params, "filename").map_err(SceneError::Parse)? {
+            Some(filename) => {
+                let file = self.dir.join(filename);
+                self.builder.add_source_file(&file);
+                let image = Image::load(&file).map_err(|err| SceneError::Parse(format!("unable to load {}: {}", file.display(), err)))?;
+                if image.width() != image.height() || image.pixel_count() == 0 {
+                    return Err(SceneError::Parse(format!("{} isn't a square equal area image", file.display())));
+                }
+                Ok(Environment::Image {
+                    image: image,
+                    strength: scale,
+                    mapping: EnvironmentMapping::EqualArea,
+                    to_map: self.state.transform.inverse(),
+                })
+            },
+            None => {
+                let radiance = color(params, "L").map_err(SceneError::Parse)?.unwrap_or(Color::from(1.0));
+                Ok(Environment::Color(radiance * scale))
+            },
+        }
+    }
+
+    fn shape(&mut self, ty: &str, params: &[Param]) -> Result<(), SceneError> {
+
+        let (positions, normals, triangles) = match ty {
+            "trianglemesh" | "bilinearmesh" => {
+                let positions = points(params, "P").map_err(SceneError::Parse)?.ok_or_else(|| SceneError::Parse(format!("{} has no positions", ty)))?;
+                let normals = points(params, "N").map_err(SceneError::Parse)?;
+                let indices = match integers(params, "indices").map_err(SceneError::Parse)? {
+                    Some(indices) => indices,
+                    None if (ty == "trianglemesh" && positions.len() == 3) || (ty == "bilinearmesh" && positions.len() == 4) => (0..positions.len() as u32).collect(),
+                    None => return Err(SceneError::Parse(format!("{} has no indices", ty))),
+                };
+
+                // Bilinear patches list their corners as p00, p10, p01, p11
+                let triangles = if ty == "trianglemesh" {
+                    indices.chunks_exact(3).map(|i| [i[0], i[1], i[2]]).collect()
+                } else {
+                    indices.chunks_exact(4).flat_map(|i| [[i[0], i[1], i[3]], [i[0], i[3], i[2]]]).collect()
+                };
+                (positions, normals, triangles)
+            },
+            "plymesh" => {
+                let filename = string(params, "filename").map_err(SceneError::Parse)?.ok_or_else(|| SceneError::Parse("plymesh has no filename".to_string()))?;
+                if filename.ends_with(".gz") {
+                    self.warn(format!("{}, compressed PLY files aren't supported", filename));
+                    return Ok(());
+                }
+                let file = self.dir.join(filename);
+                self.builder.add_source_file(&file);
+                let ply = read_ply(&file).map_err(|err| match err {
+                    SceneError::Io(err) => SceneError::Parse(format!("unable to read {}: {}", file.display(), err)),
+                    err => err,
+                })?;
+                (ply.positions, ply.normals, ply.triangles)
+            },
+            _ => {
+                self.warn(format!("{} shapes, only triangle, bilinear and PLY meshes are supported", ty));
+                return Ok(());
+            },
+        };
+
+        let mut material = match self.state.material {
+            Some(material) => material,
+            None => return Ok(()),
+        };
+        if let Some((radiance, two_sided)) = self.state.area_light {
+            material.emissive = radiance;
+            material.one_sided_emission = !two_sided;
+        }
+
+        let transform = self.state.transform;
+        let positions = positions.iter().map(|p| transform.transform_pos(*p)).collect::<Vec<_>>();
+        let normals = normals.map(|normals| normals.iter().map(|n| transform.transform_normal(*n).normalized()).collect::<Vec<_>>());
+
+        // Surfaces face their normals if they have some, otherwise their winding gives their side,
+        // which ReverseOrientation turns around. Mirroring transforms keep it
+        let flip = |tri: [u32; 3]| [tri[0], tri[2], tri[1]];
+        let triangles = match &normals {
+            Some(normals) => triangles.into_iter().map(|tri| {
+                let p = tri.map(|i| positions.get(i as usize).copied());
+                let n = tri.map(|i| normals.get(i as usize).copied().unwrap_or(Vec3::zero()));
+                let face = match p {
+                    [Some(a), Some(b), Some(c)] => face_normal([a, b, c]),
+                    _ => None,
+                };
+                match face {
+                    Some(face) if face.dot(n[0] + n[1] + n[2]) < 0.0 => flip(tri),
+                    _ => tri,
+                }
+            }).collect(),
+            None if (transform.determinant() < 0.0) != self.state.reverse_orientation => triangles.into_iter().map(flip).collect(),
+            None => triangles,
+        };
+
+        match build_mesh(positions, normals, None, None, triangles, material, self.normal_generation) {
+            Ok(Some(mesh)) => self.builder.push(mesh),
+            Ok(None) => {},
+            Err(reason) => return Err(SceneError::InvalidGeometry { location: format!("{} shape", ty), reason: reason }),
+        }
+        Ok(())
+    }
+
+    fn finish(self) {
+        let (width, height) = self.resolution;
+        let ratio = width as f32 / height as f32;
+
+        // pbrt's camera looks down +Z with +Y up, and its fov is the one of the shorter image side
+        if let Some(camera) = self.camera {
+            let world_from_camera = camera.camera_from_world.inverse();
+            let right = world_from_camera.transform_dir(Vec3::new(1.0, 0.0, 0.0)).normalized();
+            let up = world_from_camera.transform_dir(Vec3::new(0.0, 1.0, 0.0)).normalized();
+            let forward = world_from_camera.transform_dir(Vec3::new(0.0, 0.0, 1.0)).normalized();
+            let tan_half_fov = (camera.fov.to_radians() * 0.5).tan();
+            let vfov = if ratio >= 1.0 { camera.fov.to_radians() } else { 2.0 * (tan_half_fov / ratio).atan() };
+
+            let transform = Transform::from_basis(right, up, -forward).with_pos(world_from_camera.position());
+            self.builder.set_camera(Camera::new(transform, vfov, ratio));
+        } else {
+            self.builder.set_camera(Camera::default().with_ratio(ratio));
+        }
+
+        if let Some(environment) = self.environment {
+            self.builder.set_environment(environment);
+        }
+        self.builder.set_render_settings(RenderSettings { resolution: Some(self.resolution), ..self.settings });
+    }
+}
+
+// The material pbrt would use, with its default values
+fn pbrt_material(ty: &str, params: &[Param]) -> Material {
+    let reflectance = color(params, "reflectance").ok().flatten();
+    let param = |name| float(params, name).ok().flatten();
+
+    // pbrt-v4 remaps roughness to alpha with a square root by default, and our alpha is the square of our roughness
+    let roughness = param("roughness")
+        .or_else(|| Some((param("uroughness")? + param("vroughness")?) * 0.5))
+        .unwrap_or(0.0)
+        .max(0.0);
+    let roughness = if boolean(params, "remaproughness").ok().flatten().unwrap_or(true) { roughness.sqrt().sqrt() } else { roughness.sqrt() };
+
+    let diffuse = Material {
+        roughness: 1.0,
+        color: reflectance.unwrap_or(Color::from(0.5)),
+        double_sided: true,
+        ..Material::default()
+    };
+
+    match ty {
+        "conductor" => Material {
+            roughness: roughness,
+            metallic: 1.0,
+            color: reflectance.unwrap_or_else(|| conductor_reflectance(params)),
+            double_sided: true,
+            ..Material::default()
+        },
+        "dielectric" | "thindielectric" => Material {
+            roughness: roughness,
+            color: Color::from(1.0),
+            transmission: 1.0,
+            ior: dielectric_ior(params),
+            double_sided: true,
+            ..Material::default()
+        },
+        _ => diffuse,
+    }
+}
+
+// Reflectance at normal incidence of the conductor's complex index of refraction, copper by default
+fn conductor_reflectance(params: &[Param]) -> Color {
+    if let (Ok(Some(eta)), Ok(Some(k))) = (color(params, "eta"), color(params, "k")) {
+        return conductor_f0(eta, k);
+    }
+
+    // pbrt's named metal spectra, like "metal-Au-eta"
+    let symbol = params.iter().find(|p| p.name == "eta").and_then(|p| match p.values.as_slice() {
+        [Token::Str(s)] => s.strip_prefix("metal-")?.strip_suffix("-eta").and_then(metal_reflectance),
+        _ => None,
+    });
+    symbol.unwrap_or_else(|| metal_reflectance("Cu").unwrap())
+}
+
+fn dielectric_ior(params: &[Param]) -> f32 {
+    match params.iter().find(|p| p.name == "eta").map(|p| p.values.as_slice()) {
+        Some([Token::Num(eta)]) => *eta as f32,
+        // Named glass spectra, at the sodium D line
+        Some([Token::Str(name)]) => match name.as_str() {
+            "glass-BAF10" => 1.670,
+            "glass-FK51A" => 1.487,
+            "glass-LASF9" => 1.850,
+            "glass-F5" => 1.603,
+            _ => 1.517,
+        },
+        _ => 1.5,
+    }
+}
+
+
+// pbrt's LookAt gives the camera from world transform, with the camera looking down +Z
+fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Result<Transform, String> {
+    let dir = (target - eye).normalized();
+    let right = up.normalized().cross(dir).normalized();
+    if right.length().is_nan() {
+        return Err("LookAt with an up vector along the view direction".to_string());
+    }
+    let new_up = dir.cross(right);
+    Ok(Transform::from_basis(right, new_up, dir).with_pos(eye).inverse())
+}
+
+// 16 numbers, column by column
+fn matrix(args: &[Token]) -> Result<Transform, String> {
+    let m = numbers(args, 16)?;
+    let column = |c: usize| Vec3::new(m[c * 4], m[c * 4 + 1], m[c * 4 + 2]);
+    Ok(Transform::from_basis(column(0), column(1), column(2)).with_pos(column(3)))
+}
+
+// Numbers of a transform directive, which can be in brackets
+fn numbers(args: &[Token], count: usize) -> Result<Vec<f32>, String> {
+    let values = args.iter().filter(|t| !matches!(t, Token::Open | Token::Close)).map(|t| match t {
+        Token::Num(n) => Ok(*n as f32),
+        _ => Err(format!("expected {} numbers", count)),
+    }).collect::<Result<Vec<_>, _>>()?;
+
+    if values.len() != count {
+        return Err(format!("expected {} numbers, got {}", count, values.len()));
+    }
+    Ok(values)
+}
+
+fn positional_string(args: &[Token], index: usize) -> Result<&str, String> {
+    match args.get(index) {
+        Some(Token::Str(s)) => Ok(s),
+        _ => Err("expected a quoted name".to_string()),
+    }
+}
+
+// Most directives have a type (or name), followed by parameters
+fn type_and_params(args: &[Token]) -> Result<(&str, Vec<Param>), String> {
+    let ty = positional_string(args, 0)?;
+    Ok((ty, parse_params(&args[1..])?))
+}
+
+fn parse_params(args: &[Token]) -> Result<Vec<Param>, String> {
+    let mut params = Vec::new();
+    let mut tokens = args.iter();
+    while let Some(token) = tokens.next() {
+        let declaration = match token {
+            Token::Str(s) => s,
+            _ => return Err("expected a \"type name\" parameter".to_string()),
+        };
+        let (ty, name) = match declaration.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [ty, name] => (ty.to_string(), name.to_string()),
+            _ => return Err(format!("invalid parameter \"{}\"", declaration)),
+        };
+
+        let values = match tokens.next() {
+            Some(Token::Open) => {
+                let mut values = Vec::new();
+                loop {
+                    match tokens.next() {
+                        Some(Token::Close) => break,
+                        Some(Token::Open) | None => return Err(format!("unterminated values of \"{}\"", name)),
+                        Some(value) => values.push(value.clone()),
+                    }
+                }
+                values
+            },
+            Some(Token::Close) | None => return Err(format!("\"{}\" has no value", name)),
+            Some(value) => vec![value.clone()],
+        };
+
+        params.push(Param { ty: ty, name: name, values: values });
+    }
+    Ok(params)
+}
+
+
+fn find<'p>(params: &'p [Param], name: &str) -> Option<&'p Param> {
+    params.iter().find(|p| p.name == name)
+}
+
+fn param_numbers(param: &Param) -> Result<Vec<f64>, String> {
+    param.values.iter().map(|v| match v {
+        Token::Num(n) => Ok(*n),
+        _ => Err(format!("\"{}\" must be numbers", param.name)),
+    }).collect()
+}
+
+fn float(params: &[Param], name: &str) -> Result<Option<f32>, String> {
+    match find(params, name) {
+        Some(param) => Ok(param_numbers(param)?.first().map(|v| *v as f32)),
+        None => Ok(None),
+    }
+}
+
+fn integer(params: &[Param], name: &str) -> Result<Option<usize>, String> {
+    Ok(integers(params, name)?.and_then(|values| values.first().map(|v| *v as usize)))
+}
+
+// Numbers are parsed as floats, so values that aren't whole or don't fit in a u32 are rejected rather than truncated
+fn integers(params: &[Param], name: &str) -> Result<Option<Vec<u32>>, String> {
+    match find(params, name) {
+        Some(param) => param_numbers(param)?.iter().map(|v| f64_to_index(*v).map_err(|err| format!("\"{}\": {}", name, err))).collect::<Result<_, _>>().map(Some),
+        None => Ok(None),
+    }
+}
+
+fn points(params: &[Param], name: &str) -> Result<Option<Vec<Vec3>>, String> {
+    match find(params, name) {
+        Some(param) => {
+            let values = param_numbers(param)?;
+            if values.len() % 3 != 0 {
+                return Err(format!("\"{}\" must have 3 numbers per point", name));
+            }
+            Ok(Some(values.chunks_exact(3).map(|v| Vec3::new(v[0] as f32, v[1] as f32, v[2] as f32)).collect()))
+        },
+        None => Ok(None),
+    }
+}
+
+fn string<'p>(params: &'p [Param], name: &str) -> Result<Option<&'p str>, String> {
+    match find(params, name).map(|p| p.values.as_slice()) {
+        Some([Token::Str(s)]) => Ok(Some(s)),
+        Some(_) => Err(format!("\"{}\" must be a string", name)),
+        None => Ok(None),
+    }
+}
+
+fn boolean(params: &[Param], name: &str) -> Result<Option<bool>, String> {
+    match find(params, name).map(|p| p.values.as_slice()) {
+        Some([Token::Word(b)]) | Some([Token::Str(b)]) if b == "true" || b == "false" => Ok(Some(b == "true")),
+        Some(_) => Err(format!("\"{}\" must be true or false", name)),
+        None => Ok(None),
+    }
+}
+
+// RGB values, black bodies (normalized to a luminance of 1 like pbrt-v4 does), and sampled spectra, which are averaged into a grey.
+// Named spectra and textures give None
+fn color(params: &[Param], name: &str) -> Result<Option<Color>, String> {
+    let param = match find(params, name) {
+        Some(param) => param,
+        None => return Ok(None),
+    };
+
+    match param.ty.as_str() {
+        "rgb" | "color" => match param_numbers(param)?.as_slice() {
+            [r, g, b] => Ok(Some(Color::new(*r as f32, *g as f32, *b as f32))),
+            _ => Err(format!("\"{}\" must have 3 numbers", name)),
+        },
+        "float" => Ok(float(params, name)?.map(Color::from)),
+        // pbrt-v3 had a scale after the temperature
+        "blackbody" => match param_numbers(param)?.as_slice() {
+            [t] => Ok(Some(Color::blackbody(*t as f32))),
+            [t, scale] => Ok(Some(Color::blackbody(*t as f32) * *scale as f32)),
+            _ => Err(format!("\"{}\" must be a temperature", name)),
+        },
+        "spectrum" if matches!(param.values.first(), Some(Token::Num(_))) => {
+            let values = param_numbers(param)?;
+            if values.len() < 2 || values.len() % 2 != 0 {
+                return Err(format!("\"{}\" must be wavelength and value pairs", name));
+            }
+            let sum = values.chunks_exact(2).map(|pair| pair[1]).sum::<f64>();
+            Ok(Some(Color::from((sum / (values.len() / 2) as f64) as f32)))
+        },
+        _ => Ok(None),
+    }
+}
+
+
+// Directives are the words starting with an upper case letter, everything up to the next one are their arguments
+fn parse_directives(text: &str) -> Result<Vec<Directive>, (usize, String)> {
+    let mut directives: Vec<Directive> = Vec::new();
+    for (line, token) in tokenize(text)? {
+        match (token, directives.last_mut()) {
+            (Token::Word(word), _) if word.starts_with(|c: char| c.is_ascii_uppercase()) => directives.push(Directive {
+                line: line,
+                name: word,
+                args: Vec::new(),
+            }),
+            (token, Some(directive)) => directive.args.push(token),
+            (_, None) => return Err((line, "expected a directive".to_string())),
+        }
+    }
+    Ok(directives)
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {},
+            '#' => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            },
+            '[' => tokens.push((line, Token::Open)),
+            ']' => tokens.push((line, Token::Close)),
+            '"' => {
+                let start = line;
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(c) => s.push(c),
+                            None => return Err((start, "unterminated string".to_string())),
+                        },
+                        Some('\n') | None => return Err((start, "unterminated string".to_string())),
+                        Some(c) => s.push(c),
+                    }
+                }
+                tokens.push((start, Token::Str(s)));
+            },
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek().copied().filter(|c| !c.is_whitespace() && !matches!(c, '[' | ']' | '"' | '#')) {
+                    word.push(c);
+                    chars.next();
+                }
+                let token = match word.parse::<f64>() {
+                    Ok(n) => Token::Num(n),
+                    Err(_) => Token::Word(word),
+                };
+                tokens.push((line, token));
+            },
+        }
+    }
+
+    Ok(tokens)
+}
@@ -0,0 +1,118 @@
+// Imports pbrt-v4 scenes: camera and render settings, lights, materials, included files and PLY meshes, and the errors of malformed scenes.
+
+use crate::scene::*;
+use crate::normals::*;
+use crate::surface::*;
+use crate::color::*;
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::integrator::*;
+use crate::ply_tests::ply;
+use crate::test_files::*;
+
+use std::path::PathBuf;
+
+
+pub const PBRT: &str = r#"
+LookAt 0 0 5  0 0 0  0 1 0
+Camera "perspective" "float fov" [ 45 ]
+Film "rgb" "integer xresolution" [ 200 ] "integer yresolution" [ 100 ] "string filename" "out.exr"
+Sampler "zsobol" "integer pixelsamples" 32
+Integrator "volpath" "integer maxdepth" [ 3 ]
+
+WorldBegin
+LightSource "infinite" "rgb L" [ 0.1 0.2 0.3 ]
+
+AttributeBegin
+    AreaLightSource "diffuse" "blackbody L" [ 6500 ] "float scale" 4
+    Translate 0 2 0
+    Shape "bilinearmesh" "point3 P" [ -1 0 -1  1 0 -1  -1 0 1  1 0 1 ]
+AttributeEnd
+
+MakeNamedMaterial "gold" "string type" "conductor"
+    "spectrum eta" "metal-Au-eta" "spectrum k" "metal-Au-k" "float roughness" 0.01
+AttributeBegin
+    NamedMaterial "gold"
+    Scale 2 2 2
+    Shape "trianglemesh" "point3 P" [ -1 -1 0  1 -1 0  0 1 0 ] "integer indices" [ 0 1 2 ]
+AttributeEnd
+
+Material "dielectric" "float eta" 1.33
+Include "mesh.pbrt"
+Shape "sphere" "float radius" 1
+"#;
+
+// Included by PBRT
+pub const MESH_PBRT: &str = "ReverseOrientation\nShape \"plymesh\" \"string filename\" \"quad.ply\"\n";
+
+#[test]
+fn pbrt_scene() {
+    let path = write_temp("pbrt", PBRT);
+    write_next_to(&path, "mesh.pbrt", MESH_PBRT);
+    write_next_to(&path, "quad.ply", ply("ascii"));
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+
+    assert_eq!(scene.settings(), RenderSettings {
+        spp: Some(32),
+        max_bounces: Some(3),
+        resolution: Some((200, 100)),
+        integrator: Some(IntegratorKind::Path),
+    });
+    assert_eq!(scene.environment().radiance(Vec3::new(0.0, 1.0, 0.0)), Color::new(0.1, 0.2, 0.3));
+
+    // pbrt's camera frame is left handed, so images have +X on their left
+    let camera = scene.camera();
+    assert!(camera.position().distance(Vec3::new(0.0, 0.0, 5.0)) < 1e-5);
+    assert!(camera.forward().distance(Vec3::new(0.0, 0.0, -1.0)) < 1e-5);
+    assert!(camera.right().distance(Vec3::new(-1.0, 0.0, 0.0)) < 1e-5);
+    assert_eq!(camera.ratio(), 2.0);
+
+    // The sphere is skipped
+    let objects = scene.objects();
+    assert_eq!(objects.len(), 3);
+
+    // The area light faces down, with a luminance of its scale
+    assert_eq!(objects[0].area(), 4.0);
+    assert!((objects[0].material().emissive.luminance() - 4.0).abs() < 0.05);
+    let hit = (&objects[0]).hit(Ray::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0))).unwrap();
+    assert!(hit.front_face);
+    assert_eq!(hit.material().unwrap().emission(hit.front_face), objects[0].material().emissive);
+
+    // Being one sided, it is opaque but dark from above
+    let hit = (&objects[0]).hit(Ray::new(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0))).unwrap();
+    assert!(!hit.front_face);
+    assert!(hit.material().unwrap().emission(hit.front_face).is_zero());
+
+    let gold = objects[1].material();
+    assert_eq!(objects[1].area(), 8.0);
+    assert_eq!(gold.metallic, 1.0);
+    assert!(gold.color.r > gold.color.g && gold.color.g > gold.color.b);
+    assert!((gold.roughness - 0.01_f32.powf(0.25)).abs() < 1e-6);
+
+    // The included PLY mesh is turned around by ReverseOrientation
+    assert_eq!(objects[2].material().ior, 1.33);
+    assert_eq!(objects[2].material().transmission, 1.0);
+    let hit = (&objects[2]).hit(Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0))).unwrap();
+    assert!(hit.front_face);
+}
+
+#[test]
+fn pbrt_errors() {
+    let error = |name: &str, path: PathBuf| match import_scene(path, NormalGeneration::Flat) {
+        Err(err) => err.to_string(),
+        Ok(_) => panic!("{} should be rejected", name),
+    };
+
+    assert!(error("pbrt_directive", write_temp("pbrt", "WorldBegin\nAttributeBegin\nFrobnicate 1\n")).ends_with(":3: unknown directive Frobnicate"));
+    assert!(error("pbrt_unmatched", write_temp("pbrt", "WorldBegin\nAttributeEnd\n")).ends_with(":2: unmatched AttributeEnd"));
+    assert!(error("pbrt_translate", write_temp("pbrt", "Translate 1 2\n")).ends_with(":1: expected 3 numbers, got 2"));
+    assert!(error("pbrt_include", write_temp("pbrt", "Include \"missing.pbrt\"\n")).contains("unable to read"));
+    let cycle = write_temp("pbrt", "Include \"loop.pbrt\"\n");
+    write_next_to(&cycle, "loop.pbrt", "Import \"scene.pbrt\"\n");
+    assert!(error("pbrt_cycle", cycle).ends_with("scene.pbrt includes itself"));
+    assert!(error("pbrt_indices", write_temp("pbrt", "Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0] \"integer indices\" [0 1 5]\n")).contains("out of the 3 vertices"));
+    assert!(error("pbrt_negative_index", write_temp("pbrt", "Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0] \"integer indices\" [0 1 -1]\n")).ends_with("\"indices\": invalid index or count -1"));
+    assert!(error("pbrt_fractional_index", write_temp("pbrt", "Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0] \"integer indices\" [0 1.5 2]\n")).ends_with("\"indices\": invalid index or count 1.5"));
+    assert!(error("pbrt_huge_index", write_temp("pbrt", "Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0] \"integer indices\" [0 1 4294967298]\n")).ends_with("\"indices\": invalid index or count 4294967298"));
+}
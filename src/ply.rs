@@ -0,0 +1,326 @@
+use crate::vec::*;
+use crate::color::*;
+use crate::material::*;
+use crate::scene::*;
+use crate::normals::*;
+use crate::utils::*;
+
+use std::path::Path;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyProperty {
+    Scalar(PlyType),
+    // Count type and item type
+    List(PlyType, PlyType),
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<(String, PlyProperty)>,
+}
+
+// Vertex attributes and triangles of a PLY file, normals and colors are None if the vertices don't have them
+pub struct PlyMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub colors: Option<Vec<Color>>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+// Values after the header, read one at a time as f64, which holds every PLY type exactly
+enum PlyBody<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], pos: usize, big_endian: bool },
+}
+
+
+// The mesh gets a default double sided material, whose color is white if the vertices have colors
+pub fn import_ply(path: &Path, builder: &mut SceneBuilder, normal_generation: NormalGeneration) -> Result<(), SceneError> {
+    builder.add_source_file(path);
+    let ply = read_ply(path)?;
+
+    let material = Material {
+        color: if ply.colors.is_some() { Color::from(1.0) } else { Material::default().color },
+        double_sided: true,
+        ..Material::default()
+    };
+
+    match build_mesh(ply.positions, ply.normals, ply.colors, None, ply.triangles, material, normal_generation) {
+        Ok(Some(mesh)) => builder.push(mesh),
+        Ok(None) => eprintln!("Skipping {}: no triangles", path.display()),
+        Err(reason) => return Err(SceneError::InvalidGeometry { location: path.display().to_string(), reason: reason }),
+    }
+
+    Ok(())
+}
+
+// Reads vertex positions, normals and colors, and faces, triangulated as fans
+pub fn read_ply(path: &Path) -> Result<PlyMesh, SceneError> {
+    let data = std::fs::read(path)?;
+    let parse_error = |msg: String| SceneError::Parse(format!("{}: {}", path.display(), msg));
+
+    let (format, elements, body_start) = parse_header(&data).map_err(parse_error)?;
+    let mut body = match format {
+        PlyFormat::Ascii => PlyBody::Ascii(std::str::from_utf8(&data[body_start..]).map_err(|_| parse_error("invalid ASCII data".to_string()))?.split_ascii_whitespace()),
+        PlyFormat::BinaryLittleEndian => PlyBody::Binary { data: &data[body_start..], pos: 0, big_endian: false },
+        PlyFormat::BinaryBigEndian => PlyBody::Binary { data: &data[body_start..], pos: 0, big_endian: true },
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut triangles = Vec::new();
+
+    let mut values = Vec::new();
+    let mut face = Vec::new();
+    for element in &elements {
+        let property_index = |name: &str| element.properties.iter().position(|(n, _)| n == name);
+        let xyz = |names: [&str; 3]| -> Option<[usize; 3]> { Some([property_index(names[0])?, property_index(names[1])?, property_index(names[2])?]) };
+        values.resize(element.properties.len(), 0.0);
+
+        match element.name.as_str() {
+            "vertex" => {
+                let position_props = xyz(["x", "y", "z"]).ok_or_else(|| parse_error("vertices have no position".to_string()))?;
+                let normal_props = xyz(["nx", "ny", "nz"]);
+                let color_props = xyz(["red", "green", "blue"]).or_else(|| xyz(["r", "g", "b"]));
+
+                // Integer colors are normalized by the type's maximum. 8 bit colors are sRGB, others are taken as linear
+                let color_type = color_props.and_then(|props| match element.properties[props[0]].1 {
+                    PlyProperty::Scalar(ty) => Some(ty),
+                    PlyProperty::List(_, _) => None,
+                });
+                let srgb = color_type == Some(PlyType::UInt8);
+                let color_scale = color_type.and_then(|ty| ty.max()).map(|max| 1.0 / max as f32).unwrap_or(1.0);
+
+                for _ in 0..element.count {
+                    body.read_row(&element.properties, &mut values, None, &mut face).map_err(parse_error)?;
+
+                    let vec3 = |props: [usize; 3]| Vec3::new(values[props[0]] as f32, values[props[1]] as f32, values[props[2]] as f32);
+                    positions.push(vec3(position_props));
+                    if let Some(props) = normal_props {
+                        normals.push(vec3(props).normalized());
+                    }
+                    if let Some(props) = color_props {
+                        let c = vec3(props);
+                        colors.push(if srgb {
+                            SRgbColor { r: c.x as u8, g: c.y as u8, b: c.z as u8 }.to_linear()
+                        } else {
+                            let c = c * color_scale;
+                            Color::new(c.x.max(0.0), c.y.max(0.0), c.z.max(0.0))
+                        });
+                    }
+                }
+            },
+
+            "face" => {
+                let indices_prop = property_index("vertex_indices").or_else(|| property_index("vertex_index"))
+                    .filter(|p| matches!(element.properties[*p].1, PlyProperty::List(_, _)))
+                    .ok_or_else(|| parse_error("faces have no vertex_indices list".to_string()))?;
+
+                for f in 0..element.count {
+                    body.read_row(&element.properties, &mut values, Some(indices_prop), &mut face).map_err(parse_error)?;
+                    if face.len() < 3 {
+                        return Err(parse_error(format!("face {} has {} vertices", f, face.len())));
+                    }
+                    for i in 1..face.len() - 1 {
+                        triangles.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+            },
+
+            // Edges, materials and other custom elements
+            _ => {
+                for _ in 0..element.count {
+                    body.read_row(&element.properties, &mut values, None, &mut face).map_err(parse_error)?;
+                }
+            },
+        }
+    }
+
+    Ok(PlyMesh {
+        positions: positions,
+        normals: if normals.is_empty() { None } else { Some(normals) },
+        colors: if colors.is_empty() { None } else { Some(colors) },
+        triangles: triangles,
+    })
+}
+
+
+// Returns the format, the elements, and where the body starts
+fn parse_header(data: &[u8]) -> Result<(PlyFormat, Vec<PlyElement>, usize), String> {
+    // The body can be binary, so the header is split into lines up to the one that is end_header, and not searched for it
+    let mut header = Vec::new();
+    let mut body_start = 0;
+    loop {
+        let rest = &data[body_start..];
+        let len = match rest.iter().position(|b| *b == b'\n') {
+            Some(len) => len,
+            None if rest.trim_ascii() == b"end_header" => return Err("no data after end_header".to_string()),
+            None => return Err("no end_header".to_string()),
+        };
+        let line = std::str::from_utf8(&rest[..len]).map_err(|_| "header is not text")?;
+        body_start += len + 1;
+
+        if line.trim() == "end_header" {
+            break;
+        }
+        header.push(line);
+    }
+
+    let mut lines = header.into_iter().map(|line| line.split_whitespace().collect::<Vec<_>>()).filter(|tokens| !tokens.is_empty());
+    if lines.next() != Some(vec!["ply"]) {
+        return Err("not a PLY file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for tokens in lines {
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(format!("unknown format \"{}\"", name)),
+                });
+            },
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| format!("invalid element count \"{}\"", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let property = PlyProperty::List(parse_type(count_type)?, parse_type(item_type)?);
+                elements.last_mut().ok_or("property before any element")?.properties.push((name.to_string(), property));
+            },
+            ["property", ty, name] => {
+                let property = PlyProperty::Scalar(parse_type(ty)?);
+                elements.last_mut().ok_or("property before any element")?.properties.push((name.to_string(), property));
+            },
+            ["comment", ..] | ["obj_info", ..] => {},
+            _ => return Err(format!("unexpected header line \"{}\"", tokens.join(" "))),
+        }
+    }
+
+    Ok((format.ok_or("no format")?, elements, body_start))
+}
+
+fn parse_type(name: &str) -> Result<PlyType, String> {
+    Ok(match name {
+        "char" | "int8" => PlyType::Int8,
+        "uchar" | "uint8" => PlyType::UInt8,
+        "short" | "int16" => PlyType::Int16,
+        "ushort" | "uint16" => PlyType::UInt16,
+        "int" | "int32" => PlyType::Int32,
+        "uint" | "uint32" => PlyType::UInt32,
+        "float" | "float32" => PlyType::Float32,
+        "double" | "float64" => PlyType::Float64,
+        _ => return Err(format!("unknown type \"{}\"", name)),
+    })
+}
+
+
+impl PlyType {
+    fn size(&self) -> usize {
+        match self {
+            PlyType::Int8 | PlyType::UInt8 => 1,
+            PlyType::Int16 | PlyType::UInt16 => 2,
+            PlyType::Int32 | PlyType::UInt32 | PlyType::Float32 => 4,
+            PlyType::Float64 => 8,
+        }
+    }
+
+    // Largest value of integer types, None for floats
+    fn max(&self) -> Option<f64> {
+        match self {
+            PlyType::Int8 => Some(i8::MAX as f64),
+            PlyType::UInt8 => Some(u8::MAX as f64),
+            PlyType::Int16 => Some(i16::MAX as f64),
+            PlyType::UInt16 => Some(u16::MAX as f64),
+            PlyType::Int32 => Some(i32::MAX as f64),
+            PlyType::UInt32 => Some(u32::MAX as f64),
+            PlyType::Float32 | PlyType::Float64 => None,
+        }
+    }
+}
+
+
+impl<'a> PlyBody<'a> {
+    fn read(&mut self, ty: PlyType) -> Result<f64, String> {
+        match self {
+            PlyBody::Ascii(tokens) => {
+                let token = tokens.next().ok_or("unexpected end of data")?;
+                token.parse::<f64>().map_err(|_| format!("invalid number \"{}\"", token))
+            },
+
+            PlyBody::Binary { data, pos, big_endian } => {
+                let bytes = data.get(*pos..*pos + ty.size()).ok_or("unexpected end of data")?;
+                *pos += ty.size();
+
+                macro_rules! decode {
+                    ($t:ty) => {{
+                        let bytes = bytes.try_into().unwrap();
+                        (if *big_endian { <$t>::from_be_bytes(bytes) } else { <$t>::from_le_bytes(bytes) }) as f64
+                    }};
+                }
+
+                Ok(match ty {
+                    PlyType::Int8 => decode!(i8),
+                    PlyType::UInt8 => decode!(u8),
+                    PlyType::Int16 => decode!(i16),
+                    PlyType::UInt16 => decode!(u16),
+                    PlyType::Int32 => decode!(i32),
+                    PlyType::UInt32 => decode!(u32),
+                    PlyType::Float32 => decode!(f32),
+                    PlyType::Float64 => decode!(f64),
+                })
+            },
+        }
+    }
+
+    // Reads one row of an element, with a value per property. Lists are skipped, with their count as value,
+    // except the one at list_index whose items go to list
+    fn read_row(&mut self, properties: &[(String, PlyProperty)], values: &mut [f64], list_index: Option<usize>, list: &mut Vec<u32>) -> Result<(), String> {
+        for (p, ((_, property), value)) in properties.iter().zip(values.iter_mut()).enumerate() {
+            match property {
+                PlyProperty::Scalar(ty) => *value = self.read(*ty)?,
+                PlyProperty::List(count_type, item) => {
+                    let count = f64_to_index(self.read(*count_type)?)? as usize;
+                    *value = count as f64;
+                    if list_index == Some(p) {
+                        list.clear();
+                        for _ in 0..count {
+                            list.push(f64_to_index(self.read(*item)?)?);
+                        }
+                    } else {
+                        for _ in 0..count {
+                            self.read(*item)?;
+                        }
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
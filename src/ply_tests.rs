@@ -0,0 +1,125 @@
+// Imports PLY meshes in every format, with vertex colors, and the malformed or truncated files that must be rejected.
+
+use crate::scene::*;
+use crate::normals::*;
+use crate::surface::*;
+use crate::color::*;
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::test_files::*;
+
+
+// A 2x2 quad in the XY plane, one color per vertex, followed by an element that must be skipped
+pub fn ply(format: &str) -> Vec<u8> {
+    let header = format!("ply\nformat {} 1.0\ncomment made by the tests\n\
+        element vertex 4\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+        element face 1\nproperty list uchar int vertex_indices\n\
+        element extra 1\nproperty list uchar short values\nproperty double weight\n\
+        end_header\n", format);
+
+    let vertices = [([-1.0, -1.0, 0.0], 255), ([1.0, -1.0, 0.0], 255), ([1.0, 1.0, 0.0], 0), ([-1.0, 1.0, 0.0], 0)];
+    let mut data = header.into_bytes();
+    if format == "ascii" {
+        for (p, c) in vertices {
+            data.extend(format!("{} {} {} {} {} 128\n", p[0], p[1], p[2], c, c).bytes());
+        }
+        data.extend(b"4 0 1 2 3\n2 7 -7 0.5\n");
+        return data;
+    }
+
+    let big_endian = format == "binary_big_endian";
+    let mut bytes = |b: &[u8]| data.extend(if big_endian { b.iter().rev().copied().collect::<Vec<_>>() } else { b.to_vec() });
+    for (p, c) in vertices {
+        for x in p {
+            bytes(&(x as f32).to_le_bytes());
+        }
+        for channel in [c, c, 128] {
+            bytes(&[channel]);
+        }
+    }
+    bytes(&[4]);
+    for i in 0..4i32 {
+        bytes(&i.to_le_bytes());
+    }
+    bytes(&[2]);
+    bytes(&7i16.to_le_bytes());
+    bytes(&(-7i16).to_le_bytes());
+    bytes(&0.5f64.to_le_bytes());
+    data
+}
+
+#[test]
+fn ply_formats() {
+    for format in ["ascii", "binary_little_endian", "binary_big_endian"] {
+        let path = write_temp("ply", ply(format));
+        let scene = import_scene(&path, NormalGeneration::Flat).unwrap_or_else(|err| panic!("{}: {}", format, err));
+        assert_eq!(scene.objects().len(), 1);
+        assert_eq!(scene.objects()[0].area(), 4.0);
+
+        // Halfway between the bottom and top colors, which are decoded from sRGB
+        let hit = (&scene).hit(Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        let bottom = SRgbColor { r: 255, g: 255, b: 128 }.to_linear();
+        let top = SRgbColor { r: 0, g: 0, b: 128 }.to_linear();
+        let expected = (bottom + top) * 0.5;
+        let color = hit.material().unwrap().color;
+        assert!((color.r - expected.r).abs() < 1e-5 && (color.b - expected.b).abs() < 1e-5, "{}: {:?}", format, color);
+    }
+}
+
+#[test]
+fn ply_errors() {
+    let mut truncated = ply("binary_little_endian");
+    truncated.truncate(truncated.len() - 20);
+    let path = write_temp("ply", truncated);
+    assert!(matches!(import_scene(&path, NormalGeneration::Flat), Err(SceneError::Parse(_))));
+
+    let path = write_temp("ply", "ply\nformat ascii 1.0\nelement vertex 0\n");
+    assert!(matches!(import_scene(&path, NormalGeneration::Flat), Err(SceneError::Parse(_))));
+
+    let path = write_temp("ply", "ply\nformat ascii 1.0\nelement vertex 0\nend_header");
+    match import_scene(&path, NormalGeneration::Flat) {
+        Err(SceneError::Parse(msg)) => assert!(msg.contains("no data after end_header"), "{}", msg),
+        _ => panic!("header without a body should be rejected"),
+    }
+
+    // Indices stored as floats or signed types must still be valid indices
+    for (name, face) in [("negative_index", "3 0 1 -1"), ("fractional_index", "3 0 1 1.5")] {
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar float vertex_indices\nend_header\n";
+        let path = write_temp("ply", format!("{}0 0 0\n1 0 0\n0 1 0\n{}\n", header, face));
+        match import_scene(&path, NormalGeneration::Flat) {
+            Err(SceneError::Parse(msg)) => assert!(msg.contains("invalid index"), "{}: {}", name, msg),
+            _ => panic!("{} should be rejected", name),
+        }
+    }
+}
+
+// Only a line that is end_header ends the header, not the word in a comment
+#[test]
+fn ply_end_header_line() {
+    for format in ["ascii", "binary_little_endian"] {
+        let original = ply(format);
+        let header_len = original.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+        let header = std::str::from_utf8(&original[..header_len]).unwrap();
+
+        // The body is kept as is, binary or not
+        let mut data = header.replace("comment made by the tests\n", "comment end_header comes last\r\nobj_info not end_header\n").into_bytes();
+        data.extend(&original[header_len..]);
+
+        let path = write_temp("ply", data);
+        let scene = import_scene(&path, NormalGeneration::Flat).unwrap_or_else(|err| panic!("{}: {}", format, err));
+        assert_eq!(scene.objects()[0].area(), 4.0);
+    }
+}
+
+#[test]
+fn ply_ushort_colors() {
+    let path = write_temp("ply", "ply\nformat ascii 1.0\nelement vertex 3\n\
+        property float x\nproperty float y\nproperty float z\nproperty ushort red\nproperty ushort green\nproperty ushort blue\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+        -1 -1 0 65535 0 0\n1 -1 0 65535 0 0\n0 1 0 65535 0 0\n3 0 1 2\n");
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    let hit = (&scene).hit(Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+    assert_eq!(hit.material().unwrap().color, Color::new(1.0, 0.0, 0.0));
+}
@@ -0,0 +1,48 @@
+use std::time::{Instant, Duration};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::io::Write;
+
+
+pub struct Progress {
+    // Shown before the percentage, on the same line
+    label: String,
+    total: usize,
+    done: AtomicUsize,
+    start: Instant,
+}
+
+
+impl Progress {
+    pub fn new(label: String, total: usize) -> Progress {
+        Progress {
+            label: label,
+            total: total,
+            done: AtomicUsize::new(0),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn advance(&self, count: usize) {
+        let done = self.done.fetch_add(count, Ordering::Relaxed) + count;
+        self.report(done);
+    }
+
+    pub fn finish(&self) {
+        eprintln!();
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now() - self.start
+    }
+
+    fn report(&self, done: usize) {
+        let ratio = if self.total == 0 { 1.0 } else { (done as f64 / self.total as f64).min(1.0) };
+
+        let elapsed = self.elapsed().as_secs_f64();
+        let eta = if ratio > 0.0 { elapsed / ratio - elapsed } else { 0.0 };
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r{}: {:6.2}% (ETA {:.1?})   ", self.label, ratio * 100.0, Duration::from_secs_f64(eta.max(0.0)));
+        let _ = stderr.flush();
+    }
+}
@@ -17,17 +17,9 @@ impl Ray {
         }
     }
 
-    pub fn new_with_epsilon(orig: Vec3, dir: Vec3) -> Ray {
-        Ray::new_with_offset(orig, dir, EPSILON)
-    }
-
-    pub fn new_with_offset(orig: Vec3, dir: Vec3, offset: f32) -> Ray {
-        let ray = Ray::new(orig, dir);
-        Ray {
-            orig: ray.orig + ray.dir * offset,
-            dir: ray.dir,
-            max: f32::MAX,
-        }
+    // Ray leaving a surface point, whose origin is moved out of the point's error bounds so it can't hit the surface again
+    pub fn spawn(pos: Vec3, error: Vec3, geom_norm: Vec3, dir: Vec3) -> Ray {
+        Ray::new(offset_ray_origin(pos, error, geom_norm, dir), dir)
     }
 
     pub fn along(&self, t: f32) -> Vec3 {
@@ -44,3 +36,24 @@ impl Ray {
 }
 
 
+
+
+// Pushes pos along the geometric normal, to the side dir points to, far enough to leave the box of its floating point error (PBRT 6.8.6)
+pub fn offset_ray_origin(pos: Vec3, error: Vec3, geom_norm: Vec3, dir: Vec3) -> Vec3 {
+    let dist = geom_norm.abs().dot(error);
+    let offset = if dir.dot(geom_norm) < 0.0 { geom_norm * -dist } else { geom_norm * dist };
+
+    // Rounding the sum could bring the point back inside the error box, so round away from the surface
+    let orig = pos + offset;
+    let round = |o: f32, off: f32| {
+        if off > 0.0 {
+            next_float_up(o)
+        } else if off < 0.0 {
+            next_float_down(o)
+        } else {
+            o
+        }
+    };
+
+    Vec3::new(round(orig.x, offset.x), round(orig.y, offset.y), round(orig.z, offset.z))
+}
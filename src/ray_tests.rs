@@ -0,0 +1,107 @@
+// Checks that rays spawned from a hit never hit their own triangle again, far from and close to the origin, and the float stepping and error bounds the offset relies on.
+
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::mesh::*;
+use crate::vertex::*;
+use crate::material::*;
+use crate::utils::*;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+
+const RAYS: usize = 2000;
+const BOUNCES: usize = 8;
+
+
+// Tilted triangle of about scale in size, about scale away from the origin
+fn triangle(scale: f32) -> [Vec3; 3] {
+    let base = Vec3::new(1.3, 0.7, -1.1) * scale;
+    [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.9, 0.2, -0.3), Vec3::new(0.1, 0.8, 0.5)].map(|p| base + p * scale)
+}
+
+fn check_spawned_rays(scale: f32) {
+    let tri = triangle(scale);
+    let norm = (tri[1] - tri[0]).cross(tri[2] - tri[0]).normalized();
+    let material = Material {
+        double_sided: true,
+        ..Material::default()
+    };
+    let mesh = Mesh::new(tri.iter().map(|p| Vertex { pos: *p, norm: norm }).collect(), vec![[0, 1, 2]], material);
+
+    let mut rng = StdRng::seed_from_u64(scale.to_bits() as u64);
+    let mut hits = 0;
+    for _ in 0..RAYS {
+        // From either side, towards a random point of the triangle
+        let (u, v) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let (u, v) = if u + v > 1.0 { (1.0 - u, 1.0 - v) } else { (u, v) };
+        let target = tri[0] + (tri[1] - tri[0]) * u + (tri[2] - tri[0]) * v;
+        let orig = target + random_unit_vector(&mut rng) * scale * 3.0;
+
+        let hit = match (&mesh).hit(Ray::new(orig, target - orig)) {
+            Some(hit) => hit,
+            None => continue,
+        };
+        hits += 1;
+
+        for _ in 0..BOUNCES {
+            let dir = random_unit_vector(&mut rng);
+            let ray = hit.spawn_ray(dir);
+
+            // The origin left the surface on the side the ray goes to
+            let side = (ray.orig - hit.pos).dot(hit.geom_norm);
+            assert!(side != 0.0 && (side > 0.0) == (dir.dot(hit.geom_norm) > 0.0), "{:?} is on the wrong side of {:?}", ray.orig, hit.pos);
+            assert!(tri.hit(ray).is_none(), "ray from {:?} towards {:?} hit its own triangle", ray.orig, dir);
+        }
+    }
+
+    // Rays towards the edges may miss, most can't
+    assert!(hits > RAYS * 9 / 10, "only {} hits", hits);
+}
+
+
+#[test]
+fn spawned_rays_far_from_origin() {
+    check_spawned_rays(1e4);
+}
+
+#[test]
+fn spawned_rays_close_to_origin() {
+    check_spawned_rays(1e-3);
+}
+
+#[test]
+fn next_float() {
+    let smallest = f32::from_bits(1);
+
+    assert_eq!(next_float_up(0.0), smallest);
+    assert_eq!(next_float_up(-0.0), smallest);
+    assert_eq!(next_float_down(0.0), -smallest);
+    assert_eq!(next_float_down(-0.0), -smallest);
+
+    assert_eq!(next_float_up(f32::INFINITY), f32::INFINITY);
+    assert_eq!(next_float_down(f32::NEG_INFINITY), f32::NEG_INFINITY);
+    assert_eq!(next_float_up(f32::NEG_INFINITY), -f32::MAX);
+    assert_eq!(next_float_down(f32::INFINITY), f32::MAX);
+    assert_eq!(next_float_up(f32::MAX), f32::INFINITY);
+
+    for x in [1.0, -1.0, 1e-3, -1e4, smallest, -smallest, f32::MIN_POSITIVE] {
+        let (up, down) = (next_float_up(x), next_float_down(x));
+        assert!(down < x && x < up, "{} is not between {} and {}", x, down, up);
+        assert_eq!(next_float_down(up), x);
+        assert_eq!(next_float_up(down), x);
+    }
+}
+
+#[test]
+fn error_bounds() {
+    assert_eq!(gamma(0), 0.0);
+    assert!(gamma(1) >= MACHINE_EPSILON);
+    for n in 1..10 {
+        assert!(gamma(n + 1) > gamma(n));
+        // Close to n ulps of 1 while n * MACHINE_EPSILON is small
+        assert!((gamma(n) / (n as f32 * MACHINE_EPSILON) - 1.0).abs() < 1e-5);
+    }
+}
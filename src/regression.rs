@@ -0,0 +1,367 @@
+// Renders small canned scenes and checks them against stored references.
+// Set RT_UPDATE_REFERENCES=1 to re-render the references after an intended change to the output.
+
+use crate::vec::*;
+use crate::vertex::*;
+use crate::mesh::*;
+use crate::transform::*;
+use crate::camera::*;
+use crate::scene::*;
+use crate::color::*;
+use crate::material::*;
+use crate::integrator::*;
+use crate::image::*;
+use crate::tile::*;
+use crate::film::*;
+use crate::{pixel_rng, trace, resume_checkpoint, parse_args, Args, PASS_SPP};
+
+use rayon::prelude::*;
+
+use std::path::PathBuf;
+use std::fs;
+use std::time::Duration;
+
+
+const SIZE: u32 = 64;
+const TEST_SPP: usize = 64;
+const REFERENCE_SPP: usize = 1024;
+const MAX_BOUNCES: usize = 4;
+
+// References use sample indices the test never reaches, so both renders are independent
+const REFERENCE_SAMPLE_OFFSET: usize = 1 << 20;
+
+// The difference is expected to be pure noise: its mean squared value should match the estimated variance,
+// and its sum over the image shouldn't drift more than a few standard deviations away from 0
+const MAX_NOISE_RATIO: f32 = 3.0;
+const MAX_BIAS_SIGMAS: f32 = 5.0;
+
+
+fn quad_mesh(quads: &[[Vec3; 4]], material: Material) -> Mesh {
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    for quad in quads {
+        // Quads are counter clockwise when seen from their front
+        let norm = (quad[1] - quad[0]).cross(quad[2] - quad[0]).normalized();
+        let base = vertices.len() as u32;
+        vertices.extend(quad.iter().map(|p| Vertex { pos: *p, norm: norm }));
+        triangles.push([base, base + 1, base + 2]);
+        triangles.push([base, base + 2, base + 3]);
+    }
+    Mesh::new(vertices, triangles, material)
+}
+
+// Outward facing sides of an axis aligned box, without the bottom
+fn box_quads(min: Vec3, max: Vec3) -> Vec<[Vec3; 4]> {
+    let v = |x: f32, y: f32, z: f32| Vec3::new(x, y, z);
+    let (x0, y0, z0) = (min.x, min.y, min.z);
+    let (x1, y1, z1) = (max.x, max.y, max.z);
+    vec![
+        [v(x0, y1, z1), v(x1, y1, z1), v(x1, y1, z0), v(x0, y1, z0)],
+        [v(x0, y0, z1), v(x1, y0, z1), v(x1, y1, z1), v(x0, y1, z1)],
+        [v(x1, y0, z0), v(x0, y0, z0), v(x0, y1, z0), v(x1, y1, z0)],
+        [v(x0, y0, z0), v(x0, y0, z1), v(x0, y1, z1), v(x0, y1, z0)],
+        [v(x1, y0, z1), v(x1, y0, z0), v(x1, y1, z0), v(x1, y1, z1)],
+    ]
+}
+
+fn diffuse(r: f32, g: f32, b: f32) -> Material {
+    Material {
+        color: Color::new(r, g, b),
+        ..Material::default()
+    }
+}
+
+fn light(strength: f32) -> Material {
+    Material {
+        color: Color::from(0.0),
+        emissive: Color::from(strength),
+        ..Material::default()
+    }
+}
+
+fn v(x: f32, y: f32, z: f32) -> Vec3 {
+    Vec3::new(x, y, z)
+}
+
+
+// Unit Cornell box, open towards +Z, with a square light on the ceiling
+fn cornell_builder() -> SceneBuilder {
+    let white = diffuse(0.73, 0.73, 0.73);
+
+    let mut builder = SceneBuilder::new();
+    builder.push(quad_mesh(&[[v(-1.0, -1.0, 1.0), v(1.0, -1.0, 1.0), v(1.0, -1.0, -1.0), v(-1.0, -1.0, -1.0)]], white));
+    builder.push(quad_mesh(&[[v(-1.0, 1.0, -1.0), v(1.0, 1.0, -1.0), v(1.0, 1.0, 1.0), v(-1.0, 1.0, 1.0)]], white));
+    builder.push(quad_mesh(&[[v(-1.0, -1.0, -1.0), v(1.0, -1.0, -1.0), v(1.0, 1.0, -1.0), v(-1.0, 1.0, -1.0)]], white));
+    builder.push(quad_mesh(&[[v(-1.0, -1.0, 1.0), v(-1.0, -1.0, -1.0), v(-1.0, 1.0, -1.0), v(-1.0, 1.0, 1.0)]], diffuse(0.65, 0.05, 0.05)));
+    builder.push(quad_mesh(&[[v(1.0, -1.0, -1.0), v(1.0, -1.0, 1.0), v(1.0, 1.0, 1.0), v(1.0, 1.0, -1.0)]], diffuse(0.12, 0.45, 0.15)));
+    builder.push(quad_mesh(&[[v(-0.3, 0.99, -0.3), v(0.3, 0.99, -0.3), v(0.3, 0.99, 0.3), v(-0.3, 0.99, 0.3)]], light(15.0)));
+    builder.set_camera(Camera::new(Transform::identity().with_pos(v(0.0, 0.0, 3.4)), 0.7, 1.0));
+    builder
+}
+
+fn cornell_empty() -> Scene {
+    cornell_builder().build()
+}
+
+fn cornell_boxes() -> Scene {
+    let white = diffuse(0.73, 0.73, 0.73);
+
+    let mut builder = cornell_builder();
+    builder.push(quad_mesh(&box_quads(v(0.05, -1.0, 0.0), v(0.65, -0.4, 0.6)), white));
+    builder.push(quad_mesh(&box_quads(v(-0.65, -1.0, -0.65), v(-0.05, 0.2, -0.05)), white));
+    builder.build()
+}
+
+// Many small objects, so the scene BVH gets several levels
+fn cube_grid() -> Scene {
+    let mut builder = SceneBuilder::new();
+    builder.push(quad_mesh(&[[v(-2.0, 0.0, 2.0), v(2.0, 0.0, 2.0), v(2.0, 0.0, -2.0), v(-2.0, 0.0, -2.0)]], diffuse(0.73, 0.73, 0.73)));
+    builder.push(quad_mesh(&[[v(-1.0, 3.0, -1.0), v(1.0, 3.0, -1.0), v(1.0, 3.0, 1.0), v(-1.0, 3.0, 1.0)]], light(4.0)));
+
+    for i in 0..25 {
+        let (x, z) = ((i % 5) as f32 * 0.6 - 1.2, (i / 5) as f32 * 0.6 - 1.2);
+        let height = 0.2 + (i % 3) as f32 * 0.2;
+        let color = diffuse(0.2 + (i % 5) as f32 * 0.15, 0.8 - (i / 5) as f32 * 0.15, 0.5);
+        builder.push(quad_mesh(&box_quads(v(x - 0.2, 0.0, z - 0.2), v(x + 0.2, height, z + 0.2)), color));
+    }
+
+    // Looking down at the grid from the front
+    let pitch = 0.6_f32;
+    let basis = Transform::from_basis(v(1.0, 0.0, 0.0), v(0.0, pitch.cos(), -pitch.sin()), v(0.0, pitch.sin(), pitch.cos()));
+    builder.set_camera(Camera::new(basis.with_pos(v(0.0, 2.2, 3.4)), 0.8, 1.0));
+    builder.build()
+}
+
+
+fn render(scene: &Scene, spp: usize, sample_offset: usize) -> Film {
+    let camera = scene.camera();
+    let no_hit = |_| Color::from(0.0);
+
+    let mut film = Film::new(SIZE, SIZE);
+    let tiles = generate_tiles(SIZE, SIZE, 16, TileOrder::Scanline);
+    let traced = tiles.par_iter().map(|tile| {
+        let mut data = film.tile(tile);
+        for (stats, (x, y)) in data.pixels.iter_mut().zip(tile.pixels()) {
+            let mut rng = pixel_rng(x, y, SIZE, sample_offset);
+            for _ in 0..spp {
+                let ray = Integrator::generate_ray(&camera, x, y, SIZE, SIZE, &mut rng);
+                stats.add(Integrator::trace(scene, ray, &no_hit, &mut rng, MAX_BOUNCES));
+            }
+        }
+        (*tile, data)
+    }).collect::<Vec<_>>();
+
+    for (tile, data) in traced {
+        film.set_tile(&tile, data);
+    }
+
+    film
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "references", &format!("{}.exr", name)].iter().collect()
+}
+
+fn check_scene(name: &str, scene: Scene) {
+    let path = reference_path(name);
+    if std::env::var_os("RT_UPDATE_REFERENCES").is_some() {
+        let reference = render(&scene, REFERENCE_SPP, REFERENCE_SAMPLE_OFFSET).resolve(|c| c);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        reference.save(&path).unwrap();
+    }
+
+    let reference = Image::load(&path).unwrap_or_else(|err| panic!("unable to load {}: {}", path.display(), err));
+    assert!(reference.width() == SIZE && reference.height() == SIZE);
+
+    let film = render(&scene, TEST_SPP, 0);
+    let image = film.resolve(|c| c);
+    let variance = film.buffer_variance_image();
+
+    // The reference is noisy too, just less so
+    let variance_scale = 1.0 + TEST_SPP as f64 / REFERENCE_SPP as f64;
+
+    let channels = |c: Color| [c.r as f64, c.g as f64, c.b as f64];
+    let mut squared_error = 0.0;
+    let mut expected = [0.0; 3];
+    let mut bias = [0.0; 3];
+    for ((t, r), var) in image.pixels().iter().zip(reference.pixels()).zip(variance.pixels()) {
+        for (c, ((t, r), var)) in channels(*t).into_iter().zip(channels(*r)).zip(channels(*var)).enumerate() {
+            let diff = t - r;
+            squared_error += diff * diff;
+            expected[c] += var * variance_scale;
+            bias[c] += diff;
+        }
+    }
+
+    let noise_ratio = (squared_error / expected.iter().sum::<f64>().max(f64::MIN_POSITIVE)) as f32;
+    assert!(noise_ratio <= MAX_NOISE_RATIO, "{}: squared error is {:.2} times the expected noise", name, noise_ratio);
+
+    // Pixels are independent, so the variance of the sum is the sum of their variances
+    for (c, (b, var)) in bias.iter().zip(expected).enumerate() {
+        let sigmas = (b / var.sqrt().max(f64::MIN_POSITIVE)).abs() as f32;
+        assert!(sigmas <= MAX_BIAS_SIGMAS, "{}: channel {} is biased by {:.2} standard deviations", name, c, sigmas);
+    }
+}
+
+
+#[test]
+fn regression_cornell_empty() {
+    check_scene("cornell_empty", cornell_empty());
+}
+
+#[test]
+fn regression_cornell_boxes() {
+    check_scene("cornell_boxes", cornell_boxes());
+}
+
+#[test]
+fn regression_cube_grid() {
+    check_scene("cube_grid", cube_grid());
+}
+
+// Stopping after the first pass and resuming from its checkpoint gives the same image as rendering in one go
+#[test]
+fn regression_resume() {
+    let scene = |spp: usize| {
+        let mut builder = cornell_builder();
+        builder.set_render_settings(RenderSettings {
+            spp: Some(spp),
+            resolution: Some((24, 16)),
+            ..RenderSettings::default()
+        });
+        builder.build()
+    };
+
+    let dir = std::env::temp_dir().join(format!("rt_resume_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let checkpoint_file = dir.join("interrupted.checkpoint");
+    let args = |checkpoint: bool, resume: bool, max_passes: Option<usize>| Args {
+        resume: resume,
+        checkpoint_file: if checkpoint { Some(checkpoint_file.clone()) } else { None },
+        max_passes: max_passes,
+        ..parse_args(std::iter::empty()).unwrap()
+    };
+
+    let (full, _, _, _) = trace(&scene(PASS_SPP * 2), &args(false, false, None), None, |_, _| {}, |_| {});
+
+    // Interrupted after its first pass, then resumed with the same settings, as main does
+    let (_, _, _, stats) = trace(&scene(PASS_SPP * 2), &args(true, false, Some(1)), None, |_, _| {}, |_| {});
+    assert_eq!(stats.passes, 1);
+    let resume_args = args(true, true, None);
+    let checkpoint = resume_checkpoint(&scene(PASS_SPP * 2), &resume_args).unwrap().unwrap();
+    assert_eq!(checkpoint.passes, 1);
+    let (resumed, _, _, _) = trace(&scene(PASS_SPP * 2), &resume_args, Some(checkpoint), |_, _| {}, |_| {});
+
+    // Another spp would mix samples of two different renders
+    match resume_checkpoint(&scene(PASS_SPP * 3), &resume_args) {
+        Err(err) => assert!(err.to_string().contains("spp"), "{}", err),
+        Ok(_) => panic!("checkpoint of another spp should be rejected"),
+    }
+
+    // Only the render that asked for a checkpoint wrote one
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(full.pixels().iter().any(|c| c.r > 0.0));
+    assert!(full.pixels() == resumed.pixels(), "resumed render doesn't match the uninterrupted one");
+}
+
+// A render with a time budget runs at least one pass, whatever the budget, and reports the samples it got to
+#[test]
+fn regression_time_budget() {
+    let mut builder = cornell_builder();
+    builder.set_render_settings(RenderSettings {
+        resolution: Some((24, 16)),
+        ..RenderSettings::default()
+    });
+    let scene = builder.build();
+
+    let parse = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string())).map(|args| args.time_budget);
+    assert_eq!(parse(&["--time-budget", "2.5"]), Ok(Some(Duration::from_millis(2500))));
+    assert!(parse(&["--time-budget", "0"]).is_err());
+    assert!(parse(&["--time-budget"]).is_err());
+
+    let args = |budget: Duration| Args {
+        time_budget: Some(budget),
+        ..parse_args(std::iter::empty()).unwrap()
+    };
+
+    let (image, _, _, stats) = trace(&scene, &args(Duration::from_nanos(1)), None, |_, _| {}, |_| {});
+    assert_eq!(stats.passes, 1);
+    assert_eq!(stats.average_spp, PASS_SPP as f64);
+    assert_eq!(stats.max_spp, PASS_SPP);
+    assert!(image.pixels().iter().any(|c| c.r > 0.0));
+
+    // Every pass adds the same number of samples to every pixel
+    let (_, _, _, stats) = trace(&scene, &args(Duration::from_millis(200)), None, |_, _| {}, |_| {});
+    assert!(stats.passes >= 1);
+    assert_eq!(stats.average_spp, (stats.passes * PASS_SPP) as f64);
+    assert_eq!(stats.max_spp, stats.passes * PASS_SPP);
+}
+
+// The box seen from outside, so that its corners show the background
+#[test]
+fn regression_aovs() {
+    let scene = |spp: usize| {
+        let mut builder = cornell_builder();
+        builder.set_camera(Camera::new(Transform::identity().with_pos(v(0.0, 0.0, 7.0)), 0.7, 1.0));
+        builder.set_render_settings(RenderSettings {
+            spp: Some(spp),
+            resolution: Some((24, 24)),
+            ..RenderSettings::default()
+        });
+        builder.build()
+    };
+
+    let dir = std::env::temp_dir().join(format!("rt_aov_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let aov_file = dir.join("aovs.exr");
+    let args = Args {
+        aov_file: Some(aov_file.clone()),
+        ..parse_args(std::iter::empty()).unwrap()
+    };
+
+    let (_, _, aovs, _) = trace(&scene(PASS_SPP), &args, None, |_, _| {}, |_| {});
+    let aovs = aovs.unwrap();
+
+    let pixels = aovs.object_id.pixels().iter().zip(aovs.material_id.pixels()).zip(aovs.depth.pixels()).zip(aovs.normal.pixels());
+    let (mut background, mut hits) = (0, 0);
+    for (((object_id, material_id), depth), normal) in pixels {
+        // Pixels on the silhouettes mix hits and misses, and keep the IDs of their first sample
+        if *depth == 0.0 {
+            background += 1;
+            assert_eq!((*object_id, *material_id, *normal), (0, 0, Vec3::zero()));
+        } else if *object_id != 0 {
+            hits += 1;
+            assert!(*depth > 0.0);
+            assert!((normal.length() - 1.0).abs() < 1e-4, "normal of length {}", normal.length());
+            assert_eq!(*material_id, scene(PASS_SPP).material_id(object_id - 1) + 1);
+        }
+    }
+    assert!(background > 0 && hits > 0);
+
+    // The white walls share their material, the red and green walls get their own
+    let material_id = |obj_index| scene(PASS_SPP).material_id(obj_index);
+    assert_eq!(material_id(0), material_id(1));
+    assert_eq!(material_id(0), material_id(2));
+    assert_ne!(material_id(0), material_id(3));
+    assert_ne!(material_id(3), material_id(4));
+
+    // IDs don't depend on the number of samples
+    let (_, _, more_samples, _) = trace(&scene(PASS_SPP * 2), &args, None, |_, _| {}, |_| {});
+    let more_samples = more_samples.unwrap();
+    assert!(aovs.object_id.pixels() == more_samples.object_id.pixels());
+    assert!(aovs.material_id.pixels() == more_samples.material_id.pixels());
+
+    let image = exr::prelude::read_all_flat_layers_from_file(&aov_file).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(image.layer_data.len(), 1);
+    let layer = &image.layer_data[0];
+    assert_eq!(layer.attributes.layer_name.as_ref().map(|name| name.to_string()), Some("aovs".to_string()));
+
+    let names = layer.channel_data.list.iter().map(|channel| channel.name.to_string()).collect::<Vec<_>>();
+    for name in ["R", "G", "B", "albedo.R", "albedo.G", "albedo.B", "direct.R", "indirect.B", "normal.X", "normal.Y", "normal.Z", "position.X", "depth.Z", "object_id.ID", "material_id.ID"] {
+        assert!(names.iter().any(|n| n == name), "no {} channel in {:?}", name, names);
+    }
+}
@@ -1,4 +1,9 @@
 use std::path::Path;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::f32::consts::PI;
+use std::io::{self, Read, Write};
 
 use crate::vec::*;
 use crate::vertex::*;
@@ -11,31 +16,61 @@ use crate::camera::*;
 use crate::color::*;
 use crate::surface::*;
 use crate::material::*;
+use crate::normals::*;
+use crate::obj::*;
+use crate::ply::*;
+use crate::scene_file::*;
+use crate::pbrt::*;
+use crate::mitsuba::*;
+use crate::scene_cache::*;
+use crate::environment::*;
+use crate::integrator::*;
+use crate::serialize::*;
 
 use rand::prelude::*;
 
-use gltf;
-
 
 const MAX_OBJECT_PER_NODE: usize = 2;
+const SPHERE_SEGMENTS: u32 = 32;
+const SPHERE_RINGS: u32 = 16;
 
 
 pub type SceneObject = Mesh;
 
+// Render settings a scene file can set, the constants of main.rs are used for the others
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RenderSettings {
+    pub spp: Option<usize>,
+    pub max_bounces: Option<usize>,
+    pub resolution: Option<(u32, u32)>,
+    pub integrator: Option<IntegratorKind>,
+}
+
 
 pub struct Scene {
     objects: Vec<SceneObject>,
-    bvh: Bvh<u32>,
+    material_ids: Vec<u32>,
+    bvh: Bvh,
+    // Object indices in the order of the BVH leaves
+    bvh_objects: Vec<u32>,
 
     emitters: Vec<u32>,
     emitter_area: f32,
 
     camera: Camera,
+    environment: Environment,
+    settings: RenderSettings,
+
+    // Files the scene was imported from, cached scenes get those recorded in their cache
+    source_files: Vec<SourceFile>,
 }
 
 pub struct SceneBuilder {
     objects: Vec<SceneObject>,
-    camera: Camera,
+    camera: Option<Camera>,
+    environment: Environment,
+    settings: RenderSettings,
+    source_files: Vec<SourceFile>,
 }
 
 
@@ -44,6 +79,29 @@ impl Scene {
         self.camera
     }
 
+    pub fn objects(&self) -> &[SceneObject] {
+        &self.objects
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    pub fn source_files(&self) -> &[SourceFile] {
+        &self.source_files
+    }
+
+    pub fn with_source_files(self, files: Vec<SourceFile>) -> Scene {
+        Scene {
+            source_files: files,
+            ..self
+        }
+    }
+
     pub fn sample_emitter_surface<R: RngCore>(&self, rng: &mut R) -> Option<(&SceneObject, Color)> {
         if self.emitters.is_empty() {
             return None;
@@ -52,19 +110,68 @@ impl Scene {
         let index = self.emitters[rng.gen_range(0..self.emitters.len())] as usize;
         let emitter = &self.objects[index];
         let norm = emitter.area() / self.emitter_area;
-        return Some((emitter, emitter.material().emissive * norm));
+        Some((emitter, emitter.material().emissive * norm))
     } 
 
+    // Objects sharing identical materials get the same ID
+    pub fn material_id(&self, obj_index: u32) -> u32 {
+        self.material_ids[obj_index as usize]
+    }
+
+    // The objects and BVHs are written as built, material IDs and emitters are rebuilt when reading
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u64(writer, self.objects.len() as u64)?;
+        for obj in &self.objects {
+            obj.write_to(writer)?;
+        }
+        write_u64(writer, self.bvh_objects.len() as u64)?;
+        for i in &self.bvh_objects {
+            write_u32(writer, *i)?;
+        }
+        self.bvh.write_to(writer)?;
+
+        self.camera.write_to(writer)?;
+        self.environment.write_to(writer)?;
+        self.settings.write_to(writer)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Scene> {
+        let mut scene = Scene::new();
+
+        scene.objects = (0..read_u64(reader)?).map(|_| SceneObject::read_from(reader)).collect::<io::Result<_>>()?;
+        let object_count = scene.objects.len();
+        scene.bvh_objects = (0..read_u64(reader)?).map(|_| match read_u32(reader)? {
+            i if (i as usize) < object_count => Ok(i),
+            _ => Err(invalid_data("object index out of range")),
+        }).collect::<io::Result<_>>()?;
+        scene.bvh = Bvh::read_from(reader, scene.bvh_objects.len())?;
+
+        scene.camera = Camera::read_from(reader)?;
+        scene.environment = Environment::read_from(reader)?;
+        scene.settings = RenderSettings::read_from(reader)?;
+
+        scene.build_material_ids();
+        scene.build_emitters();
+
+        Ok(scene)
+    }
+
 
     fn new() -> Scene {
         Scene {
             objects: Vec::new(),
+            material_ids: Vec::new(),
             bvh: Bvh::empty(),
+            bvh_objects: Vec::new(),
 
             emitters: Vec::new(),
             emitter_area: 0.0,
 
-            camera: Camera::new(Transform::identity(), 60.0_f32.to_radians(), 1.0),
+            camera: Camera::default(),
+            environment: Environment::black(),
+            settings: RenderSettings::default(),
+
+            source_files: Vec::new(),
         }
     }
 
@@ -72,6 +179,18 @@ impl Scene {
         let mut indices = (0..self.objects.len() as u32).collect::<Vec<_>>();
         let object_aabb = |i: &u32| self.objects[*i as usize].aabb();
         self.bvh = Bvh::new(indices.as_mut_slice(), object_aabb, MAX_OBJECT_PER_NODE);
+        self.bvh_objects = indices;
+    }
+
+    // Materials are told apart by their serialized bits
+    fn build_material_ids(&mut self) {
+        let mut ids = HashMap::new();
+        self.material_ids = self.objects.iter().map(|obj| {
+            let mut key = Vec::new();
+            obj.material().write_to(&mut key).expect("writing to a Vec can't fail");
+            let next_id = ids.len() as u32;
+            *ids.entry(key).or_insert(next_id)
+        }).collect();
     }
 
     fn build_emitters(&mut self) {
@@ -81,11 +200,55 @@ impl Scene {
 }
 
 
+impl RenderSettings {
+    // Unset settings are written as 0
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u64(writer, self.spp.map_or(0, |spp| spp as u64 + 1))?;
+        write_u64(writer, self.max_bounces.map_or(0, |bounces| bounces as u64 + 1))?;
+        let (width, height) = self.resolution.unwrap_or((0, 0));
+        write_u32(writer, width)?;
+        write_u32(writer, height)?;
+        write_u32(writer, match self.integrator {
+            None => 0,
+            Some(IntegratorKind::Path) => 1,
+            Some(IntegratorKind::Direct) => 2,
+        })
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<RenderSettings> {
+        let optional = |value: u64| value.checked_sub(1).map(|v| v as usize);
+        let spp = optional(read_u64(reader)?);
+        let max_bounces = optional(read_u64(reader)?);
+        let resolution = match (read_u32(reader)?, read_u32(reader)?) {
+            (0, 0) => None,
+            (0, _) | (_, 0) => return Err(invalid_data("invalid resolution")),
+            resolution => Some(resolution),
+        };
+        let integrator = match read_u32(reader)? {
+            0 => None,
+            1 => Some(IntegratorKind::Path),
+            2 => Some(IntegratorKind::Direct),
+            _ => return Err(invalid_data("invalid integrator")),
+        };
+
+        Ok(RenderSettings {
+            spp: spp,
+            max_bounces: max_bounces,
+            resolution: resolution,
+            integrator: integrator,
+        })
+    }
+}
+
+
 impl SceneBuilder {
     pub fn new() -> SceneBuilder {
         SceneBuilder {
             objects: Vec::new(),
-            camera: Camera::new(Transform::identity(), 60.0_f32.to_radians(), 1.0),
+            camera: None,
+            environment: Environment::black(),
+            settings: RenderSettings::default(),
+            source_files: Vec::new(),
         }
     }
 
@@ -94,16 +257,56 @@ impl SceneBuilder {
     } 
 
     pub fn set_camera(&mut self, camera: Camera) {
-        self.camera = camera;
+        self.camera = Some(camera);
     } 
 
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
+    }
+
+    pub fn set_render_settings(&mut self, settings: RenderSettings) {
+        self.settings = settings;
+    }
+
+    pub fn camera(&self) -> Option<Camera> {
+        self.camera
+    }
+
+    pub fn take_objects(&mut self) -> Vec<SceneObject> {
+        std::mem::take(&mut self.objects)
+    }
+
+    // Importers add every file they read, so that the scene cache can tell when it is outdated.
+    // Files are stamped when added, before reading them if possible, so that changes made while importing are caught
+    pub fn add_source_file(&mut self, path: &Path) {
+        self.add_source_files(vec![SourceFile::new(path)]);
+    }
+
+    pub fn add_source_files(&mut self, files: Vec<SourceFile>) {
+        for file in files {
+            if !self.source_files.iter().any(|f| f.path() == file.path()) {
+                self.source_files.push(file);
+            }
+        }
+    }
+
+    pub fn take_source_files(&mut self) -> Vec<SourceFile> {
+        std::mem::take(&mut self.source_files)
+    }
+
     pub fn build(self) -> Scene {
         let mut scene = Scene::new();
 
         scene.objects = self.objects;
-        scene.camera = self.camera;
+        if let Some(camera) = self.camera {
+            scene.camera = camera;
+        }
+        scene.environment = self.environment;
+        scene.settings = self.settings;
+        scene.source_files = self.source_files;
 
         scene.build_bvh();
+        scene.build_material_ids();
         scene.build_emitters();
 
         scene
@@ -115,13 +318,13 @@ impl<'scene> Hittable for &'scene Scene {
     type Result = HitRecord<'scene>;
 
     fn hit(&self, ray: Ray) -> Option<Self::Result> {
-        self.bvh.trace(ray, |mut r, objects| {
+        self.bvh.trace(ray, &self.bvh_objects, |mut r, objects| {
             let mut hit_rec: Option<Self::Result> = None;
             for i in objects {
                 let obj = &self.objects[*i as usize];
                 if let Some(hit) = obj.hit(r) {
                     r = r.with_max(hit.dist);
-                    hit_rec = Some(hit);
+                    hit_rec = Some(HitRecord { obj_index: Some(*i), ..hit });
                 }
             }
             hit_rec
@@ -131,11 +334,180 @@ impl<'scene> Hittable for &'scene Scene {
 
 
 
-pub fn import_scene<P: AsRef<Path>>(path: P) -> gltf::Result<Scene> {
-    let (document, buffers, _images) = gltf::import(path)?;
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(String),
+    UnsupportedExtension(String),
+    // Location names the broken part of the file, like its node and primitive
+    InvalidGeometry { location: String, reason: String },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{}", err),
+            SceneError::Parse(msg) => write!(f, "parse error: {}", msg),
+            SceneError::UnsupportedExtension(ext) => write!(f, "unsupported scene format \"{}\"", ext),
+            SceneError::InvalidGeometry { location, reason } => write!(f, "invalid geometry in {}: {}", location, reason),
+        }
+    }
+}
+
+impl Error for SceneError {
+}
+
+impl From<std::io::Error> for SceneError {
+    fn from(err: std::io::Error) -> Self {
+        SceneError::Io(err)
+    }
+}
+
+impl From<gltf::Error> for SceneError {
+    fn from(err: gltf::Error) -> Self {
+        match err {
+            gltf::Error::Io(err) => SceneError::Io(err),
+            err => SceneError::Parse(err.to_string()),
+        }
+    }
+}
 
 
+// The format is picked from the file extension. Primitives without normals get generated ones, flat or smooth
+pub fn import_scene<P: AsRef<Path>>(path: P, normal_generation: NormalGeneration) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
     let mut builder = SceneBuilder::new();
+    import_into(path, &mut builder, normal_generation)?;
+    let scene = builder.build();
+
+    {
+        println!("camera.position = {}", scene.camera.position());
+        println!("camera.forward  = {}", scene.camera.forward());
+        println!("camera.right    = {}", scene.camera.right());
+        println!("camera.up       = {}", scene.camera.up());
+        println!("{} emitters", scene.emitters.len());
+        println!("{} objects", scene.objects.len());
+    }
+
+    Ok(scene)
+}
+
+// Adds the content of a scene file to builder, picking the format from the file extension
+pub fn import_into(path: &Path, builder: &mut SceneBuilder, normal_generation: NormalGeneration) -> Result<(), SceneError> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "gltf" | "glb" => import_gltf(path, builder, normal_generation),
+        "obj" => import_obj(path, builder, normal_generation),
+        "ply" => import_ply(path, builder, normal_generation),
+        "pbrt" => import_pbrt(path, builder, normal_generation),
+        "xml" => import_mitsuba(path, builder, normal_generation),
+        "toml" => import_scene_file(path, builder, normal_generation),
+        _ => Err(SceneError::UnsupportedExtension(extension)),
+    }
+}
+
+// Checks the geometry of a primitive and turns it into a mesh, generating normals if there aren't any.
+// Vertex colors, if any, multiply the material's color. Texture coordinates are kept on the mesh.
+// Returns None if no triangle is left, and the reason if the geometry is invalid
+pub fn build_mesh(positions: Vec<Vec3>, normals: Option<Vec<Vec3>>, colors: Option<Vec<Color>>, uvs: Option<Vec<[f32; 2]>>, triangles: Vec<[u32; 3]>, material: Material, normal_generation: NormalGeneration) -> Result<Option<Mesh>, String> {
+    if let Some(i) = positions.iter().position(|p| !(p.x.is_finite() && p.y.is_finite() && p.z.is_finite())) {
+        return Err(format!("position {} is not finite", i));
+    }
+
+    if let Some(tri) = triangles.iter().find(|tri| tri.iter().any(|i| *i as usize >= positions.len())) {
+        return Err(format!("triangle {:?} is out of the {} vertices", tri, positions.len()));
+    }
+
+    if let Some(colors) = &colors {
+        if colors.len() != positions.len() {
+            return Err(format!("{} colors for {} vertices", colors.len(), positions.len()));
+        }
+    }
+
+    if let Some(uvs) = &uvs {
+        if uvs.len() != positions.len() {
+            return Err(format!("{} texture coordinates for {} vertices", uvs.len(), positions.len()));
+        }
+    }
+
+    let (vertices, triangles, colors, uvs) = match normals {
+        Some(normals) => {
+            if normals.len() != positions.len() {
+                return Err(format!("{} normals for {} vertices", normals.len(), positions.len()));
+            }
+            let vertices = positions.into_iter().zip(normals).map(|(p, n)| Vertex { pos: p, norm: n }).collect();
+            (vertices, triangles, colors, uvs)
+        },
+        None => {
+            let (vertices, triangles, sources) = generate_normals(&positions, &triangles, normal_generation);
+            let colors = colors.map(|colors| sources.iter().map(|i| colors[*i as usize]).collect());
+            let uvs = uvs.map(|uvs| sources.iter().map(|i| uvs[*i as usize]).collect());
+            (vertices, triangles, colors, uvs)
+        },
+    };
+
+    if triangles.is_empty() {
+        return Ok(None);
+    }
+
+    let mut mesh = Mesh::new(vertices, triangles, material);
+    if let Some(colors) = colors {
+        mesh = mesh.with_colors(colors);
+    }
+    if let Some(uvs) = uvs {
+        mesh = mesh.with_uvs(uvs);
+    }
+    Ok(Some(mesh))
+}
+
+// Unit sphere made of rings of latitude and segments of longitude, whose positions are also its normals.
+// Triangles touching the poles would be degenerate on one side, so they only have the other
+pub fn uv_sphere() -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    let mut positions = Vec::new();
+    for ring in 0..=SPHERE_RINGS {
+        let theta = PI * ring as f32 / SPHERE_RINGS as f32;
+        for segment in 0..=SPHERE_SEGMENTS {
+            let phi = 2.0 * PI * segment as f32 / SPHERE_SEGMENTS as f32;
+            positions.push(Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()));
+        }
+    }
+
+    let mut triangles = Vec::new();
+    for ring in 0..SPHERE_RINGS {
+        for segment in 0..SPHERE_SEGMENTS {
+            let i = ring * (SPHERE_SEGMENTS + 1) + segment;
+            let below = i + SPHERE_SEGMENTS + 1;
+            if ring > 0 {
+                triangles.push([i, i + 1, below]);
+            }
+            if ring < SPHERE_RINGS - 1 {
+                triangles.push([i + 1, below + 1, below]);
+            }
+        }
+    }
+
+    (positions, triangles)
+}
+
+
+fn import_gltf(path: &Path, builder: &mut SceneBuilder, normal_generation: NormalGeneration) -> Result<(), SceneError> {
+    builder.add_source_file(path);
+    let (document, buffers, _images) = gltf::import(path)?;
+
+    // Buffers and images in other files, which import has read too
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let buffer_uris = document.buffers().filter_map(|buffer| match buffer.source() {
+        gltf::buffer::Source::Uri(uri) => Some(uri),
+        gltf::buffer::Source::Bin => None,
+    });
+    let image_uris = document.images().filter_map(|image| match image.source() {
+        gltf::image::Source::Uri { uri, .. } => Some(uri),
+        gltf::image::Source::View { .. } => None,
+    });
+    for uri in buffer_uris.chain(image_uris).filter(|uri| !uri.starts_with("data:")) {
+        builder.add_source_file(&dir.join(uri));
+    }
+
 
     let mut nodes = document.scenes().flat_map(|s| s.nodes()).map(|n| (Transform::identity(), n)).collect::<Vec<_>>();
     while !nodes.is_empty() {
@@ -147,67 +519,94 @@ pub fn import_scene<P: AsRef<Path>>(path: P) -> gltf::Result<Scene> {
 
             if let Some(mesh) = node.mesh() {
                 for primitive in mesh.primitives() {
+                    let location = format!("node {} ({}), mesh {}, primitive {}", node.index(), node.name().unwrap_or("unnamed"), mesh.index(), primitive.index());
+
+                    let mode = primitive.mode();
+                    if !is_triangle_mode(mode) {
+                        eprintln!("Skipping {}: made of {:?}, only triangles are rendered", location, mode);
+                        continue;
+                    }
+
                     let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-                    match (reader.read_positions(), reader.read_normals(), reader.read_indices()) {
-                        (Some(positions), Some(normals), Some(indices)) => {
-                            let vertices = positions.zip(normals).map(|(p, n)| Vertex {
-                                pos: transform.transform_pos(Vec3::from(p)),
-                                norm: transform.transform_dir(Vec3::from(n)).normalized(),
-                            }).collect();
-
-                            let indices = indices.into_u32().collect::<Vec<_>>();
-                            let triangles = indices.as_slice().chunks(3).map(|sl| [sl[0], sl[1], sl[2]]).collect();
-                            let material = import_material(primitive.material());
-                            builder.push(Mesh::new(vertices, triangles, material));
-                        },
-
-                        _ => {
-                            eprintln!("Incomplete mesh");
-                        },
+                    let positions = match reader.read_positions() {
+                        Some(positions) => positions.map(|p| transform.transform_pos(Vec3::from(p))).collect::<Vec<_>>(),
+                        None => return Err(SceneError::InvalidGeometry { location: location, reason: "no positions".to_string() }),
+                    };
+
+                    // Non indexed primitives use their vertices in order
+                    let indices = match reader.read_indices() {
+                        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                        None => (0..positions.len() as u32).collect(),
+                    };
+                    let mut triangles = triangulate(mode, &indices);
+
+                    // Mirroring nodes turn counter clockwise triangles clockwise, which would flip their front face
+                    if transform.determinant() < 0.0 {
+                        triangles.iter_mut().for_each(|tri| tri.swap(1, 2));
+                    }
+
+                    // Normals from the file follow the inverse transpose, and generated ones are computed after the transform,
+                    // so both stay correct under non uniform scales
+                    let normals = reader.read_normals().map(|normals| normals.map(|n| transform.transform_normal(Vec3::from(n)).normalized()).collect());
+
+                    let material = import_material(primitive.material());
+                    match build_mesh(positions, normals, None, None, triangles, material, normal_generation) {
+                        Ok(Some(mesh)) => builder.push(mesh),
+                        Ok(None) => eprintln!("Skipping {}: no triangles", location),
+                        Err(reason) => return Err(SceneError::InvalidGeometry { location: location, reason: reason }),
                     }
                 }
             }
 
             if let Some(cam) = node.camera() {
-                match cam.projection() {
-                    gltf::camera::Projection::Perspective(p) => {
-                        builder.set_camera(Camera::new(transform, p.yfov(), p.aspect_ratio().unwrap_or(1.0)));
-                    },
-
-                    _ => {
-
-                    }
+                if let gltf::camera::Projection::Perspective(p) = cam.projection() {
+                    builder.set_camera(Camera::new(transform, p.yfov(), p.aspect_ratio().unwrap_or(1.0)));
                 }
             }
         }
         nodes = children;
     }
 
-    let scene = builder.build();
+    Ok(())
+}
 
-    {
-        println!("camera.position = {}", scene.camera.position());
-        println!("camera.forward  = {}", scene.camera.forward());
-        println!("camera.right    = {}", scene.camera.right());
-        println!("camera.up       = {}", scene.camera.up());
-        println!("{} emitters", scene.emitters.len());
-        println!("{} objects", scene.objects.len());
-    }
+fn is_triangle_mode(mode: gltf::mesh::Mode) -> bool {
+    use gltf::mesh::Mode;
+    matches!(mode, Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan)
+}
 
-    Ok(scene)
+// Triangles of a list, strip or fan, keeping the winding of the first one (glTF 2.0, 3.7.2.1)
+pub fn triangulate(mode: gltf::mesh::Mode, indices: &[u32]) -> Vec<[u32; 3]> {
+    use gltf::mesh::Mode;
+    let strip_count = indices.len().saturating_sub(2);
+    match mode {
+        Mode::Triangles => indices.chunks_exact(3).map(|sl| [sl[0], sl[1], sl[2]]).collect(),
+        Mode::TriangleStrip => (0..strip_count).map(|i| {
+            if i % 2 == 0 {
+                [indices[i], indices[i + 1], indices[i + 2]]
+            } else {
+                [indices[i], indices[i + 2], indices[i + 1]]
+            }
+        }).collect(),
+        Mode::TriangleFan => (0..strip_count).map(|i| [indices[i + 1], indices[i + 2], indices[0]]).collect(),
+        _ => Vec::new(),
+    }
 }
 
 fn import_material(mat: gltf::Material) -> Material {
     let to_color = |col: &[f32]| Color::new(col[0], col[1], col[2]);
 
     let pbr = mat.pbr_metallic_roughness();
-    //let transmission = mat.transmission().map(|tr| tr.transmission_factor()).unwrap_or(0.0);
+    let transmission = mat.transmission().map(|tr| tr.transmission_factor()).unwrap_or(0.0);
 
     Material {
         roughness: pbr.roughness_factor(),
         metallic: pbr.metallic_factor(),
         color: to_color(&pbr.base_color_factor()[0..3]),
         emissive: to_color(&mat.emissive_factor()[0..3]) * mat.emissive_strength().unwrap_or(1.0),
+        transmission: transmission,
+        double_sided: mat.double_sided(),
+        ..Material::default()
     }
 }
 
@@ -0,0 +1,162 @@
+use crate::scene::*;
+use crate::normals::*;
+use crate::serialize::*;
+
+use std::path::{Path, PathBuf};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::fs::{self, File};
+use std::time::UNIX_EPOCH;
+
+
+const MAGIC: &[u8; 4] = b"RTSC";
+// Must be bumped when the format changes, and when importers change the scenes they build
+const VERSION: u32 = 4;
+// Longer paths come from a corrupted file
+const MAX_PATH_LEN: usize = 1 << 16;
+
+
+// A file importers read, with its size and modification time in nanoseconds, or None if it didn't exist
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    path: PathBuf,
+    stamp: Option<(u64, u64)>,
+}
+
+// What a cached scene was imported from: every file read by the importers, and the normal generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneSource {
+    files: Vec<SourceFile>,
+    smooth_normals: bool,
+}
+
+
+impl SourceFile {
+    // Paths are made absolute, so that the cache works from any directory
+    pub fn new(path: &Path) -> SourceFile {
+        let path = fs::canonicalize(path).or_else(|_| std::path::absolute(path)).unwrap_or_else(|_| path.to_path_buf());
+        let stamp = fs::metadata(&path).and_then(|metadata| {
+            let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_err(invalid_data)?;
+            Ok((metadata.len(), modified.as_nanos() as u64))
+        }).ok();
+
+        SourceFile {
+            path: path,
+            stamp: stamp,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Whether the file is unchanged, or still missing
+    fn is_current(&self) -> bool {
+        SourceFile::new(&self.path) == *self
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let path = self.path.to_str().ok_or_else(|| invalid_data(format!("{} isn't valid UTF-8", self.path.display())))?;
+        write_u64(writer, path.len() as u64)?;
+        writer.write_all(path.as_bytes())?;
+
+        let (len, modified_nanos) = self.stamp.unwrap_or((0, 0));
+        write_u32(writer, self.stamp.is_some() as u32)?;
+        write_u64(writer, len)?;
+        write_u64(writer, modified_nanos)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<SourceFile> {
+        let len = read_u64(reader)? as usize;
+        if len > MAX_PATH_LEN {
+            return Err(invalid_data("path too long"));
+        }
+        let path = String::from_utf8(read_bytes(reader, len)?).map_err(invalid_data)?;
+
+        let exists = read_u32(reader)? != 0;
+        let stamp = (read_u64(reader)?, read_u64(reader)?);
+        Ok(SourceFile {
+            path: PathBuf::from(path),
+            stamp: if exists { Some(stamp) } else { None },
+        })
+    }
+}
+
+impl SceneSource {
+    pub fn new(files: &[SourceFile], normal_generation: NormalGeneration) -> SceneSource {
+        SceneSource {
+            files: files.to_vec(),
+            smooth_normals: matches!(normal_generation, NormalGeneration::Smooth),
+        }
+    }
+
+    // Outdated once any of the files changes, appears or disappears
+    fn is_current(&self, normal_generation: NormalGeneration) -> bool {
+        self.smooth_normals == matches!(normal_generation, NormalGeneration::Smooth) && self.files.iter().all(|file| file.is_current())
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u64(writer, self.files.len() as u64)?;
+        for file in &self.files {
+            file.write_to(writer)?;
+        }
+        write_u32(writer, self.smooth_normals as u32)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<SceneSource> {
+        Ok(SceneSource {
+            files: (0..read_u64(reader)?).map(|_| SourceFile::read_from(reader)).collect::<io::Result<_>>()?,
+            smooth_normals: read_u32(reader)? != 0,
+        })
+    }
+}
+
+
+// The cache of a scene is next to its file, scene.gltf is cached in scene.gltf.cache
+pub fn scene_cache_path(scene_file: &Path) -> PathBuf {
+    let mut path = scene_file.as_os_str().to_owned();
+    path.push(".cache");
+    PathBuf::from(path)
+}
+
+pub fn save_scene_cache<P: AsRef<Path>>(path: P, scene: &Scene, source: &SceneSource) -> io::Result<()> {
+    let path = path.as_ref();
+
+    // Written next to the previous cache and swapped, so that a run stopped mid-write doesn't leave a truncated one
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(MAGIC)?;
+        write_u32(&mut writer, VERSION)?;
+        source.write_to(&mut writer)?;
+        scene.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+
+    fs::rename(tmp_path, path)
+}
+
+// None if there is no cache, or if it is outdated: made by another version, with other normals, or before a file of the scene changed
+pub fn load_scene_cache<P: AsRef<Path>>(path: P, normal_generation: NormalGeneration) -> io::Result<Option<Scene>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a scene cache"));
+    }
+
+    if read_u32(&mut reader)? != VERSION {
+        return Ok(None);
+    }
+    let source = SceneSource::read_from(&mut reader)?;
+    if !source.is_current(normal_generation) {
+        return Ok(None);
+    }
+
+    Ok(Some(Scene::read_from(&mut reader)?.with_source_files(source.files)))
+}
@@ -0,0 +1,107 @@
+// Saves imported scenes to a scene cache and loads them back, and checks when a cache is outdated or corrupted.
+
+use crate::scene::*;
+use crate::normals::*;
+use crate::surface::*;
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::scene_cache::*;
+use crate::environment::*;
+use crate::image::*;
+use crate::color::*;
+use crate::transform::*;
+use crate::ply_tests::ply;
+use crate::pbrt_tests::{PBRT, MESH_PBRT};
+use crate::test_files::*;
+
+use std::io;
+
+
+#[test]
+fn scene_cache() {
+    let pbrt = write_temp("pbrt", PBRT);
+    write_next_to(&pbrt, "mesh.pbrt", MESH_PBRT);
+    let quad = write_next_to(&pbrt, "quad.ply", ply("ascii"));
+
+    for path in [&pbrt, &quad] {
+        let cache_file = scene_cache_path(path);
+        let scene = import_scene(path, NormalGeneration::Flat).unwrap();
+        save_scene_cache(&cache_file, &scene, &SceneSource::new(scene.source_files(), NormalGeneration::Flat)).unwrap();
+        let cached = load_scene_cache(&cache_file, NormalGeneration::Flat).unwrap().unwrap();
+
+        assert_eq!(cached.settings(), scene.settings());
+        assert_eq!(cached.source_files(), scene.source_files());
+        assert_eq!(cached.camera().position(), scene.camera().position());
+        assert_eq!(cached.camera().forward(), scene.camera().forward());
+        assert_eq!(cached.camera().ratio(), scene.camera().ratio());
+        assert_eq!(cached.environment().radiance(Vec3::new(0.0, 1.0, 0.0)), scene.environment().radiance(Vec3::new(0.0, 1.0, 0.0)));
+        assert_eq!(cached.objects().len(), scene.objects().len());
+        for (a, b) in cached.objects().iter().zip(scene.objects()) {
+            assert_eq!(a.material(), b.material());
+            assert_eq!(a.area(), b.area());
+        }
+
+        // The cached BVHs give the same hits, with the same vertex colors
+        for i in 0..64 {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 3.0), Vec3::new((i % 8) as f32 * 0.1 - 0.35, (i / 8) as f32 * 0.1 - 0.35, -1.0));
+            let hits = [(&cached).hit(ray), (&scene).hit(ray)].map(|hit| hit.map(|hit| (hit.dist, hit.front_face, hit.vertex_color, hit.material().unwrap())));
+            assert_eq!(hits[0], hits[1]);
+        }
+    }
+
+    // The pbrt scene depends on the file it includes and on the PLY mesh that one refers to
+    let save = || {
+        let scene = import_scene(&pbrt, NormalGeneration::Flat).unwrap();
+        let names = scene.source_files().iter().map(|file| file.path().file_name().unwrap().to_str().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(names, ["scene.pbrt", "mesh.pbrt", "quad.ply"]);
+        save_scene_cache(scene_cache_path(&pbrt), &scene, &SceneSource::new(scene.source_files(), NormalGeneration::Flat)).unwrap();
+    };
+
+    // The cache is outdated for other normals, or once any of the files changes
+    let cache_file = scene_cache_path(&pbrt);
+    save();
+    assert!(load_scene_cache(&cache_file, NormalGeneration::Flat).unwrap().is_some());
+    assert!(load_scene_cache(&cache_file, NormalGeneration::Smooth).unwrap().is_none());
+    std::fs::write(&quad, [ply("ascii"), b"0 0 0 0 0 0\n".to_vec()].concat()).unwrap();
+    assert!(load_scene_cache(&cache_file, NormalGeneration::Flat).unwrap().is_none());
+    save();
+    std::fs::write(&pbrt, format!("{}# edited\n", PBRT)).unwrap();
+    assert!(load_scene_cache(&cache_file, NormalGeneration::Flat).unwrap().is_none());
+    assert!(load_scene_cache(pbrt.with_file_name("missing.cache"), NormalGeneration::Flat).unwrap().is_none());
+
+    // Truncated caches are errors
+    save();
+    let data = std::fs::read(&cache_file).unwrap();
+    std::fs::write(&cache_file, &data[..data.len() / 2]).unwrap();
+    assert!(load_scene_cache(&cache_file, NormalGeneration::Flat).is_err());
+}
+
+#[test]
+fn environment_image() {
+    let image_environment = |width, height| Environment::Image {
+        image: Image::new(width, height, (0..width * height).map(|i| Color::from(i as f32)).collect()),
+        strength: 2.0,
+        mapping: EnvironmentMapping::Equirectangular,
+        to_map: Transform::identity(),
+    };
+    let to_bytes = |environment: &Environment| {
+        let mut bytes = Vec::new();
+        environment.write_to(&mut bytes).unwrap();
+        bytes
+    };
+
+    let environment = image_environment(4, 2);
+    let read = Environment::read_from(&mut to_bytes(&environment).as_slice()).unwrap();
+    for dir in [Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, -1.0, -1.0)] {
+        assert_eq!(read.radiance(dir), environment.radiance(dir));
+    }
+
+    // Images without pixels have nothing to return for any direction
+    for (width, height) in [(0, 2), (4, 0), (0, 0)] {
+        match Environment::read_from(&mut to_bytes(&image_environment(width, height)).as_slice()) {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("{}x{} environment should be rejected", width, height),
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use crate::vec::*;
+use crate::color::*;
+use crate::material::*;
+use crate::scene::*;
+use crate::normals::*;
+use crate::transform::*;
+use crate::camera::*;
+use crate::environment::*;
+use crate::integrator::*;
+use crate::image::*;
+use crate::mesh::*;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use toml::{Table, Value};
+
+
+// A table, with its path in the file for error messages
+struct Section<'a> {
+    name: String,
+    table: &'a Table,
+}
+
+// Material fields replacing those of the materials of a mesh
+#[derive(Debug, Clone, Copy, Default)]
+struct MaterialOverride {
+    color: Option<Color>,
+    roughness: Option<f32>,
+    metallic: Option<f32>,
+    emissive: Option<Color>,
+    transmission: Option<f32>,
+    ior: Option<f32>,
+    double_sided: Option<bool>,
+}
+
+
+
+// Scene files are written in TOML:
+//
+//   [render]
+//   spp = 64
+//   bounces = 6
+//   resolution = [1280, 720]
+//   integrator = "path"            # or "direct"
+//
+//   [camera]
+//   position = [0, 1, 3.5]
+//   look_at = [0, 1, 0]
+//   up = [0, 1, 0]
+//   fov = 40                       # vertical, in degrees
+//
+//   [environment]
+//   color = [0.1, 0.1, 0.15]       # or image = "sky.exr", an equirectangular map
+//   strength = 1
+//
+//   [materials.red]
+//   color = [0.8, 0.1, 0.1]
+//   roughness = 0.3
+//
+//   [[mesh]]
+//   path = "cornel.gltf"           # glTF, OBJ or PLY, relative to the scene file
+//   scale = 2                      # or [x, y, z]
+//   rotate = [0, 45, 0]            # degrees around X, then Y, then Z
+//   translate = [0, 0, -1]
+//   material = "red"               # or an inline table, only the fields it has are replaced
+//   normals = "smooth"             # for meshes without normals
+//
+//   [[light]]
+//   shape = "quad"                 # width horizontal (along X when facing up or down), or "sphere" with a radius
+//   position = [0, 1.98, 0]
+//   normal = [0, -1, 0]
+//   size = [0.5, 0.4]
+//   emission = [17, 12, 4]
+//   strength = 1
+//
+// Without a camera, the one of the first mesh file having one is used. A camera needs a resolution, which sets its aspect ratio
+pub fn import_scene_file(path: &Path, builder: &mut SceneBuilder, normal_generation: NormalGeneration) -> Result<(), SceneError> {
+    builder.add_source_file(path);
+    let text = std::fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let error = |msg: String| SceneError::Parse(format!("{}: {}", path.display(), msg));
+
+    let root = text.parse::<Table>().map_err(|err| {
+        let line = err.span().map(|span| text[..span.start].matches('\n').count() + 1).unwrap_or(0);
+        SceneError::Parse(format!("{}:{}: {}", path.display(), line, err.message().trim_end()))
+    })?;
+    let root = Section { name: String::new(), table: &root };
+    root.check_keys(&["render", "camera", "environment", "materials", "mesh", "light"]).map_err(error)?;
+
+    let settings = match root.table("render").map_err(error)? {
+        Some(render) => parse_render_settings(&render).map_err(error)?,
+        None => RenderSettings::default(),
+    };
+
+    let mut materials = HashMap::new();
+    if let Some(section) = root.table("materials").map_err(error)? {
+        for (name, material) in section.subsections().map_err(error)? {
+            materials.insert(name, parse_material(&material).map_err(error)?);
+        }
+    }
+
+    let mut file_camera = None;
+    for mesh in root.tables("mesh").map_err(error)? {
+        mesh.check_keys(&["path", "scale", "rotate", "translate", "material", "normals"]).map_err(error)?;
+
+        let file = match mesh.string("path").map_err(error)? {
+            Some(file) => dir.join(file),
+            None => return Err(error(format!("{} has no path", mesh.name))),
+        };
+        if file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml")) {
+            return Err(error(format!("{}: scene files can't include other scene files", mesh.name)));
+        }
+
+        let transform = parse_transform(&mesh).map_err(error)?;
+        let material = match mesh.get("material") {
+            Some(Value::String(name)) => *materials.get(name.as_str()).ok_or_else(|| error(format!("{}.material: unknown material \"{}\"", mesh.name, name)))?,
+            Some(_) => parse_material(&mesh.table("material").map_err(error)?.unwrap()).map_err(error)?,
+            None => MaterialOverride::default(),
+        };
+        let mesh_normals = match mesh.string("normals").map_err(error)? {
+            Some("flat") => NormalGeneration::Flat,
+            Some("smooth") => NormalGeneration::Smooth,
+            Some(other) => return Err(error(format!("{}.normals: expected \"flat\" or \"smooth\", got \"{}\"", mesh.name, other))),
+            None => normal_generation,
+        };
+
+        let mut included = SceneBuilder::new();
+        import_into(&file, &mut included, mesh_normals).map_err(|err| in_file(&file, err))?;
+        builder.add_source_files(included.take_source_files());
+
+        for obj in included.take_objects() {
+            let obj_material = material.apply(*obj.material());
+            let obj = match &transform {
+                Some(transform) => obj.transformed(transform),
+                None => obj,
+            };
+            builder.push(obj.with_material(obj_material));
+        }
+
+        if file_camera.is_none() {
+            file_camera = included.camera().map(|camera| match &transform {
+                Some(transform) => camera.transformed(transform),
+                None => camera,
+            });
+        }
+    }
+
+    for light in root.tables("light").map_err(error)? {
+        builder.push(parse_light(&light).map_err(error)?);
+    }
+
+    let camera = match root.table("camera").map_err(error)? {
+        Some(_) if settings.resolution.is_none() => return Err(error("camera: needs a resolution in render, to set its aspect ratio".to_string())),
+        Some(camera) => Some(parse_camera(&camera).map_err(error)?),
+        None => file_camera,
+    };
+    // The image size comes from the resolution instead of the camera's aspect ratio, so they must match
+    match (camera, settings.resolution) {
+        (camera, Some((width, height))) => builder.set_camera(camera.unwrap_or_default().with_ratio(width as f32 / height as f32)),
+        (Some(camera), None) => builder.set_camera(camera),
+        (None, None) => {},
+    }
+
+    if let Some(environment) = root.table("environment").map_err(error)? {
+        if let Some(file) = environment.string("image").map_err(error)? {
+            builder.add_source_file(&dir.join(file));
+        }
+        builder.set_environment(parse_environment(&environment, dir).map_err(error)?);
+    }
+
+    builder.set_render_settings(settings);
+
+    Ok(())
+}
+
+// SceneError::Io doesn't say which file couldn't be read
+fn in_file(file: &Path, err: SceneError) -> SceneError {
+    match err {
+        SceneError::Io(err) => SceneError::Io(std::io::Error::new(err.kind(), format!("{}: {}", file.display(), err))),
+        err => err,
+    }
+}
+
+
+fn parse_render_settings(render: &Section) -> Result<RenderSettings, String> {
+    render.check_keys(&["spp", "bounces", "resolution", "integrator"])?;
+
+    let spp = render.integer("spp")?;
+    if spp == Some(0) {
+        return Err(format!("{}: must be at least 1", render.key_name("spp")));
+    }
+
+    let resolution = match render.numbers("resolution", &[2])? {
+        Some(size) if size.iter().all(|s| *s >= 1.0 && s.fract() == 0.0) => Some((size[0] as u32, size[1] as u32)),
+        Some(_) => return Err(format!("{}: expected a width and a height of at least 1 pixel", render.key_name("resolution"))),
+        None => None,
+    };
+
+    let integrator = match render.string("integrator")? {
+        Some(name) => Some(IntegratorKind::parse(name).ok_or_else(|| format!("{}: expected \"path\" or \"direct\", got \"{}\"", render.key_name("integrator"), name))?),
+        None => None,
+    };
+
+    Ok(RenderSettings {
+        spp: spp,
+        max_bounces: render.integer("bounces")?,
+        resolution: resolution,
+        integrator: integrator,
+    })
+}
+
+fn parse_camera(camera: &Section) -> Result<Camera, String> {
+    camera.check_keys(&["position", "look_at", "up", "fov"])?;
+
+    let position = camera.vec3("position")?.unwrap_or(Vec3::zero());
+    let look_at = camera.vec3("look_at")?.unwrap_or(position - Vec3::new(0.0, 0.0, 1.0));
+    let up = camera.vec3("up")?.unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+    let fov = camera.number("fov")?.unwrap_or(60.0);
+
+    if fov <= 0.0 || fov >= 180.0 {
+        return Err(format!("{}: must be between 0 and 180 degrees", camera.key_name("fov")));
+    }
+
+    match Transform::look_at(position, look_at, up) {
+        Some(transform) => Ok(Camera::new(transform, fov.to_radians(), 1.0)),
+        None => Err(format!("{}: look_at must differ from the position and not be along up", camera.name)),
+    }
+}
+
+fn parse_environment(environment: &Section, dir: &Path) -> Result<Environment, String> {
+    environment.check_keys(&["color", "image", "strength"])?;
+    let strength = environment.number("strength")?.unwrap_or(1.0);
+
+    match (environment.string("image")?, environment.color("color")?) {
+        (Some(_), Some(_)) => Err(format!("{}: has both a color and an image", environment.name)),
+        (Some(file), None) => {
+            let file = dir.join(file);
+            let image = Image::load(&file).map_err(|err| format!("{}.image: unable to load {}: {}", environment.name, file.display(), err))?;
+            if image.pixel_count() == 0 {
+                return Err(format!("{}.image: {} is empty", environment.name, file.display()));
+            }
+            Ok(Environment::Image {
+                image: image,
+                strength: strength,
+                mapping: EnvironmentMapping::Equirectangular,
+                to_map: Transform::identity(),
+            })
+        },
+        (None, color) => Ok(Environment::Color(color.unwrap_or(Color::from(0.0)) * strength)),
+    }
+}
+
+fn parse_material(material: &Section) -> Result<MaterialOverride, String> {
+    material.check_keys(&["color", "roughness", "metallic", "emissive", "transmission", "ior", "double_sided"])?;
+
+    Ok(MaterialOverride {
+        color: material.color("color")?,
+        roughness: material.number("roughness")?,
+        metallic: material.number("metallic")?,
+        emissive: material.color("emissive")?,
+        transmission: material.number("transmission")?,
+        ior: material.number("ior")?,
+        double_sided: material.bool("double_sided")?,
+    })
+}
+
+// Integers and floats are both numbers
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(n) => Some(*n as f64),
+        Value::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+// Scaled, then rotated around X, Y and Z in that order, then translated. None if the mesh isn't moved
+fn parse_transform(mesh: &Section) -> Result<Option<Transform>, String> {
+    let scale = mesh.numbers("scale", &[1, 3])?;
+    let rotate = mesh.vec3("rotate")?;
+    let translate = mesh.vec3("translate")?;
+    if scale.is_none() && rotate.is_none() && translate.is_none() {
+        return Ok(None);
+    }
+
+    let scale = match scale {
+        Some(s) if s.len() == 3 => Vec3::new(s[0], s[1], s[2]),
+        Some(s) => Vec3::from(s[0]),
+        None => Vec3::from(1.0),
+    };
+    let rotate = rotate.unwrap_or(Vec3::zero());
+    let rotation = Transform::rotation(Vec3::new(0.0, 0.0, 1.0), rotate.z)
+        .then(Transform::rotation(Vec3::new(0.0, 1.0, 0.0), rotate.y))
+        .then(Transform::rotation(Vec3::new(1.0, 0.0, 0.0), rotate.x));
+
+    Ok(Some(Transform::translation(translate.unwrap_or(Vec3::zero())).then(rotation).then(Transform::scaling(scale))))
+}
+
+// Lights are one sided emissive meshes, lighting the side their normal points to. They stay opaque from behind
+fn parse_light(light: &Section) -> Result<Mesh, String> {
+    light.check_keys(&["shape", "position", "normal", "size", "radius", "emission", "strength"])?;
+
+    let position = light.vec3("position")?.unwrap_or(Vec3::zero());
+    let emission = light.color("emission")?.unwrap_or(Color::from(1.0)) * light.number("strength")?.unwrap_or(1.0);
+    let material = Material {
+        color: Color::from(0.0),
+        emissive: emission,
+        double_sided: true,
+        one_sided_emission: true,
+        ..Material::default()
+    };
+
+    let (positions, normals, triangles) = match light.string("shape")?.unwrap_or("quad") {
+        "quad" => {
+            let normal = light.vec3("normal")?.unwrap_or(Vec3::new(0.0, -1.0, 0.0)).normalized();
+            let size = light.numbers("size", &[1, 2])?.unwrap_or(vec![1.0]);
+
+            let tangent = if normal.y.abs() > 0.999 {
+                Vec3::new(1.0, 0.0, 0.0)
+            } else {
+                Vec3::new(0.0, 1.0, 0.0).cross(normal).normalized()
+            };
+            let u = tangent * (size[0] * 0.5);
+            let v = normal.cross(tangent) * (size[size.len() - 1] * 0.5);
+
+            let positions = vec![position - u - v, position + u - v, position + u + v, position - u + v];
+            (positions, vec![normal; 4], vec![[0, 1, 2], [0, 2, 3]])
+        },
+
+        "sphere" => {
+            let radius = light.number("radius")?.unwrap_or(0.5);
+
+            let (normals, triangles) = uv_sphere();
+            let positions = normals.iter().map(|n| position + *n * radius).collect();
+            (positions, normals, triangles)
+        },
+
+        other => return Err(format!("{}: expected \"quad\" or \"sphere\", got \"{}\"", light.key_name("shape"), other)),
+    };
+
+    match build_mesh(positions, Some(normals), None, None, triangles, material, NormalGeneration::Flat) {
+        Ok(Some(mesh)) => Ok(mesh),
+        Ok(None) => Err(format!("{} has no area", light.name)),
+        Err(reason) => Err(format!("{}: {}", light.name, reason)),
+    }
+}
+
+
+impl MaterialOverride {
+    fn apply(&self, material: Material) -> Material {
+        Material {
+            color: self.color.unwrap_or(material.color),
+            roughness: self.roughness.unwrap_or(material.roughness),
+            metallic: self.metallic.unwrap_or(material.metallic),
+            emissive: self.emissive.unwrap_or(material.emissive),
+            transmission: self.transmission.unwrap_or(material.transmission),
+            ior: self.ior.unwrap_or(material.ior),
+            double_sided: self.double_sided.unwrap_or(material.double_sided),
+            one_sided_emission: material.one_sided_emission,
+        }
+    }
+}
+
+
+impl<'a> Section<'a> {
+    fn key_name(&self, key: &str) -> String {
+        if self.name.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", self.name, key)
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&'a Value> {
+        self.table.get(key)
+    }
+
+    // Unknown keys are most likely typos, which would otherwise be silently ignored
+    fn check_keys(&self, known: &[&str]) -> Result<(), String> {
+        match self.table.keys().find(|key| !known.contains(&key.as_str())) {
+            Some(key) => Err(format!("unknown key \"{}\"", self.key_name(key))),
+            None => Ok(()),
+        }
+    }
+
+    fn number(&self, key: &str) -> Result<Option<f32>, String> {
+        match self.get(key) {
+            Some(value) => as_number(value).map(|n| Some(n as f32)).ok_or_else(|| format!("{}: expected a number", self.key_name(key))),
+            None => Ok(None),
+        }
+    }
+
+    fn integer(&self, key: &str) -> Result<Option<usize>, String> {
+        match self.get(key) {
+            Some(Value::Integer(n)) if *n >= 0 => Ok(Some(*n as usize)),
+            Some(_) => Err(format!("{}: expected a positive integer", self.key_name(key))),
+            None => Ok(None),
+        }
+    }
+
+    fn bool(&self, key: &str) -> Result<Option<bool>, String> {
+        match self.get(key) {
+            Some(Value::Boolean(b)) => Ok(Some(*b)),
+            Some(_) => Err(format!("{}: expected true or false", self.key_name(key))),
+            None => Ok(None),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<Option<&'a str>, String> {
+        match self.get(key) {
+            Some(Value::String(s)) => Ok(Some(s.as_str())),
+            Some(_) => Err(format!("{}: expected a string", self.key_name(key))),
+            None => Ok(None),
+        }
+    }
+
+    // A number or an array of numbers, whose length is one of counts
+    fn numbers(&self, key: &str, counts: &[usize]) -> Result<Option<Vec<f32>>, String> {
+        let values = match self.get(key) {
+            Some(Value::Array(values)) => values.iter().map(|v| as_number(v).map(|n| n as f32)).collect::<Option<Vec<_>>>().unwrap_or_default(),
+            Some(value) => as_number(value).map(|n| vec![n as f32]).unwrap_or_default(),
+            None => return Ok(None),
+        };
+
+        if counts.contains(&values.len()) {
+            Ok(Some(values))
+        } else {
+            let counts = counts.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            Err(format!("{}: expected {} numbers", self.key_name(key), counts.join(" or ")))
+        }
+    }
+
+    fn vec3(&self, key: &str) -> Result<Option<Vec3>, String> {
+        Ok(self.numbers(key, &[3])?.map(|v| Vec3::new(v[0], v[1], v[2])))
+    }
+
+    // A single number is a grey
+    fn color(&self, key: &str) -> Result<Option<Color>, String> {
+        Ok(self.numbers(key, &[1, 3])?.map(|v| if v.len() == 3 { Color::new(v[0], v[1], v[2]) } else { Color::from(v[0]) }))
+    }
+
+    fn table(&self, key: &str) -> Result<Option<Section<'a>>, String> {
+        match self.get(key) {
+            Some(Value::Table(table)) => Ok(Some(Section { name: self.key_name(key), table: table })),
+            Some(_) => Err(format!("{}: expected a table", self.key_name(key))),
+            None => Ok(None),
+        }
+    }
+
+    // An array of tables, or a single table
+    fn tables(&self, key: &str) -> Result<Vec<Section<'a>>, String> {
+        match self.get(key) {
+            Some(Value::Array(values)) => values.iter().enumerate().map(|(i, value)| match value {
+                Value::Table(table) => Ok(Section { name: format!("{}[{}]", self.key_name(key), i), table: table }),
+                _ => Err(format!("{}[{}]: expected a table", self.key_name(key), i)),
+            }).collect(),
+            Some(_) => Ok(self.table(key)?.into_iter().collect()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn subsections(&self) -> Result<Vec<(&'a str, Section<'a>)>, String> {
+        self.table.keys().map(|key| Ok((key.as_str(), self.table(key)?.unwrap()))).collect()
+    }
+}
@@ -0,0 +1,133 @@
+// Imports TOML scene files: render settings, camera, environment, material overrides, transformed meshes and lights, and their errors.
+
+use crate::scene::*;
+use crate::normals::*;
+use crate::surface::*;
+use crate::color::*;
+use crate::vec::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::integrator::*;
+use crate::scene_tests::{gltf_with_data_uris, CAMERA_Z};
+use crate::test_files::*;
+
+
+const SCENE_FILE: &str = r#"
+# Two copies of the triangle, and two lights
+[render]
+spp = 8
+bounces = 2
+resolution = [320, 240]
+integrator = "direct"
+
+[camera]
+position = [0, 0, 5]
+look_at = [0, 0, 0]
+fov = 45
+
+[environment]
+color = 0.5
+strength = 2
+
+[materials]
+red = { color = [0.8, 0.1, 0.1], roughness = 0.25 }
+
+[[mesh]]
+path = "triangle.gltf"
+translate = [0, 0, -1]
+scale = 2
+material = "red"
+
+[[mesh]]
+path = 'triangle.gltf'
+rotate = [0, 180, 0]
+material.metallic = 0
+
+[[light]]
+shape = "sphere"
+position = [0, 2, 0]
+radius = 0.25
+emission = [4, 4, 4]
+
+[[light]]
+position = [0, -2, 0]
+normal = [0, 1, 0]
+size = [
+    1,
+    0.5,  # depth
+]
+emission = 10
+"#;
+
+#[test]
+fn scene_file() {
+    let path = write_temp("toml", SCENE_FILE);
+    write_next_to(&path, "triangle.gltf", gltf_with_data_uris([0, 1, 2]));
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+
+    assert_eq!(scene.settings(), RenderSettings {
+        spp: Some(8),
+        max_bounces: Some(2),
+        resolution: Some((320, 240)),
+        integrator: Some(IntegratorKind::Direct),
+    });
+    assert_eq!(scene.camera().position(), Vec3::new(0.0, 0.0, 5.0));
+    assert_eq!(scene.camera().ratio(), 320.0 / 240.0);
+    assert_eq!(scene.environment().radiance(Vec3::new(0.0, 1.0, 0.0)), Color::from(1.0));
+
+    let objects = scene.objects();
+    assert_eq!(objects.len(), 4);
+
+    // Only the fields set by the scene file replace those of the glTF material
+    assert_eq!(objects[0].area(), 8.0);
+    assert_eq!(objects[0].material().color, Color::new(0.8, 0.1, 0.1));
+    assert_eq!(objects[0].material().roughness, 0.25);
+    assert_eq!(objects[1].material().metallic, 0.0);
+    assert_eq!(objects[1].material().roughness, 1.0);
+
+    // The turned around copy faces -Z
+    let hit = (&objects[1]).hit(Ray::new(Vec3::new(0.0, 0.0, -3.0), Vec3::new(0.0, 0.0, 1.0))).unwrap();
+    assert!(hit.front_face);
+    assert!((hit.shading_norm.z + 1.0).abs() < 1e-6);
+
+    let sphere_area = 4.0 * std::f32::consts::PI * 0.25 * 0.25;
+    assert!(objects[2].area() < sphere_area && objects[2].area() > sphere_area * 0.98);
+    assert_eq!(objects[2].material().emissive, Color::from(4.0));
+    assert!((objects[3].area() - 0.5).abs() < 1e-6);
+    assert_eq!(objects[3].material().emissive, Color::from(10.0));
+
+    // The quad light faces up, from below it blocks rays without emitting
+    let hit = (&objects[3]).hit(Ray::new(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0))).unwrap();
+    assert!(!hit.front_face);
+    assert!(hit.material().unwrap().emission(hit.front_face).is_zero());
+}
+
+#[test]
+fn scene_file_camera_from_mesh() {
+    let path = write_temp("toml", "[[mesh]]\npath = '''triangle.gltf'''\ntranslate = [0, 0, 1]\n");
+    write_next_to(&path, "triangle.gltf", gltf_with_data_uris([0, 1, 2]));
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    assert_eq!(scene.camera().position().z, CAMERA_Z + 1.0);
+    assert_eq!(scene.settings(), RenderSettings::default());
+}
+
+#[test]
+fn scene_file_errors() {
+    let error = |name: &str, scene: &str| {
+        let path = write_temp("toml", scene);
+        write_next_to(&path, "triangle.gltf", gltf_with_data_uris([0, 1, 2]));
+        match import_scene(&path, NormalGeneration::Flat) {
+            Err(err) => err.to_string(),
+            Ok(_) => panic!("{} should be rejected", name),
+        }
+    };
+
+    assert!(error("syntax", "[render]\nspp = 8\nbounces 4\n").ends_with(":3: expected `.`, `=`"));
+    assert!(error("typo", "[render]\nsamples = 8\n").ends_with("unknown key \"render.samples\""));
+    assert!(error("bad_type", "[[mesh]]\npath = \"triangle.gltf\"\nscale = [1, 2]\n").ends_with("mesh[0].scale: expected 1 or 3 numbers"));
+    assert!(error("unknown_material", "[[mesh]]\npath = \"triangle.gltf\"\nmaterial = \"gold\"\n").ends_with("unknown material \"gold\""));
+    assert!(error("nested", "[[mesh]]\npath = \"nested.toml\"\n").contains("can't include other scene files"));
+    assert!(error("missing_mesh", "[[mesh]]\npath = \"missing.obj\"\n").contains("missing.obj"));
+    assert!(error("duplicate", "[camera]\nfov = 40\nfov = 50\n").ends_with(":3: duplicate key `fov` in table `camera`"));
+    assert!(error("no_resolution", "[camera]\nfov = 40\n").ends_with("camera: needs a resolution in render, to set its aspect ratio"));
+}
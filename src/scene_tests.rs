@@ -0,0 +1,357 @@
+// Imports small generated glTF files, with embedded data URIs or as binary .glb, and files that must fail with the right SceneError. Also builds scenes from meshes directly.
+
+use crate::scene::*;
+use crate::normals::*;
+use crate::surface::*;
+use crate::color::*;
+use crate::material::*;
+use crate::vec::*;
+use crate::vertex::*;
+use crate::mesh::*;
+use crate::ray::*;
+use crate::hit::*;
+use crate::test_files::*;
+
+
+// One triangle facing +Z, and a camera looking at it
+const POSITIONS: [[f32; 3]; 3] = [[-1.0, -1.0, 0.0], [1.0, -1.0, 0.0], [0.0, 1.0, 0.0]];
+pub const CAMERA_Z: f32 = 3.0;
+
+
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(CHARS[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn png_1x1() -> Vec<u8> {
+    let mut data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut data, 1, 1);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[255, 128, 0]).unwrap();
+    }
+    data
+}
+
+// Positions followed by the indices, padded to 4 bytes
+fn geometry(indices: [u16; 3]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for p in POSITIONS.iter().flatten() {
+        data.extend_from_slice(&p.to_le_bytes());
+    }
+    for i in indices {
+        data.extend_from_slice(&i.to_le_bytes());
+    }
+    data.resize(data.len().next_multiple_of(4), 0);
+    data
+}
+
+// Without buffer_uri, the buffer is the BIN chunk of a .glb. The image is either a URI or a view of the buffer
+fn gltf_json(buffer_uri: Option<&str>, buffer_len: usize, image: &str, image_view: Option<(usize, usize)>) -> String {
+    let positions_len = POSITIONS.len() * 12;
+    let buffer = match buffer_uri {
+        Some(uri) => format!(r#"{{ "byteLength": {}, "uri": "{}" }}"#, buffer_len, uri),
+        None => format!(r#"{{ "byteLength": {} }}"#, buffer_len),
+    };
+    let image_view = match image_view {
+        Some((offset, len)) => format!(r#", {{ "buffer": 0, "byteOffset": {}, "byteLength": {} }}"#, offset, len),
+        None => String::new(),
+    };
+
+    format!(r#"{{
+        "asset": {{ "version": "2.0" }},
+        "scene": 0,
+        "scenes": [{{ "nodes": [0, 1] }}],
+        "nodes": [
+            {{ "name": "triangle", "mesh": 0 }},
+            {{ "camera": 0, "translation": [0, 0, {camera_z}] }}
+        ],
+        "cameras": [{{ "type": "perspective", "perspective": {{ "yfov": 0.8, "aspectRatio": 1.0, "znear": 0.1 }} }}],
+        "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}] }}],
+        "accessors": [
+            {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [-1, -1, 0], "max": [1, 1, 0] }},
+            {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+        ],
+        "bufferViews": [
+            {{ "buffer": 0, "byteOffset": 0, "byteLength": {positions_len} }},
+            {{ "buffer": 0, "byteOffset": {positions_len}, "byteLength": 6 }}{image_view}
+        ],
+        "buffers": [{buffer}],
+        "images": [{image}]
+    }}"#, camera_z = CAMERA_Z, positions_len = positions_len, image_view = image_view, buffer = buffer, image = image)
+}
+
+pub fn gltf_with_data_uris(indices: [u16; 3]) -> String {
+    let data = geometry(indices);
+    let buffer_uri = format!("data:application/octet-stream;base64,{}", base64(&data));
+    let image = format!(r#"{{ "uri": "data:image/png;base64,{}" }}"#, base64(&png_1x1()));
+    gltf_json(Some(&buffer_uri), data.len(), &image, None)
+}
+
+// Header, then a JSON and a BIN chunk, each padded to 4 bytes (glTF 2.0, 4.4)
+fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+    let chunk = |kind: &[u8; 4], data: &[u8], pad: u8| {
+        let mut data = data.to_vec();
+        data.resize(data.len().next_multiple_of(4), pad);
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend(data);
+        chunk
+    };
+
+    let json = chunk(b"JSON", json.as_bytes(), b' ');
+    let bin = chunk(b"BIN\0", bin, 0);
+
+    let mut data = b"glTF".to_vec();
+    data.extend_from_slice(&2u32.to_le_bytes());
+    data.extend_from_slice(&((12 + json.len() + bin.len()) as u32).to_le_bytes());
+    data.extend(json);
+    data.extend(bin);
+    data
+}
+
+fn check_triangle_scene(scene: &Scene) {
+    assert_eq!(scene.objects().len(), 1);
+    assert_eq!(scene.objects()[0].area(), 2.0);
+    assert_eq!(scene.camera().position().z, CAMERA_Z);
+}
+
+
+#[test]
+fn gltf_with_embedded_data() {
+    let path = write_temp("gltf", gltf_with_data_uris([0, 1, 2]));
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    check_triangle_scene(&scene);
+}
+
+#[test]
+fn binary_gltf() {
+    // The image is stored in the BIN chunk too, after the geometry
+    let mut bin = geometry([0, 1, 2]);
+    let image_offset = bin.len();
+    let image = png_1x1();
+    bin.extend_from_slice(&image);
+
+    let json = gltf_json(None, bin.len(), r#"{ "bufferView": 2, "mimeType": "image/png" }"#, Some((image_offset, image.len())));
+
+    let path = write_temp("glb", glb(&json, &bin));
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    check_triangle_scene(&scene);
+}
+
+#[test]
+fn missing_file() {
+    let path = std::env::temp_dir().join("rt-scene-tests-missing.gltf");
+    assert!(matches!(import_scene(&path, NormalGeneration::Flat), Err(SceneError::Io(_))));
+}
+
+#[test]
+fn malformed_gltf() {
+    let path = write_temp("gltf", "{ \"asset\": ");
+    assert!(matches!(import_scene(&path, NormalGeneration::Flat), Err(SceneError::Parse(_))));
+}
+
+#[test]
+fn unsupported_extension() {
+    let path = write_temp("fbx", "");
+    match import_scene(&path, NormalGeneration::Flat) {
+        Err(SceneError::UnsupportedExtension(ext)) => assert_eq!(ext, "fbx"),
+        _ => panic!("fbx files should be rejected"),
+    }
+}
+
+#[test]
+fn out_of_range_indices() {
+    let path = write_temp("gltf", gltf_with_data_uris([0, 1, 3]));
+    match import_scene(&path, NormalGeneration::Flat) {
+        Err(SceneError::InvalidGeometry { location, .. }) => {
+            assert!(location.contains("node 0 (triangle)"), "{}", location);
+            assert!(location.contains("primitive 0"), "{}", location);
+        },
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("indices out of range should be rejected"),
+    }
+}
+
+// Positions of a single primitive without indices, in a data URI, with the given glTF mode
+fn unindexed_gltf(positions: &[[f32; 3]], mode: u32) -> String {
+    let data = positions.iter().flatten().flat_map(|p| p.to_le_bytes()).collect::<Vec<_>>();
+    format!(r#"{{
+        "asset": {{ "version": "2.0" }},
+        "scene": 0,
+        "scenes": [{{ "nodes": [0] }}],
+        "nodes": [{{ "mesh": 0 }}],
+        "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "mode": {mode} }}] }}],
+        "accessors": [{{ "bufferView": 0, "componentType": 5126, "count": {count}, "type": "VEC3", "min": [-1, -1, 0], "max": [1, 1, 0] }}],
+        "bufferViews": [{{ "buffer": 0, "byteLength": {len} }}],
+        "buffers": [{{ "byteLength": {len}, "uri": "data:application/octet-stream;base64,{data}" }}]
+    }}"#, mode = mode, count = positions.len(), len = data.len(), data = base64(&data))
+}
+
+// Without indices, every 3 vertices make a triangle
+#[test]
+fn unindexed_gltf_primitive() {
+    let positions = [[-1.0, -1.0, 0.0], [1.0, -1.0, 0.0], [1.0, 1.0, 0.0], [-1.0, -1.0, 0.0], [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0]];
+    let path = write_temp("gltf", unindexed_gltf(&positions, 4));
+
+    for normals in [NormalGeneration::Flat, NormalGeneration::Smooth] {
+        let scene = import_scene(&path, normals).unwrap();
+        assert_eq!(scene.objects().len(), 1);
+        assert_eq!(scene.objects()[0].area(), 4.0);
+
+        for (x, y) in [(0.5, -0.5), (-0.5, 0.5)] {
+            let hit = (&scene).hit(Ray::new(Vec3::new(x, y, CAMERA_Z), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+            assert_eq!(hit.shading_norm, Vec3::new(0.0, 0.0, 1.0), "{:?}", normals);
+        }
+    }
+}
+
+// One triangle with normals, in a data URI, under a node with the given transform properties
+fn transformed_gltf(positions: [[f32; 3]; 3], normals: [[f32; 3]; 3], node: &str) -> String {
+    let data = positions.iter().chain(&normals).flatten().flat_map(|p| p.to_le_bytes()).collect::<Vec<_>>();
+    format!(r#"{{
+        "asset": {{ "version": "2.0" }},
+        "scene": 0,
+        "scenes": [{{ "nodes": [0] }}],
+        "nodes": [{{ "mesh": 0, {node} }}],
+        "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0, "NORMAL": 1 }} }}] }}],
+        "accessors": [
+            {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [-1, -1, -1], "max": [1, 1, 1] }},
+            {{ "bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3, "type": "VEC3" }}
+        ],
+        "bufferViews": [{{ "buffer": 0, "byteLength": {len} }}],
+        "buffers": [{{ "byteLength": {len}, "uri": "data:application/octet-stream;base64,{data}" }}]
+    }}"#, node = node, len = data.len(), data = base64(&data))
+}
+
+// Mirrored along X, the triangle still faces +Z, so it must not be culled from that side
+#[test]
+fn mirrored_gltf_node() {
+    let path = write_temp("gltf", transformed_gltf(POSITIONS, [[0.0, 0.0, 1.0]; 3], r#""scale": [-1, 1, 1]"#));
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+
+    let hit = (&scene).hit(Ray::new(Vec3::new(0.0, 0.0, CAMERA_Z), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+    assert!(hit.front_face);
+    assert_eq!(hit.geom_norm, Vec3::new(0.0, 0.0, 1.0));
+    assert_eq!(hit.shading_norm, Vec3::new(0.0, 0.0, 1.0));
+}
+
+// A triangle facing (1, 1, 0), stretched along Y. Its normal has to lean towards X to stay perpendicular to it
+#[test]
+fn scaled_gltf_normals() {
+    let normal = Vec3::new(1.0, 1.0, 0.0).normalized();
+    let path = write_temp("gltf", transformed_gltf([[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, -1.0, 0.0]], [normal.into(); 3], r#""scale": [1, 2, 1]"#));
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+
+    let expected = Vec3::new(2.0, 1.0, 0.0).normalized();
+    let center = Vec3::new(1.0, -2.0, 1.0) / 3.0;
+    let hit = (&scene).hit(Ray::new(center + expected * 3.0, -expected)).unwrap();
+    assert!(hit.front_face);
+    assert!((hit.geom_norm - expected).length() < 1e-5, "{:?}", hit.geom_norm);
+    assert!((hit.shading_norm - expected).length() < 1e-5, "{:?}", hit.shading_norm);
+}
+
+// Positions in the z = 0 plane, so triangles seen from +Z are counter clockwise when their normal is +Z.
+// Indices start at 10, so that they can't be mistaken for positions in the triangles
+fn check_counter_clockwise(positions: &[[f32; 2]], triangles: &[[u32; 3]]) {
+    for tri in triangles {
+        let p = tri.map(|i| Vec3::new(positions[i as usize - 10][0], positions[i as usize - 10][1], 0.0));
+        assert_eq!(face_normal(p), Some(Vec3::new(0.0, 0.0, 1.0)), "{:?} is clockwise", tri);
+    }
+}
+
+#[test]
+fn triangle_strip() {
+    use gltf::mesh::Mode;
+
+    // Zigzag between y = 1 and y = 0, the first triangle counter clockwise
+    let positions = [[0.0, 1.0], [0.0, 0.0], [1.0, 1.0], [1.0, 0.0], [2.0, 1.0]];
+    let triangles = triangulate(Mode::TriangleStrip, &[10, 11, 12, 13, 14]);
+
+    // Every other triangle is swapped to keep the winding of the first
+    assert_eq!(triangles, vec![[10, 11, 12], [11, 13, 12], [12, 13, 14]]);
+    check_counter_clockwise(&positions, &triangles);
+
+    assert!(triangulate(Mode::TriangleStrip, &[0, 1]).is_empty());
+}
+
+#[test]
+fn triangle_fan() {
+    use gltf::mesh::Mode;
+
+    // Around the first vertex, counter clockwise
+    let positions = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [-1.0, 1.0]];
+    let triangles = triangulate(Mode::TriangleFan, &[10, 11, 12, 13, 14]);
+
+    assert_eq!(triangles, vec![[11, 12, 10], [12, 13, 10], [13, 14, 10]]);
+    check_counter_clockwise(&positions, &triangles);
+
+    assert!(triangulate(Mode::TriangleFan, &[0, 1]).is_empty());
+}
+
+#[test]
+fn triangle_list() {
+    use gltf::mesh::Mode;
+
+    // Leftover indices don't make a triangle
+    assert_eq!(triangulate(Mode::Triangles, &[0, 1, 2, 3, 4]), vec![[0, 1, 2]]);
+    assert!(triangulate(Mode::Points, &[0, 1, 2]).is_empty());
+    assert!(triangulate(Mode::LineStrip, &[0, 1, 2]).is_empty());
+}
+
+// A non indexed strip covering the same quad as unindexed_gltf_primitive, seen from its front
+#[test]
+fn unindexed_gltf_strip() {
+    let positions = [[-1.0, 1.0, 0.0], [-1.0, -1.0, 0.0], [1.0, 1.0, 0.0], [1.0, -1.0, 0.0]];
+    let path = write_temp("gltf", unindexed_gltf(&positions, 5));
+
+    let scene = import_scene(&path, NormalGeneration::Flat).unwrap();
+    assert_eq!(scene.objects()[0].area(), 4.0);
+    for (x, y) in [(0.5, -0.5), (-0.5, 0.5)] {
+        let hit = (&scene).hit(Ray::new(Vec3::new(x, y, CAMERA_Z), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(hit.shading_norm, Vec3::new(0.0, 0.0, 1.0));
+    }
+}
+
+
+#[test]
+fn object_and_material_ids() {
+    let red = Material { color: Color::new(1.0, 0.0, 0.0), ..Material::default() };
+    let green = Material { color: Color::new(0.0, 1.0, 0.0), ..Material::default() };
+
+    // Three triangles facing +Z side by side, the outer ones sharing a material
+    let mut builder = SceneBuilder::new();
+    for (i, material) in [red, green, red].into_iter().enumerate() {
+        let x = i as f32 * 3.0;
+        let vertices = POSITIONS.iter().map(|p| Vertex { pos: Vec3::new(p[0] + x, p[1], p[2]), norm: Vec3::new(0.0, 0.0, 1.0) }).collect();
+        builder.push(Mesh::new(vertices, vec![[0, 1, 2]], material));
+    }
+    let scene = builder.build();
+
+    let ids = (0..3).map(|i| {
+        let hit = (&scene).hit(Ray::new(Vec3::new(i as f32 * 3.0, 0.0, CAMERA_Z), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        let index = hit.obj_index.unwrap();
+        assert!(std::ptr::eq(hit.obj.unwrap(), &scene.objects()[index as usize]));
+        (index, scene.material_id(index))
+    }).collect::<Vec<_>>();
+
+    assert_eq!(ids[0].1, ids[2].1);
+    assert_ne!(ids[0].1, ids[1].1);
+    let mut objects = ids.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+    objects.sort();
+    assert_eq!(objects, vec![0, 1, 2]);
+}
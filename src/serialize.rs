@@ -0,0 +1,81 @@
+use crate::vec::*;
+use crate::color::*;
+
+use std::io::{self, Read, Write};
+
+
+pub fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub fn write_f32<W: Write>(writer: &mut W, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub fn write_f64<W: Write>(writer: &mut W, value: f64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub fn write_vec3<W: Write>(writer: &mut W, value: Vec3) -> io::Result<()> {
+    write_f32(writer, value.x)?;
+    write_f32(writer, value.y)?;
+    write_f32(writer, value.z)
+}
+
+pub fn write_color<W: Write>(writer: &mut W, value: Color) -> io::Result<()> {
+    write_f32(writer, value.r)?;
+    write_f32(writer, value.g)?;
+    write_f32(writer, value.b)
+}
+
+
+pub fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+pub fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+pub fn read_vec3<R: Read>(reader: &mut R) -> io::Result<Vec3> {
+    Ok(Vec3::new(read_f32(reader)?, read_f32(reader)?, read_f32(reader)?))
+}
+
+pub fn read_color<R: Read>(reader: &mut R) -> io::Result<Color> {
+    Ok(Color::new(read_f32(reader)?, read_f32(reader)?, read_f32(reader)?))
+}
+
+
+// Fails with UnexpectedEof instead of allocating len bytes when a corrupted length is too large
+pub fn read_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+pub fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,26 @@
+// Temporary files for the importer tests. Tests run in parallel, so every scene gets a directory of its own.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::fs;
+
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+
+// Writes scene.<ext> in a new directory, and returns its path
+pub fn write_temp<C: AsRef<[u8]>>(ext: &str, contents: C) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rt-tests-{}-{}", std::process::id(), NEXT_DIR.fetch_add(1, Ordering::Relaxed)));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(format!("scene.{}", ext));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+// Writes a file the scene at path refers to, in the same directory
+pub fn write_next_to<C: AsRef<[u8]>>(path: &Path, name: &str, contents: C) -> PathBuf {
+    let path = path.with_file_name(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
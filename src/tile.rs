@@ -0,0 +1,119 @@
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert,
+}
+
+
+impl Tile {
+    pub fn pixel_count(&self) -> usize {
+        (self.width as usize) * (self.height as usize)
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (x, y, width) = (self.x, self.y, self.width);
+        (0..self.pixel_count() as u32).map(move |i| (x + i % width, y + i / width))
+    }
+}
+
+
+pub fn generate_tiles(width: u32, height: u32, tile_size: u32, order: TileOrder) -> Vec<Tile> {
+    debug_assert!(tile_size > 0);
+
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+
+    let tile = |tx: u32, ty: u32| {
+        let x = tx * tile_size;
+        let y = ty * tile_size;
+        Tile {
+            x: x,
+            y: y,
+            width: tile_size.min(width - x),
+            height: tile_size.min(height - y),
+        }
+    };
+
+    let coords = match order {
+        TileOrder::Scanline => (0..tiles_x * tiles_y).map(|i| (i % tiles_x, i / tiles_x)).collect(),
+        TileOrder::Spiral => spiral_order(tiles_x, tiles_y),
+        TileOrder::Hilbert => hilbert_order(tiles_x, tiles_y),
+    };
+
+    debug_assert!(coords.len() == (tiles_x * tiles_y) as usize);
+
+    coords.into_iter().map(|(tx, ty)| tile(tx, ty)).collect()
+}
+
+
+// Walks a square spiral starting from the center tile, skipping positions outside of the grid
+fn spiral_order(tiles_x: u32, tiles_y: u32) -> Vec<(u32, u32)> {
+    let total = (tiles_x * tiles_y) as usize;
+    let mut coords = Vec::with_capacity(total);
+
+    let (mut x, mut y) = ((tiles_x as i64 - 1) / 2, (tiles_y as i64 - 1) / 2);
+    let (mut dx, mut dy) = (1, 0);
+    let mut leg_len = 1;
+
+    let push = |x: i64, y: i64, coords: &mut Vec<(u32, u32)>| {
+        if x >= 0 && y >= 0 && x < tiles_x as i64 && y < tiles_y as i64 {
+            coords.push((x as u32, y as u32));
+        }
+    };
+
+    push(x, y, &mut coords);
+    while coords.len() < total {
+        for _ in 0..2 {
+            for _ in 0..leg_len {
+                x += dx;
+                y += dy;
+                push(x, y, &mut coords);
+            }
+            (dx, dy) = (-dy, dx);
+        }
+        leg_len += 1;
+    }
+
+    coords
+}
+
+// Follows a Hilbert curve over the smallest power of two square containing the grid
+fn hilbert_order(tiles_x: u32, tiles_y: u32) -> Vec<(u32, u32)> {
+    let side = tiles_x.max(tiles_y).max(1).next_power_of_two();
+
+    (0..side * side)
+        .map(|d| hilbert_to_xy(side, d))
+        .filter(|(x, y)| *x < tiles_x && *y < tiles_y)
+        .collect()
+}
+
+fn hilbert_to_xy(side: u32, d: u32) -> (u32, u32) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
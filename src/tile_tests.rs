@@ -0,0 +1,55 @@
+// Checks that every tile order schedules each tile of the grid exactly once, including non square and non power of two grids.
+
+use crate::tile::*;
+
+
+const ORDERS: [TileOrder; 3] = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+const GRIDS: [(u32, u32); 7] = [(1, 1), (1, 6), (6, 1), (3, 5), (5, 3), (7, 2), (2, 7)];
+
+
+// Number of times each pixel is covered by the tiles
+fn coverage(width: u32, height: u32, tiles: &[Tile]) -> Vec<u32> {
+    let mut covered = vec![0; (width * height) as usize];
+    for tile in tiles {
+        assert!(tile.width > 0 && tile.height > 0, "empty tile {:?}", tile);
+        for (x, y) in tile.pixels() {
+            assert!(x < width && y < height, "tile {:?} is outside of {}x{}", tile, width, height);
+            covered[(y * width + x) as usize] += 1;
+        }
+    }
+    covered
+}
+
+
+#[test]
+fn every_tile_once() {
+    for order in ORDERS {
+        for (tiles_x, tiles_y) in GRIDS {
+            // With 1 pixel tiles, the tiles are the grid positions
+            let tiles = generate_tiles(tiles_x, tiles_y, 1, order);
+            assert_eq!(tiles.len(), (tiles_x * tiles_y) as usize, "{:?} {}x{}", order, tiles_x, tiles_y);
+            assert!(coverage(tiles_x, tiles_y, &tiles).iter().all(|c| *c == 1), "{:?} {}x{}", order, tiles_x, tiles_y);
+        }
+    }
+}
+
+// The last row and column of tiles are cut to the image
+#[test]
+fn partial_tiles() {
+    for order in ORDERS {
+        for (tiles_x, tiles_y) in GRIDS {
+            let (width, height) = (tiles_x * 16 - 5, tiles_y * 16 - 11);
+            let tiles = generate_tiles(width, height, 16, order);
+            assert_eq!(tiles.len(), (tiles_x * tiles_y) as usize, "{:?} {}x{}", order, width, height);
+            assert!(coverage(width, height, &tiles).iter().all(|c| *c == 1), "{:?} {}x{}", order, width, height);
+        }
+    }
+}
+
+#[test]
+fn spiral_starts_at_center() {
+    for (tiles_x, tiles_y) in GRIDS {
+        let first = generate_tiles(tiles_x, tiles_y, 1, TileOrder::Spiral)[0];
+        assert_eq!((first.x, first.y), ((tiles_x - 1) / 2, (tiles_y - 1) / 2));
+    }
+}
@@ -1,5 +1,8 @@
 
 use crate::vec::*;
+use crate::serialize::*;
+
+use std::io::{self, Read, Write};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Transform {
@@ -26,6 +29,40 @@ impl Transform {
         }
     }
 
+    pub fn translation(pos: Vec3) -> Transform {
+        Transform::identity().with_pos(pos)
+    }
+
+    pub fn scaling(scale: Vec3) -> Transform {
+        Transform::from_basis(
+            Vec3::new(scale.x, 0.0, 0.0),
+            Vec3::new(0.0, scale.y, 0.0),
+            Vec3::new(0.0, 0.0, scale.z),
+        )
+    }
+
+    // Counter clockwise when looking down the axis (Rodrigues' formula)
+    pub fn rotation(axis: Vec3, degrees: f32) -> Transform {
+        let axis = axis.normalized();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let rotate = |v: Vec3| v * cos + axis.cross(v) * sin + axis * (axis.dot(v) * (1.0 - cos));
+        Transform::from_basis(
+            rotate(Vec3::new(1.0, 0.0, 0.0)),
+            rotate(Vec3::new(0.0, 1.0, 0.0)),
+            rotate(Vec3::new(0.0, 0.0, 1.0)),
+        )
+    }
+
+    // Placed at pos and looking down -Z at target, like cameras. None if the up direction is along the view direction
+    pub fn look_at(pos: Vec3, target: Vec3, up: Vec3) -> Option<Transform> {
+        let z = (pos - target).normalized();
+        let x = up.cross(z).normalized();
+        if x.length().is_nan() {
+            return None;
+        }
+        Some(Transform::from_basis(x, z.cross(x), z).with_pos(pos))
+    }
+
     pub fn with_pos(self, pos: Vec3) -> Transform {
         Transform {
             basis: self.basis,
@@ -51,6 +88,44 @@ impl Transform {
         self.basis[2] * dir.z
     }
 
+    pub fn determinant(&self) -> f32 {
+        self.basis[0].dot(self.basis[1].cross(self.basis[2]))
+    }
+
+    // Normals follow the inverse transpose, which keeps them perpendicular to the surface under non uniform scales.
+    // Scaled by the determinant (up to its sign), so not normalized
+    pub fn transform_normal(&self, norm: Vec3) -> Vec3 {
+        let [a, b, c] = self.basis;
+        (b.cross(c) * norm.x + c.cross(a) * norm.y + a.cross(b) * norm.z) * self.determinant().signum()
+    }
+
+    pub fn inverse(&self) -> Transform {
+        // Rows of the inverse are the cross products of the columns, over the determinant
+        let [a, b, c] = self.basis;
+        let det = self.determinant();
+        let rows = [b.cross(c) / det, c.cross(a) / det, a.cross(b) / det];
+        let inverse = Transform::from_basis(
+            Vec3::new(rows[0].x, rows[1].x, rows[2].x),
+            Vec3::new(rows[0].y, rows[1].y, rows[2].y),
+            Vec3::new(rows[0].z, rows[1].z, rows[2].z),
+        );
+        inverse.with_pos(-inverse.transform_dir(self.pos))
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for v in self.basis {
+            write_vec3(writer, v)?;
+        }
+        write_vec3(writer, self.pos)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Transform> {
+        Ok(Transform {
+            basis: [read_vec3(reader)?, read_vec3(reader)?, read_vec3(reader)?],
+            pos: read_vec3(reader)?,
+        })
+    }
+
     pub fn then(&self, o: Transform) -> Transform {
         Transform {
             basis: [
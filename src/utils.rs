@@ -6,8 +6,46 @@ use rand::prelude::*;
 pub const PI: f32 = core::f32::consts::PI;
 pub const INV_PI: f32 = 1.0 / PI;
 pub const EPSILON: f32 = 0.00001;
+pub const MACHINE_EPSILON: f32 = f32::EPSILON * 0.5;
 
 
+// SplitMix64 finalizer, used to derive decorrelated seeds from indices
+pub fn hash_u64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// Bound on the relative error of n chained floating point operations (PBRT 3.9.1)
+pub fn gamma(n: i32) -> f32 {
+    (n as f32 * MACHINE_EPSILON) / (1.0 - n as f32 * MACHINE_EPSILON)
+}
+
+pub fn next_float_up(x: f32) -> f32 {
+    if x.is_infinite() && x > 0.0 {
+        return x;
+    }
+
+    // -0 and +0 both step to the smallest positive float
+    let x = if x == -0.0 { 0.0 } else { x };
+    let bits = x.to_bits();
+    f32::from_bits(if x >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+pub fn next_float_down(x: f32) -> f32 {
+    -next_float_up(-x)
+}
+
+// Indices and counts read as floats, or as signed types, which must be whole and fit in a u32
+pub fn f64_to_index(value: f64) -> Result<u32, String> {
+    if value >= 0.0 && value <= u32::MAX as f64 && value.fract() == 0.0 {
+        Ok(value as u32)
+    } else {
+        Err(format!("invalid index or count {}", value))
+    }
+}
+
 pub fn random_unit_vector<R: RngCore>(rng: &mut R) -> Vec3 {
     loop {
         let v = Vec3::new(
@@ -31,11 +69,22 @@ pub fn random_in_hemisphere<R: RngCore>(norm: Vec3, rng: &mut R) -> Vec3 {
     }
 }
 
+// Tangent and bitangent completing a unit normal into an orthonormal basis (Duff et al. 2017)
+pub fn orthonormal_basis(norm: Vec3) -> (Vec3, Vec3) {
+    let sign = 1.0_f32.copysign(norm.z);
+    let a = -1.0 / (sign + norm.z);
+    let b = norm.x * norm.y * a;
+    (
+        Vec3::new(1.0 + sign * norm.x * norm.x * a, sign * b, -sign * norm.x),
+        Vec3::new(b, sign + norm.y * norm.y * a, -norm.y),
+    )
+}
+
 pub fn quadrant(v: Vec3) -> usize {
     let mut q = 0;
     for i in 0..3 {
         if v[i] < 0.0 {
-            q = q | (1 << i);
+            q |= 1 << i;
         }
     }
 
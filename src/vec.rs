@@ -34,7 +34,7 @@ impl Vec3 {
     }
 
     pub fn length2(&self) -> f32 {
-        self.dot(self.clone())
+        self.dot(*self)
     }
 
     pub fn distance(&self, p: Vec3) -> f32 {
@@ -46,12 +46,12 @@ impl Vec3 {
     }
 
     pub fn normalized(&self) -> Vec3 {
-        self.clone() / self.length()
+        *self / self.length()
     }
 
     pub fn length_normalized(&self) -> (f32, Vec3) {
         let len = self.length();
-        (len, self.clone() / len)
+        (len, *self / len)
     }
 
     pub fn cross(&self, o: Vec3) -> Vec3 {
@@ -62,6 +62,10 @@ impl Vec3 {
         }
     }
 
+    pub fn abs(&self) -> Vec3 {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
     pub fn max(&self, o: Vec3) -> Vec3 {
         Vec3 {
             x: f32::max(self.x, o.x),
@@ -115,9 +119,9 @@ impl From<Color> for Vec3 {
     }
 }
 
-impl Into<[f32; 3]> for Vec3 {
-    fn into(self) -> [f32; 3] {
-        [self.x, self.y, self.z]
+impl From<Vec3> for [f32; 3] {
+    fn from(val: Vec3) -> Self {
+        [val.x, val.y, val.z]
     }
 }
 